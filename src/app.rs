@@ -11,24 +11,96 @@
 // You should have received a copy of the GNU General Public License along with TUIModPlayer. If
 // not, see <https://www.gnu.org/licenses/>.
 
-use std::sync::{Arc, Mutex};
+use std::{
+    cell::Cell,
+    collections::HashSet,
+    ffi::OsString,
+    io::stdout,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
-use crate::control::ModuleControl;
+use crate::control::{
+    pitch_value_for_semitones, tempo_value_for_percent, ModuleControl, RepeatMode,
+};
 
-use crate::options::Options;
+use crate::options::{BackendKind, Options, DEFAULT_PLAYLIST_FILE};
 use crate::player::PlayState;
-use crate::playlist::{PlayList, PlayListModuleProvider};
+use crate::playlist::{
+    Favorites, ListOrder, PlayList, PlayListItem, PlayListModuleProvider, PlayStats, ScanOptions,
+    SortKey,
+};
+use crate::session::Session;
 
-use crate::backend::{Backend, BackendEvent, CpalBackend};
-use crate::ui::run_ui;
+use crate::backend::{
+    has_output_device, Backend, BackendEvent, ControlEvent, CpalBackend, NullBackend,
+};
+use crate::ui::{run_ui, ColorScheme};
+use crate::util::IsSomeAnd;
 
 use anyhow::Result;
+use crossterm::{execute, terminal};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Items to move the playlist cursor when pressing PageUp/PageDown.
+const CURSOR_PAGE_STEP: isize = 10;
+
+/// How often to check for backend events when running without the UI.
+const HEADLESS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the playlist loader adds the modules found so far to the playlist.
+const LOAD_BATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Minutes of the sleep timer to cycle through when pressing the key.
+const SLEEP_TIMER_PRESETS: &[u64] = &[15, 30, 60, 90];
+
+/// Seconds to seek when pressing the left/right arrow keys.
+const SEEK_STEP: f64 = 5.0;
+
+/// Seconds to seek when pressing the left/right arrow keys with shift.
+const SEEK_STEP_LONG: f64 = 30.0;
+
+/// The step of the output volume, in percent.
+const VOLUME_STEP: usize = 5;
+
+/// Attempts to reopen the audio output after the stream failed, e.g. when the device is unplugged.
+const STREAM_RETRIES: usize = 5;
+
+/// The time between the attempts to reopen the audio output.
+const STREAM_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Default)]
 pub enum UiMode {
     #[default]
     Normal,
     Filter,
+    /// Typing a path to add to the playlist.
+    AddPath,
+    /// Typing the index of the item to jump to.
+    JumpTo,
+    /// Typing the order of the playing module to seek to.
+    Goto,
+    /// Typing the file to export the view of the playlist to.
+    ExportView,
+    /// Choosing the output device from `AppState::device_names`.
+    SelectDevice,
+    /// Typing the tempo in percent of the normal tempo.
+    SetTempo,
+    /// Typing the pitch offset in semitones.
+    SetPitch,
+}
+
+/// The pane that receives the cursor keys.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum Focus {
+    #[default]
+    Playlist,
+    Message,
 }
 
 pub struct AppState {
@@ -38,20 +110,133 @@ pub struct AppState {
     pub playlist: Arc<Mutex<PlayList>>,
     pub control: ModuleControl,
     pub ui_mode: UiMode,
+    /// The text being typed in input modes other than `UiMode::Filter`.
+    pub input: String,
+    /// The order to seek to when the first module starts playing, restored from the last session.
+    pub resume_order: Option<usize>,
+    /// The start of the A-B loop, in seconds.
+    pub loop_a: Option<f64>,
+    /// The end of the A-B loop, in seconds.
+    pub loop_b: Option<f64>,
+    /// True while the paths on the command line are being rescanned.
+    pub rescanning: Arc<AtomicBool>,
+    /// True while the paths on the command line are being loaded after starting up.
+    pub loading: Arc<AtomicBool>,
+    /// True until the item to resume from is loaded, or the loading finishes without it.
+    resume_pending: Arc<AtomicBool>,
+    /// The playlist was exhausted while loading.  Start playing when more items are loaded.
+    waiting_for_playlist: bool,
+    /// Show the song message instead of instrument or sample names in the Message pane.
+    pub show_song_message: bool,
+    /// Show the keys of the actions over the other panes.
+    pub show_help: bool,
+    /// The output devices to choose from in `UiMode::SelectDevice`.
+    pub device_names: Vec<String>,
+    /// The index of the highlighted device in `device_names`.
+    pub device_cursor: usize,
+    pub focus: Focus,
+    /// The first line shown in the Message pane.
+    pub message_scroll: usize,
+    /// Lines visible in the Message pane when it was last rendered.  Used for scrolling by pages.
+    pub message_pane_height: Cell<usize>,
+    /// Tells the metadata scanner thread to stop.
+    pub stop_metadata_scan: Arc<AtomicBool>,
+    /// The metadata scanner thread, joined when quitting so that it can save the cache.
+    metadata_scanner: Option<JoinHandle<()>>,
+    /// True while the content of the items is being hashed to find duplicates.
+    pub finding_duplicates: Arc<AtomicBool>,
+    /// Tells the thread finding duplicates to stop.
+    pub stop_finding_duplicates: Arc<AtomicBool>,
+    /// When to pause (or quit, with `--sleep-quit`) if the sleep timer is set.
+    pub sleep_deadline: Option<Instant>,
+    /// Stop when the playing module ends instead of playing the next one.
+    pub stop_after_current: bool,
+    /// The output volume in percent.  Applied by the backend after decoding, unlike the gain.
+    pub volume: usize,
+    /// The number of failed attempts to reopen the output after the stream failed, and when to
+    /// make the next attempt.
+    stream_retry: Option<(usize, Instant)>,
+    /// The output could not be reopened.  Further errors of the stream are ignored until another
+    /// device is selected, or the user pauses or resumes to try again.
+    stream_failed: bool,
+    /// Lets media keys and tools like `playerctl` control the player.  `None` if the session bus
+    /// is unavailable.
+    #[cfg(all(feature = "mpris", target_os = "linux"))]
+    pub mpris: Option<crate::mpris::MprisServer>,
+    pub color_scheme: ColorScheme,
 }
 
 impl AppState {
+    /// The state before the loading and the playback start.
+    pub fn new(
+        options: Options,
+        backend: Box<dyn Backend>,
+        playlist: Arc<Mutex<PlayList>>,
+        control: ModuleControl,
+        color_scheme: ColorScheme,
+    ) -> Self {
+        Self {
+            options,
+            play_state: None,
+            backend,
+            playlist,
+            control,
+            ui_mode: Default::default(),
+            input: String::new(),
+            resume_order: None,
+            loop_a: None,
+            loop_b: None,
+            rescanning: Default::default(),
+            loading: Default::default(),
+            resume_pending: Default::default(),
+            waiting_for_playlist: false,
+            show_song_message: false,
+            show_help: false,
+            device_names: vec![],
+            device_cursor: 0,
+            focus: Default::default(),
+            message_scroll: 0,
+            message_pane_height: Cell::new(0),
+            stop_metadata_scan: Default::default(),
+            metadata_scanner: None,
+            finding_duplicates: Default::default(),
+            stop_finding_duplicates: Default::default(),
+            sleep_deadline: None,
+            stop_after_current: false,
+            volume: 100,
+            stream_retry: None,
+            stream_failed: false,
+            color_scheme,
+            #[cfg(all(feature = "mpris", target_os = "linux"))]
+            mpris: None,
+        }
+    }
+
     pub fn start_playing(&mut self) {
         self.backend.start();
     }
 
     pub fn next(&mut self) {
-        self.playlist.lock().unwrap().goto_next_module(1);
+        {
+            let mut playlist = self.playlist.lock().unwrap();
+            if !playlist.goto_next_queued() {
+                playlist.goto_following();
+            }
+        }
         self.backend.reload();
     }
 
+    /// Play the previous item in the list.  When shuffled or in random mode, play the previously
+    /// played item instead, if any.
     pub fn prev(&mut self) {
-        self.playlist.lock().unwrap().goto_previous_module(1);
+        {
+            let mut playlist = self.playlist.lock().unwrap();
+            let went_back = (playlist.get_order() == ListOrder::Shuffled || playlist.is_random())
+                && playlist.goto_history_back();
+            if !went_back {
+                playlist.goto_previous_module(1);
+            }
+        }
         self.backend.reload();
     }
 
@@ -65,21 +250,706 @@ impl AppState {
         self.backend.reload();
     }
 
+    pub fn play_first(&mut self) {
+        if self.playlist.lock().unwrap().goto_first() {
+            self.backend.reload();
+        }
+    }
+
+    pub fn play_last(&mut self) {
+        if self.playlist.lock().unwrap().goto_last() {
+            self.backend.reload();
+        }
+    }
+
+    pub fn cursor_up(&mut self) {
+        self.playlist.lock().unwrap().move_cursor_by(-1);
+    }
+
+    pub fn cursor_down(&mut self) {
+        self.playlist.lock().unwrap().move_cursor_by(1);
+    }
+
+    pub fn cursor_page_up(&mut self) {
+        self.playlist
+            .lock()
+            .unwrap()
+            .move_cursor_by(-CURSOR_PAGE_STEP);
+    }
+
+    pub fn cursor_page_down(&mut self) {
+        self.playlist
+            .lock()
+            .unwrap()
+            .move_cursor_by(CURSOR_PAGE_STEP);
+    }
+
+    pub fn cursor_first(&mut self) {
+        self.playlist.lock().unwrap().move_cursor_to_first();
+    }
+
+    pub fn cursor_last(&mut self) {
+        self.playlist.lock().unwrap().move_cursor_to_last();
+    }
+
+    /// Restrict the playback to the directory of the item under the cursor (or the playing item),
+    /// or play the whole playlist again.
+    pub fn toggle_scope(&mut self) {
+        let mut playlist = self.playlist.lock().unwrap();
+        if playlist.get_scope().is_some() {
+            playlist.exit_scope();
+        } else if let Some(view_index) = playlist.cursor.or(playlist.now_playing_in_view) {
+            playlist.enter_scope(view_index);
+        }
+    }
+
+    pub fn toggle_grouped(&mut self) {
+        self.playlist.lock().unwrap().toggle_grouped();
+    }
+
+    /// Collapse or expand the group of the item under the cursor, or of the playing item.
+    pub fn toggle_group(&mut self) {
+        let mut playlist = self.playlist.lock().unwrap();
+        if !playlist.is_grouped() {
+            return;
+        }
+        if let Some(view_index) = playlist.cursor.or(playlist.now_playing_in_view) {
+            playlist.toggle_group_collapsed(view_index);
+        }
+    }
+
+    pub fn reset_cursor(&mut self) {
+        self.playlist.lock().unwrap().reset_cursor();
+    }
+
+    pub fn play_cursor(&mut self) {
+        let has_cursor = self.playlist.lock().unwrap().play_cursor();
+        if has_cursor {
+            self.backend.reload();
+        }
+    }
+
+    fn move_item_by(&mut self, offset: isize) {
+        let mut playlist = self.playlist.lock().unwrap();
+        if let Some(from) = playlist.cursor.or(playlist.now_playing_in_view) {
+            if let Some(to) = from.checked_add_signed(offset) {
+                playlist.move_item(from, to);
+            }
+        }
+    }
+
+    /// Play the item at the given view index, as shown in the title of the playlist.
+    pub fn jump_to(&mut self, input: &str) {
+        let index = match input.trim().parse::<usize>() {
+            Ok(index) => index,
+            Err(e) => {
+                log::error!("Invalid playlist index {:?}: {}", input, e);
+                return;
+            }
+        };
+        {
+            let mut playlist = self.playlist.lock().unwrap();
+            if !playlist.play_view_index(index) {
+                log::error!(
+                    "Playlist index {} out of range.  Length: {}",
+                    index,
+                    playlist.len()
+                );
+                return;
+            }
+        }
+        self.backend.reload();
+    }
+
+    /// Seek to the start of an order of the playing module.
+    pub fn goto_order(&mut self, input: &str) {
+        let order = match input.trim().parse::<usize>() {
+            Ok(order) => order,
+            Err(e) => {
+                log::error!("Invalid order {:?}: {}", input, e);
+                return;
+            }
+        };
+        let Some(ref play_state) = self.play_state else {
+            return;
+        };
+        let n_orders = play_state.module_info.n_orders;
+        if order >= n_orders {
+            log::error!("Order {} out of range.  Orders: {}", order, n_orders);
+            return;
+        }
+        self.backend
+            .send_control_event(ControlEvent::generic(move |module| {
+                module.set_position_order_row(order as i32, 0);
+            }));
+    }
+
+    /// Add the item under the cursor, or the playing item, to the queue, or remove it if queued.
+    pub fn toggle_queued(&mut self) {
+        let mut playlist = self.playlist.lock().unwrap();
+        if let Some(view_index) = playlist.cursor.or(playlist.now_playing_in_view) {
+            playlist.toggle_queued(view_index);
+        }
+    }
+
+    /// Star the item under the cursor, or the playing item, or unstar it if starred.
+    pub fn toggle_favorite(&mut self) {
+        let mut playlist = self.playlist.lock().unwrap();
+        if let Some(view_index) = playlist.cursor.or(playlist.now_playing_in_view) {
+            playlist.toggle_favorite(view_index);
+        }
+    }
+
+    /// Move the item under the cursor, or the playing item, one place up.
+    pub fn move_item_up(&mut self) {
+        self.move_item_by(-1);
+    }
+
+    /// Move the item under the cursor, or the playing item, one place down.
+    pub fn move_item_down(&mut self) {
+        self.move_item_by(1);
+    }
+
+    /// Load the paths on the command line in a worker thread.  The modules found are added to the
+    /// playlist in batches so that the playback and the UI can start before the loading finishes.
+    pub fn start_loading(&mut self, shuffle_seed: Option<u64>, mut session: Option<Session>) {
+        self.loading.store(true, Ordering::SeqCst);
+        self.resume_pending
+            .store(session.is_some(), Ordering::SeqCst);
+
+        let playlist = self.playlist.clone();
+        let loading = self.loading.clone();
+        let resume_pending = self.resume_pending.clone();
+        let paths = self.options.paths.clone();
+        let scan_options = ScanOptions::from_options(&self.options);
+        std::thread::Builder::new()
+            .name("PlaylistLoader".to_string())
+            .spawn(move || {
+                log::info!("Loading from {} root paths...", paths.len());
+                let mut flush = |batch: &mut PlayList| {
+                    let mut playlist = playlist.lock().unwrap();
+                    playlist.append(std::mem::replace(batch, PlayList::new()));
+                    if let Some(seed) = shuffle_seed {
+                        shuffle_with_seed(&mut playlist, seed);
+                    }
+                    if session
+                        .as_ref()
+                        .is_some_and2(|session| session.restore_playlist(&mut playlist))
+                    {
+                        session = None;
+                        resume_pending.store(false, Ordering::SeqCst);
+                    }
+                };
+
+                let mut batch = PlayList::new();
+                let mut last_flush = Instant::now();
+                for path in paths.iter() {
+                    crate::playlist::scan_path(path, &scan_options, |mod_path| {
+                        batch.add_item(PlayListItem::new(mod_path));
+                        if last_flush.elapsed() >= LOAD_BATCH_INTERVAL {
+                            flush(&mut batch);
+                            last_flush = Instant::now();
+                        }
+                    });
+                }
+                flush(&mut batch);
+
+                log::info!(
+                    "Loading finished.  The playlist has {} items.",
+                    playlist.lock().unwrap().items.len()
+                );
+                resume_pending.store(false, Ordering::SeqCst);
+                loading.store(false, Ordering::SeqCst);
+            })
+            .unwrap();
+    }
+
+    /// Read the titles of the modules in the playlist in a worker thread.
+    pub fn start_metadata_scan(&mut self) {
+        let playlist = self.playlist.clone();
+        let stop = self.stop_metadata_scan.clone();
+        let metadata_scanner = std::thread::Builder::new()
+            .name("MetadataScanner".to_string())
+            .spawn(move || crate::playlist::scan_metadata(playlist, stop))
+            .unwrap();
+        self.metadata_scanner = Some(metadata_scanner);
+    }
+
+    /// Stop the metadata scanner and wait until it has saved the cache.
+    pub fn finish_metadata_scan(&mut self) {
+        self.stop_metadata_scan.store(true, Ordering::SeqCst);
+        if let Some(metadata_scanner) = self.metadata_scanner.take() {
+            // Wake it up if it is waiting for new items.
+            metadata_scanner.thread().unpark();
+            if metadata_scanner.join().is_err() {
+                log::error!("The metadata scanner panicked");
+            }
+        }
+    }
+
+    /// Hash the content of the items in a worker thread and mark the duplicates.  Cancel it if it
+    /// is already running.
+    pub fn find_duplicates(&mut self) {
+        if self.finding_duplicates.load(Ordering::SeqCst) {
+            log::info!("Cancelling finding duplicates...");
+            self.stop_finding_duplicates.store(true, Ordering::SeqCst);
+            return;
+        }
+
+        self.finding_duplicates.store(true, Ordering::SeqCst);
+        self.stop_finding_duplicates.store(false, Ordering::SeqCst);
+        let playlist = self.playlist.clone();
+        let finding_duplicates = self.finding_duplicates.clone();
+        let stop = self.stop_finding_duplicates.clone();
+        std::thread::Builder::new()
+            .name("DuplicateFinder".to_string())
+            .spawn(move || {
+                crate::playlist::find_duplicates(playlist, stop);
+                finding_duplicates.store(false, Ordering::SeqCst);
+            })
+            .unwrap();
+    }
+
+    /// Remove the items marked as duplicates, keeping one copy of each module.
+    pub fn remove_duplicates(&mut self) {
+        let n_removed = self.playlist.lock().unwrap().remove_duplicates();
+        log::info!("Removed {} duplicates.", n_removed);
+    }
+
+    /// Scan a path for modules in a worker thread and add them to the playlist.
+    pub fn add_path(&mut self, path: String) {
+        if !Path::new(&path).exists() {
+            log::error!("Cannot add path.  It does not exist: {}", path);
+            return;
+        }
+
+        let playlist = self.playlist.clone();
+        let scan_options = ScanOptions::from_options(&self.options);
+        std::thread::Builder::new()
+            .name("PathLoader".to_string())
+            .spawn(move || {
+                log::info!("Loading from {}...", path);
+                // Scan into a separate playlist so that the shared one is not locked for long.
+                let mut new_playlist = PlayList::new();
+                crate::playlist::load_from_path(&mut new_playlist, &path, &scan_options);
+                let n_items = new_playlist.items.len();
+                playlist.lock().unwrap().append(new_playlist);
+                log::info!("Added {} items from {}", n_items, path);
+            })
+            .unwrap();
+    }
+
+    /// Scan the paths on the command line again in a worker thread.  Add the modules not in the
+    /// playlist yet, and remove the items whose files are gone, except the playing one.
+    pub fn rescan(&mut self) {
+        if self.rescanning.swap(true, Ordering::SeqCst) {
+            log::info!("Already rescanning.");
+            return;
+        }
+
+        let playlist = self.playlist.clone();
+        let rescanning = self.rescanning.clone();
+        let paths = self.options.paths.clone();
+        let scan_options = ScanOptions::from_options(&self.options);
+        std::thread::Builder::new()
+            .name("Rescanner".to_string())
+            .spawn(move || {
+                log::info!("Rescanning...");
+                let mut scanned = PlayList::new();
+                for path in paths.iter() {
+                    crate::playlist::load_from_path(&mut scanned, path, &scan_options);
+                }
+                let scanned_names = scanned
+                    .items
+                    .iter()
+                    .map(|item| item.mod_path.display_full_name())
+                    .collect::<HashSet<_>>();
+                let root_paths = paths.iter().map(OsString::from).collect::<HashSet<_>>();
+
+                let mut playlist = playlist.lock().unwrap();
+                let now_playing_id = playlist.now_playing_in_items.map(|i| playlist.items[i].id);
+                let n_before = playlist.items.len();
+                // Items added in other ways are not expected to be found by the scan.
+                playlist.retain_items(|item| {
+                    Some(item.id) == now_playing_id
+                        || !root_paths.contains(&item.mod_path.root_path)
+                        || scanned_names.contains(&item.mod_path.display_full_name())
+                });
+                let n_removed = n_before - playlist.items.len();
+
+                let existing_names = playlist
+                    .items
+                    .iter()
+                    .map(|item| item.mod_path.display_full_name())
+                    .collect::<HashSet<_>>();
+                scanned
+                    .items
+                    .retain(|item| !existing_names.contains(&item.mod_path.display_full_name()));
+                let n_added = scanned.items.len();
+                playlist.append(scanned);
+
+                log::info!(
+                    "Rescanning finished.  Added {} items.  Removed {} items.",
+                    n_added,
+                    n_removed
+                );
+                rescanning.store(false, Ordering::SeqCst);
+            })
+            .unwrap();
+    }
+
+    /// Sort the playlist by the next sort key, or restore the original order after the last key.
+    pub fn cycle_sort(&mut self) {
+        let mut playlist = self.playlist.lock().unwrap();
+        let sort_key = match playlist.get_order() {
+            ListOrder::Sorted(sort_key) => sort_key.next(),
+            _ => Some(SortKey::Name),
+        };
+        match sort_key {
+            Some(sort_key) => playlist.sort_by(sort_key),
+            None => playlist.restore_original_order(),
+        }
+    }
+
+    /// Shuffle the playlist again, even if it is already shuffled.
+    pub fn reshuffle(&mut self) {
+        self.playlist
+            .lock()
+            .unwrap()
+            .shuffle(&mut rand::thread_rng());
+    }
+
+    /// Shuffle the playlist, or restore the original order if it is already shuffled.
+    pub fn toggle_shuffle(&mut self) {
+        let mut playlist = self.playlist.lock().unwrap();
+        if playlist.get_order() == ListOrder::Shuffled {
+            playlist.restore_original_order();
+        } else {
+            playlist.shuffle(&mut rand::thread_rng());
+        }
+    }
+
+    /// Set the sleep timer to the next preset longer than the time left, or turn it off after the
+    /// longest preset.
+    pub fn cycle_sleep_timer(&mut self) {
+        let remaining_minutes = self
+            .sleep_deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()).as_secs() / 60);
+        let next_preset = SLEEP_TIMER_PRESETS
+            .iter()
+            .find(|preset| !remaining_minutes.is_some_and2(|remaining| *remaining >= **preset));
+        match next_preset {
+            Some(minutes) => {
+                self.sleep_deadline = Some(Instant::now() + Duration::from_secs(minutes * 60));
+                log::info!("Sleep timer set to {} minutes.", minutes);
+            }
+            None => {
+                self.sleep_deadline = None;
+                log::info!("Sleep timer turned off.");
+            }
+        }
+    }
+
+    /// Pause the playback if the sleep timer has expired.  Return true if the player should quit.
+    pub fn check_sleep_timer(&mut self) -> bool {
+        match self.sleep_deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                self.sleep_deadline = None;
+                log::info!("Sleep timer expired.");
+                if !self.backend.is_paused() {
+                    self.pause_resume();
+                }
+                self.options.sleep_quit
+            }
+            _ => false,
+        }
+    }
+
+    /// Stop when the playing module ends instead of playing the next one, or stop no longer.
+    pub fn toggle_stop_after_current(&mut self) {
+        self.stop_after_current = !self.stop_after_current;
+        self.backend.set_stop_after_current(self.stop_after_current);
+    }
+
+    /// Let the items that failed to open be played again, e.g. after fixing the files.
+    pub fn clear_failed(&mut self) {
+        let n_cleared = self.playlist.lock().unwrap().clear_failed();
+        log::info!("Cleared the failure marks of {} items.", n_cleared);
+    }
+
+    pub fn toggle_random(&mut self) {
+        self.playlist.lock().unwrap().toggle_random();
+    }
+
+    /// Remove the item under the cursor, or the playing item if the cursor is not active.
+    pub fn remove_item(&mut self) {
+        let was_playing = {
+            let mut playlist = self.playlist.lock().unwrap();
+            match playlist.cursor.or(playlist.now_playing_in_view) {
+                Some(view_index) => playlist.remove_item(view_index),
+                None => false,
+            }
+        };
+        if was_playing {
+            self.backend.reload();
+        }
+    }
+
     pub fn pause_resume(&mut self) {
+        if self.stream_failed {
+            // Give the output another chance, e.g. after plugging the device in again.
+            log::info!("Trying to reopen the audio output...");
+            self.stream_failed = false;
+            self.stream_retry = Some((0, Instant::now()));
+        }
         self.backend.pause_resume();
+        self.update_mpris();
     }
 
-    pub fn handle_backend_events(&mut self) {
+    /// Carry out the requests from MPRIS clients.
+    #[cfg(all(feature = "mpris", target_os = "linux"))]
+    pub fn handle_mpris_commands(&mut self) {
+        use crate::mpris::MprisCommand;
+
+        let Some(ref mpris) = self.mpris else {
+            return;
+        };
+        let commands = std::iter::from_fn(|| mpris.poll_command()).collect::<Vec<_>>();
+        for command in commands {
+            match command {
+                MprisCommand::PlayPause => self.pause_resume(),
+                MprisCommand::Play if self.backend.is_paused() => self.pause_resume(),
+                MprisCommand::Pause if !self.backend.is_paused() => self.pause_resume(),
+                MprisCommand::Play | MprisCommand::Pause => {}
+                MprisCommand::Next => self.next(),
+                MprisCommand::Previous => self.prev(),
+            }
+        }
+    }
+
+    #[cfg(not(all(feature = "mpris", target_os = "linux")))]
+    pub fn handle_mpris_commands(&mut self) {}
+
+    /// Tell MPRIS clients about the playing module and whether it is paused.
+    #[cfg(all(feature = "mpris", target_os = "linux"))]
+    fn update_mpris(&self) {
+        let Some(ref mpris) = self.mpris else {
+            return;
+        };
+        let track = self.play_state.as_ref().map(|play_state| {
+            let playlist = self.playlist.lock().unwrap();
+            let id = playlist
+                .now_playing_in_view
+                .and_then(|view_index| playlist.get_item(view_index))
+                .map(|item| item.id)
+                .unwrap_or_default();
+            drop(playlist);
+            crate::mpris::Track {
+                id,
+                title: self
+                    .playing_title(&play_state.module_info.title)
+                    .unwrap_or_default(),
+                duration: play_state.module_info.duration,
+                moment_state: play_state.moment_state.clone(),
+            }
+        });
+        mpris.update(track, self.backend.is_paused());
+    }
+
+    #[cfg(not(all(feature = "mpris", target_os = "linux")))]
+    fn update_mpris(&self) {}
+
+    fn seek(&mut self, offset_seconds: f64) {
+        self.backend
+            .send_control_event(ControlEvent::generic(move |module| {
+                // Seeking past the end leaves nothing to render,
+                // so the module ends the same way as when it is played to the end.
+                let duration = module.get_duration_seconds().max(0.0);
+                let target = module.get_position_seconds() + offset_seconds;
+                module.set_position_seconds(target.clamp(0.0, duration));
+            }));
+    }
+
+    /// Jump to the start of the order `offset` orders away from the current one, staying within
+    /// the module.
+    fn step_order(&mut self, offset: isize) {
+        let Some(ref play_state) = self.play_state else {
+            return;
+        };
+        let Some(last_order) = play_state.module_info.n_orders.checked_sub(1) else {
+            return;
+        };
+        let order = play_state.moment_state.read().order;
+        let target = order.saturating_add_signed(offset).min(last_order);
+        self.backend
+            .send_control_event(ControlEvent::generic(move |module| {
+                module.set_position_order_row(target as i32, 0);
+            }));
+    }
+
+    pub fn next_order(&mut self) {
+        self.step_order(1);
+    }
+
+    pub fn prev_order(&mut self) {
+        self.step_order(-1);
+    }
+
+    pub fn seek_forward(&mut self) {
+        self.seek(SEEK_STEP);
+    }
+
+    pub fn seek_backward(&mut self) {
+        self.seek(-SEEK_STEP);
+    }
+
+    pub fn seek_forward_long(&mut self) {
+        self.seek(SEEK_STEP_LONG);
+    }
+
+    pub fn seek_backward_long(&mut self) {
+        self.seek(-SEEK_STEP_LONG);
+    }
+
+    /// Handle the events from the backend.  Return true if the playlist is exhausted.
+    pub fn handle_backend_events(&mut self) -> bool {
+        let mut exhausted = false;
         while let Some(be_ev) = self.backend.poll_event() {
             match be_ev {
                 BackendEvent::StartedPlaying { play_state } => {
+                    if self.options.set_title && !self.options.no_ui {
+                        self.update_terminal_title(&play_state.module_info.title);
+                    }
+                    self.update_now_playing_file(Some(&play_state.module_info.title));
                     self.play_state = Some(play_state);
+                    self.update_mpris();
+                    self.message_scroll = 0;
+                    if self.loop_a.is_some() || self.loop_b.is_some() {
+                        // Loop points only make sense for the module they were set in.
+                        self.clear_loop();
+                    }
+                    if let Some(order) = self.resume_order.take() {
+                        self.backend
+                            .send_control_event(ControlEvent::generic(move |module| {
+                                module.set_position_order_row(order as i32, 0);
+                            }));
+                    }
+                }
+                BackendEvent::SubsongSelected { module_info } => {
+                    if let Some(ref mut play_state) = self.play_state {
+                        play_state.module_info = module_info;
+                    }
+                    self.update_mpris();
+                    if self.loop_a.is_some() || self.loop_b.is_some() {
+                        self.clear_loop();
+                    }
+                }
+                BackendEvent::StoppedAfterCurrent => {
+                    self.stop_after_current = false;
+                    log::info!("Stopped after the module.  Press space to play the next one.");
+                }
+                BackendEvent::StreamError(message) => {
+                    log::error!("Audio stream error: {}", message);
+                    if self.stream_retry.is_none() && !self.stream_failed {
+                        self.stream_retry = Some((0, Instant::now()));
+                    }
                 }
                 BackendEvent::PlayListExhausted => {
                     self.play_state = None;
+                    self.update_now_playing_file(None);
+                    self.update_mpris();
+                    if self.loading.load(Ordering::SeqCst) {
+                        self.waiting_for_playlist = true;
+                    } else {
+                        exhausted = true;
+                    }
                 }
             }
         }
+        if self.waiting_for_playlist {
+            self.resume_when_loaded();
+        }
+        self.retry_stream();
+        exhausted
+    }
+
+    /// Try to play through the default output device if the stream failed, a few times before
+    /// giving up.
+    fn retry_stream(&mut self) {
+        let Some((attempts, next_attempt)) = self.stream_retry else {
+            return;
+        };
+        if Instant::now() < next_attempt {
+            return;
+        }
+        match self.backend.recover() {
+            Ok(()) => self.stream_retry = None,
+            Err(e) if attempts + 1 < STREAM_RETRIES => {
+                log::warn!("Cannot reopen the audio output: {}", e);
+                self.stream_retry = Some((attempts + 1, Instant::now() + STREAM_RETRY_INTERVAL));
+            }
+            Err(e) => {
+                log::error!(
+                    "Cannot reopen the audio output after {} attempts: {}.  Pause or resume to \
+                     try again.",
+                    STREAM_RETRIES,
+                    e
+                );
+                self.stream_retry = None;
+                self.stream_failed = true;
+            }
+        }
+    }
+
+    /// Start playing again if the playlist was exhausted while loading and more items have been
+    /// loaded since.  If the loading finished with nothing new, the backend reports the playlist
+    /// exhausted again.
+    fn resume_when_loaded(&mut self) {
+        let ready = !self.loading.load(Ordering::SeqCst)
+            || (!self.resume_pending.load(Ordering::SeqCst)
+                && !self.playlist.lock().unwrap().items.is_empty());
+        if ready {
+            self.waiting_for_playlist = false;
+            self.backend.reload();
+        }
+    }
+
+    /// The module title, or the file name of the playing item if the module is untitled.
+    fn playing_title(&self, module_title: &str) -> Option<String> {
+        if module_title.trim().is_empty() {
+            let playlist = self.playlist.lock().unwrap();
+            playlist
+                .now_playing_in_view
+                .and_then(|view_index| playlist.get_item(view_index))
+                .map(|item| item.mod_path.display_name())
+        } else {
+            Some(module_title.trim().to_string())
+        }
+    }
+
+    /// Show the module title in the title bar of the terminal.
+    fn update_terminal_title(&self, module_title: &str) {
+        let Some(title) = self.playing_title(module_title) else {
+            return;
+        };
+        // Control characters would end the escape sequence early.
+        let title = title.replace(|c: char| c.is_control(), " ");
+        if let Err(e) = execute!(stdout(), terminal::SetTitle(title)) {
+            log::warn!("Failed to set the terminal title: {}", e);
+        }
+    }
+
+    /// Write the module title to the `--now-playing-file`, or empty it if `module_title` is `None`.
+    pub fn update_now_playing_file(&self, module_title: Option<&str>) {
+        let Some(ref path) = self.options.now_playing_file else {
+            return;
+        };
+        let content = module_title
+            .and_then(|title| self.playing_title(title))
+            .unwrap_or_default();
+        if let Err(e) = write_file_atomically(Path::new(path), content.as_bytes()) {
+            log::warn!("Failed to write the now-playing file {}: {}", path, e);
+        }
     }
 
     fn send_apply_mod_settings_event(&mut self) {
@@ -147,48 +1017,514 @@ impl AppState {
         self.send_apply_mod_settings_event();
     }
 
-    pub fn toggle_repeat(&mut self) {
-        self.control.repeat = !self.control.repeat;
+    /// The value of the tempo or pitch control for the number typed in `UiMode::SetTempo` or
+    /// `UiMode::SetPitch`, clamped to the range of the control.  `None` if the input is invalid.
+    pub fn typed_control_value(&self) -> Option<i32> {
+        let number = self.input.trim().parse::<f64>().ok()?;
+        let (mut field, value) = match self.ui_mode {
+            UiMode::SetTempo => (self.control.tempo.clone(), tempo_value_for_percent(number)?),
+            UiMode::SetPitch => (
+                self.control.pitch.clone(),
+                pitch_value_for_semitones(number)?,
+            ),
+            _ => return None,
+        };
+        field.set_value(value);
+        Some(field.value())
+    }
+
+    /// Set the tempo or pitch to the number typed, and go back to the normal mode.
+    pub fn set_typed_control(&mut self) {
+        match (self.typed_control_value(), &self.ui_mode) {
+            (Some(value), UiMode::SetTempo) => self.control.tempo.set_value(value),
+            (Some(value), UiMode::SetPitch) => self.control.pitch.set_value(value),
+            (Some(_), _) => {}
+            (None, _) => log::error!("Invalid number {:?}", self.input),
+        }
+        self.input.clear();
+        self.ui_mode = UiMode::Normal;
+        self.send_apply_mod_settings_event();
+    }
+
+    pub fn volume_down(&mut self) {
+        self.volume = self.volume.saturating_sub(VOLUME_STEP);
+        self.backend.set_volume(self.volume);
+    }
+
+    pub fn volume_up(&mut self) {
+        self.volume = (self.volume + VOLUME_STEP).min(100);
+        self.backend.set_volume(self.volume);
+    }
+
+    pub fn reset_controls(&mut self) {
+        self.control.reset_fields();
+        self.send_apply_mod_settings_event();
+    }
+
+    /// Select the subsong `delta` positions after the current one, wrapping around.
+    fn select_subsong_relative(&mut self, delta: isize) {
+        let Some(ref play_state) = self.play_state else {
+            return;
+        };
+        let n_subsongs = play_state.module_info.n_subsongs;
+        if n_subsongs <= 1 {
+            log::info!("The module has no other subsongs.");
+            return;
+        }
+        let subsong = (play_state.module_info.subsong as isize + delta)
+            .rem_euclid(n_subsongs as isize) as usize;
+        self.backend
+            .send_control_event(ControlEvent::SelectSubsong(subsong));
+    }
+
+    pub fn next_subsong(&mut self) {
+        self.select_subsong_relative(1);
+    }
+
+    pub fn prev_subsong(&mut self) {
+        self.select_subsong_relative(-1);
+    }
+
+    pub fn toggle_song_message(&mut self) {
+        self.show_song_message = !self.show_song_message;
+        self.message_scroll = 0;
+    }
+
+    /// The lines currently shown in the Message pane, if a module is playing.
+    pub fn shown_message(&self) -> Option<&Vec<String>> {
+        self.play_state.as_ref().map(|ps| {
+            if self.show_song_message {
+                &ps.module_info.song_message
+            } else {
+                &ps.module_info.message
+            }
+        })
+    }
+
+    pub fn toggle_focus(&mut self) {
+        self.focus = match self.focus {
+            Focus::Playlist => Focus::Message,
+            Focus::Message => Focus::Playlist,
+        };
+    }
+
+    /// Scroll the Message pane by `delta` lines, stopping when the last line is visible.
+    pub fn scroll_message(&mut self, delta: isize) {
+        let lines = self.shown_message().map_or(0, |message| message.len());
+        let max_scroll = lines.saturating_sub(self.message_pane_height.get());
+        self.message_scroll = self
+            .message_scroll
+            .saturating_add_signed(delta)
+            .min(max_scroll);
+    }
+
+    /// Scroll the Message pane by `pages` pages.
+    pub fn scroll_message_pages(&mut self, pages: isize) {
+        let page_height = self.message_pane_height.get().max(1) as isize;
+        self.scroll_message(pages * page_height);
+    }
+
+    /// Cycle through repeat modes.
+    pub fn cycle_repeat(&mut self) {
+        self.control.repeat = self.control.repeat.next();
+        self.playlist.lock().unwrap().stop_at_end = self.control.repeat == RepeatMode::Off;
+        self.send_apply_mod_settings_event();
+    }
+
+    fn current_position(&self) -> Option<f64> {
+        self.play_state
+            .as_ref()
+            .map(|ps| ps.moment_state.read().position)
+    }
+
+    fn update_ab_loop(&mut self) {
+        if let (Some(a), Some(b)) = (self.loop_a, self.loop_b) {
+            if a > b {
+                self.loop_a = Some(b);
+                self.loop_b = Some(a);
+            }
+        }
+        self.control.ab_loop = self.loop_a.zip(self.loop_b);
         self.send_apply_mod_settings_event();
     }
+
+    pub fn set_loop_a(&mut self) {
+        if let Some(position) = self.current_position() {
+            self.loop_a = Some(position);
+            self.update_ab_loop();
+        }
+    }
+
+    pub fn set_loop_b(&mut self) {
+        if let Some(position) = self.current_position() {
+            self.loop_b = Some(position);
+            self.update_ab_loop();
+        }
+    }
+
+    pub fn clear_loop(&mut self) {
+        self.loop_a = None;
+        self.loop_b = None;
+        self.update_ab_loop();
+    }
+
+    /// Write all items in the playlist to an M3U file.
+    pub fn export_playlist(&self, path: &Path) {
+        let playlist = self.playlist.lock().unwrap();
+        match crate::playlist::save_to_m3u(&playlist, path) {
+            Ok(()) => log::info!(
+                "Saved {} items to {}",
+                playlist.items.len(),
+                path.to_string_lossy()
+            ),
+            Err(e) => log::error!(
+                "Failed to save playlist to {}: {}",
+                path.to_string_lossy(),
+                e
+            ),
+        }
+    }
+
+    pub fn save_playlist(&mut self) {
+        let path = self
+            .options
+            .playlist
+            .as_deref()
+            .unwrap_or(DEFAULT_PLAYLIST_FILE);
+        self.export_playlist(Path::new(path));
+    }
+
+    /// Where `export_playlist_out` writes to.  Use `--playlist-out` if given.  Otherwise, write
+    /// next to the first path given on the command line.
+    fn playlist_out_path(&self) -> PathBuf {
+        if let Some(ref path) = self.options.playlist_out {
+            return PathBuf::from(path);
+        }
+        let dir = match self.options.paths.first().map(Path::new) {
+            Some(path) if path.is_dir() => path,
+            Some(path) => path.parent().unwrap_or(Path::new("")),
+            None => Path::new(""),
+        };
+        dir.join(DEFAULT_PLAYLIST_FILE)
+    }
+
+    pub fn export_playlist_out(&mut self) {
+        let path = self.playlist_out_path();
+        self.export_playlist(&path);
+    }
+
+    /// List the output devices to choose from, starting at the current one.
+    pub fn start_selecting_device(&mut self) {
+        let (device_names, current) = self.backend.output_devices();
+        if device_names.is_empty() {
+            log::error!("No output devices found.");
+            return;
+        }
+        self.device_names = device_names;
+        self.device_cursor = current.unwrap_or(0);
+        self.ui_mode = UiMode::SelectDevice;
+    }
+
+    pub fn move_device_cursor(&mut self, offset: isize) {
+        let last = self.device_names.len().saturating_sub(1);
+        self.device_cursor = self.device_cursor.saturating_add_signed(offset).min(last);
+    }
+
+    /// Play through the highlighted output device.
+    pub fn select_device(&mut self) {
+        self.ui_mode = UiMode::Normal;
+        let Some(name) = self.device_names.get(self.device_cursor) else {
+            return;
+        };
+        match self.backend.switch_device(name) {
+            Ok(()) => {
+                self.stream_retry = None;
+                self.stream_failed = false;
+            }
+            Err(e) => log::error!("Failed to switch to the output device {:?}: {}", name, e),
+        }
+    }
+
+    /// Write the items in the view, i.e. as filtered, to an M3U file in a worker thread.
+    pub fn export_view(&mut self, path: String) {
+        if path.trim().is_empty() {
+            log::error!("Cannot export the view.  No path is given.");
+            return;
+        }
+        let entries = self.playlist.lock().unwrap().view_entries();
+        std::thread::Builder::new()
+            .name("ViewExporter".to_string())
+            .spawn(move || {
+                let entries_iter = entries
+                    .iter()
+                    .map(|(title, mod_path)| (title.clone(), mod_path));
+                match crate::playlist::save_entries_to_m3u(entries_iter, Path::new(&path)) {
+                    Ok(()) => log::info!("Exported {} items to {}", entries.len(), path),
+                    Err(e) => log::error!("Failed to export the view to {}: {}", path, e),
+                }
+            })
+            .unwrap();
+    }
+
+    /// Render the playing module, with the current control settings, into a WAV file in the
+    /// current directory in a worker thread.  The playback is not interrupted.
+    pub fn export_current(&mut self) {
+        let Some(ref play_state) = self.play_state else {
+            log::error!("Cannot export.  Nothing is playing.");
+            return;
+        };
+        let subsong = play_state.module_info.subsong;
+        let Some(mod_path) = ({
+            let playlist = self.playlist.lock().unwrap();
+            playlist
+                .now_playing_in_items
+                .and_then(|i| playlist.items.get(i))
+                .map(|item| item.mod_path.clone())
+        }) else {
+            return;
+        };
+        let control = self.control.clone();
+        let sample_rate = self.options.sample_rate;
+        let path = PathBuf::from(crate::render::wav_file_name(&mod_path));
+        std::thread::Builder::new()
+            .name("ModuleExporter".to_string())
+            .spawn(move || {
+                log::info!("Rendering {}...", path.to_string_lossy());
+                match crate::render::render_module_to_wav(
+                    &mod_path,
+                    subsong,
+                    &control,
+                    sample_rate,
+                    &path,
+                ) {
+                    Ok(()) => log::info!("Rendered to {}", path.to_string_lossy()),
+                    Err(e) => log::error!("Failed to render to {}: {}", path.to_string_lossy(), e),
+                }
+            })
+            .unwrap();
+    }
 }
 
 pub fn run(options: Options) -> Result<()> {
+    if options.list_devices {
+        return crate::backend::list_output_devices(options.audio_host.as_deref());
+    }
+
     let mut playlist = PlayList::new();
+    playlist.play_stats = PlayStats::load();
+    playlist.favorites = Favorites::load();
+
+    if let Some(ref playlist_file) = options.playlist {
+        log::info!("Loading playlist file {}...", playlist_file);
+        if let Err(e) = crate::playlist::load_from_m3u(&mut playlist, Path::new(playlist_file)) {
+            log::error!("Failed to load playlist file {}: {}", playlist_file, e);
+        }
+    }
+
+    // Always shuffle with a known seed so that the order can be reproduced with `--shuffle-seed`.
+    let shuffle_seed = (options.shuffle || options.shuffle_seed.is_some()).then(|| {
+        let seed = options
+            .shuffle_seed
+            .unwrap_or_else(|| rand::thread_rng().gen());
+        log::info!("Shuffling playlist with seed {}...", seed);
+        seed
+    });
+    // Sorting overrides shuffling.  Items loaded later are kept in the same order by `append`.
+    let shuffle_seed = match options.sort {
+        Some(sort_key) => {
+            log::info!("Sorting playlist by {}...", sort_key);
+            playlist.sort_by(sort_key);
+            None
+        }
+        None => shuffle_seed,
+    };
+
+    let mut control = ModuleControl::default();
+
+    if options.render_out.is_some() || options.render_to.is_some() {
+        // Nothing to show while rendering, so just load everything before starting.
+        log::info!("Loading from {} root paths...", options.paths.len());
+        let scan_options = ScanOptions::from_options(&options);
+        for path in options.paths.iter() {
+            crate::playlist::load_from_path(&mut playlist, path, &scan_options);
+        }
+        if let Some(seed) = shuffle_seed {
+            shuffle_with_seed(&mut playlist, seed);
+        }
+        if let Some(ref render_to) = options.render_to {
+            return crate::render::render_playlist_to_dir(
+                &playlist,
+                &control,
+                options.sample_rate,
+                Path::new(render_to),
+            );
+        }
+        if let Some(ref render_out) = options.render_out {
+            return crate::render::render_playlist_to_wav(
+                &mut playlist,
+                &control,
+                options.sample_rate,
+                Path::new(render_out),
+            );
+        }
+    }
 
-    log::info!("Loading from {} root paths...", options.paths.len());
-    for path in options.paths.iter() {
-        crate::playlist::load_from_path(&mut playlist, path, options.deep_archive_search);
+    let color_scheme = match options.theme {
+        Some(ref theme) => ColorScheme::load(theme)?,
+        None => ColorScheme::default(),
+    };
+
+    if let Some(seed) = shuffle_seed {
+        shuffle_with_seed(&mut playlist, seed);
     }
 
-    log::info!("Shuffling playlist...");
-    if options.shuffle {
-        playlist.shuffle();
+    let mut resume_order = None;
+    // The session to resume if its item is not loaded yet.
+    let mut pending_session = None;
+
+    if !options.no_resume || !options.no_restore {
+        match Session::load() {
+            Ok(Some(session)) => {
+                // The control settings are kept even if the paths are different.
+                if !options.no_restore {
+                    control = session.control.clone();
+                }
+                if !options.no_resume && session.paths == options.paths {
+                    resume_order = Some(session.order).filter(|order| *order != 0);
+                    if !session.restore_playlist(&mut playlist) {
+                        pending_session = Some(session);
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log::warn!("Failed to load the last session: {}", e);
+            }
+        }
     }
 
+    if options.loop_playlist {
+        control.repeat = RepeatMode::All;
+    }
+    // Without the UI, there is no way to stop other than Ctrl-C unless the playlist ends.
+    playlist.stop_at_end =
+        control.repeat == RepeatMode::Off || (options.no_ui && !options.loop_playlist);
+
     let playlist = Arc::new(Mutex::new(playlist));
     let module_provider = Box::new(PlayListModuleProvider::new(playlist.clone()));
 
-    let control = ModuleControl::default();
-
-    let backend: Box<dyn Backend> = Box::new(CpalBackend::new(
-        options.sample_rate,
-        module_provider,
-        control.clone(),
-    ));
-
-    let mut app_state = AppState {
-        options,
-        play_state: None,
-        backend,
-        playlist,
-        control,
-        ui_mode: Default::default(),
+    let backend_kind = options.backend.unwrap_or_else(|| {
+        if options.device.is_none() && !has_output_device(options.audio_host.as_deref()) {
+            log::warn!("No output device found.  Playing without sound.");
+            BackendKind::Null
+        } else {
+            BackendKind::Cpal
+        }
+    });
+    let backend: Box<dyn Backend> = match backend_kind {
+        BackendKind::Cpal => Box::new(CpalBackend::new(
+            options.audio_host.as_deref(),
+            options.device.as_deref(),
+            options.sample_rate,
+            options.buffer_frames,
+            module_provider,
+            control.clone(),
+        )?),
+        BackendKind::Null => Box::new(NullBackend::new(
+            options.sample_rate,
+            module_provider,
+            control.clone(),
+        )),
     };
 
+    let mut app_state = AppState::new(options, backend, playlist, control, color_scheme);
+    app_state.resume_order = resume_order;
+
+    #[cfg(all(feature = "mpris", target_os = "linux"))]
+    if !app_state.options.no_mpris {
+        match crate::mpris::MprisServer::start() {
+            Ok(mpris) => app_state.mpris = Some(mpris),
+            Err(e) => log::warn!("Failed to start the MPRIS server: {}", e),
+        }
+    }
+
+    if let Some(minutes) = app_state.options.sleep {
+        app_state.sleep_deadline = Some(Instant::now() + Duration::from_secs(minutes * 60));
+    }
+
+    app_state.start_loading(shuffle_seed, pending_session);
     app_state.start_playing();
+    app_state.start_metadata_scan();
 
-    run_ui(&mut app_state)?;
+    let result = if app_state.options.no_ui {
+        run_headless(&mut app_state)
+    } else {
+        run_ui(&mut app_state)
+    };
+    app_state.finish_metadata_scan();
+    app_state
+        .stop_finding_duplicates
+        .store(true, Ordering::SeqCst);
+    app_state.update_now_playing_file(None);
+    result?;
+
+    if let Err(e) = Session::from_app_state(&app_state).save() {
+        log::error!("Failed to save the session: {}", e);
+    }
+    let mut playlist = app_state.playlist.lock().unwrap();
+    if let Err(e) = playlist.play_stats.save() {
+        log::error!("Failed to save the play counts: {}", e);
+    }
+    if let Err(e) = playlist.favorites.save() {
+        log::error!("Failed to save the favorites: {}", e);
+    }
+
+    Ok(())
+}
+
+/// Shuffle the items in the order in which they were added, so that the same seed gives the same
+/// order no matter how many times the playlist has been shuffled while loading.
+fn shuffle_with_seed(playlist: &mut PlayList, seed: u64) {
+    playlist.restore_original_order();
+    playlist.shuffle(&mut StdRng::seed_from_u64(seed));
+}
+
+/// Replace the content of a file by writing a temporary file next to it and renaming it, so that
+/// readers never see a partially written file.
+fn write_file_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    std::fs::write(&temp_path, content)?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Play without the UI until the playlist is exhausted or Ctrl-C is pressed.
+fn run_headless(app_state: &mut AppState) -> Result<()> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    {
+        let interrupted = interrupted.clone();
+        ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))?;
+    }
+
+    while !interrupted.load(Ordering::SeqCst) {
+        if app_state.handle_backend_events() {
+            log::info!("Finished playing the playlist.");
+            return Ok(());
+        }
+        app_state.handle_mpris_commands();
+        // Nothing can resume the playback without the UI, so always quit.
+        if app_state
+            .sleep_deadline
+            .is_some_and2(|deadline| Instant::now() >= *deadline)
+        {
+            log::info!("Sleep timer expired.");
+            return Ok(());
+        }
+        std::thread::sleep(HEADLESS_POLL_INTERVAL);
+    }
 
+    log::info!("Interrupted.");
     Ok(())
 }
@@ -0,0 +1,257 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use sevenz_rust::{Password, SevenZReader};
+use zip::ZipArchive;
+
+/// Files larger than this are not read from archives, in case a broken or malicious archive
+/// claims an absurd size.  Modules are far smaller than this.
+pub const MAX_FILE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// The kinds of archives that may contain modules.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    SevenZ,
+}
+
+/// Lower-case file name suffixes of each kind of archive.
+const ARCHIVE_SUFFIXES: &[(&str, ArchiveKind)] = &[
+    (".zip", ArchiveKind::Zip),
+    (".tar", ArchiveKind::Tar),
+    (".tar.gz", ArchiveKind::TarGz),
+    (".tgz", ArchiveKind::TarGz),
+    (".7z", ArchiveKind::SevenZ),
+];
+
+/// Split a file name into the name without the archive suffix and the kind of the archive.
+fn split_archive_suffix(name: &str) -> Option<(&str, ArchiveKind)> {
+    let lower_name = name.to_ascii_lowercase();
+    ARCHIVE_SUFFIXES
+        .iter()
+        .find(|(suffix, _)| lower_name.ends_with(suffix))
+        .map(|(suffix, kind)| (&name[..name.len() - suffix.len()], *kind))
+}
+
+/// The file name without the archive suffix, e.g. `song.mod` for `song.mod.tar.gz`.
+/// Return `None` if it is not the name of an archive.
+pub fn strip_archive_suffix(name: &str) -> Option<&str> {
+    split_archive_suffix(name).map(|(stem, _)| stem)
+}
+
+pub enum ReadWhatFromArchive<'a> {
+    Name(&'a str),
+    /// The first file.  Used for archived singles.
+    First,
+}
+
+impl ReadWhatFromArchive<'_> {
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            ReadWhatFromArchive::Name(archive_path) => name == *archive_path,
+            ReadWhatFromArchive::First => true,
+        }
+    }
+
+    fn not_found(&self) -> anyhow::Error {
+        match self {
+            ReadWhatFromArchive::Name(archive_path) => {
+                anyhow::anyhow!("File not found in archive: {}", archive_path)
+            }
+            ReadWhatFromArchive::First => anyhow::anyhow!("Empty archive"),
+        }
+    }
+}
+
+impl ArchiveKind {
+    /// Identify the kind of archive by its file name.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy();
+        split_archive_suffix(&name).map(|(_, kind)| kind)
+    }
+
+    /// The names and the sizes of the files in the archive.
+    ///
+    /// Unlike `for_each_file`, this does not decompress the files in 7z archives.
+    pub fn list_files(self, archive: impl Read + Seek) -> Result<Vec<(String, u64)>> {
+        match self {
+            ArchiveKind::SevenZ => {
+                let reader = open_7z(archive)?;
+                Ok(reader
+                    .archive()
+                    .files
+                    .iter()
+                    .filter(|entry| !entry.is_directory())
+                    .map(|entry| (entry.name().to_string(), entry.size()))
+                    .collect())
+            }
+            _ => {
+                let mut files = Vec::new();
+                self.for_each_file(archive, |name, size, _| files.push((name, size)))?;
+                Ok(files)
+            }
+        }
+    }
+
+    /// Call `visit` with the name, the size and the content of each file in the archive.
+    pub fn for_each_file(
+        self,
+        archive: impl Read + Seek,
+        mut visit: impl FnMut(String, u64, &mut dyn Read),
+    ) -> Result<()> {
+        match self {
+            ArchiveKind::Zip => {
+                let mut zip = ZipArchive::new(archive)?;
+                for i in 0..zip.len() {
+                    match zip.by_index(i) {
+                        Ok(zip_file) if zip_file.is_dir() => {}
+                        Ok(mut zip_file) => {
+                            let name = zip_file.name().to_string();
+                            let size = zip_file.size();
+                            visit(name, size, &mut zip_file);
+                        }
+                        Err(e) => {
+                            log::debug!("Skip zip entry: {} Error: {}", i, e);
+                        }
+                    }
+                }
+                Ok(())
+            }
+            ArchiveKind::Tar => for_each_file_in_tar(tar::Archive::new(archive), visit),
+            ArchiveKind::TarGz => {
+                for_each_file_in_tar(tar::Archive::new(GzDecoder::new(archive)), visit)
+            }
+            ArchiveKind::SevenZ => {
+                let mut reader = open_7z(archive)?;
+                reader.for_each_entries(|entry, content| {
+                    if !entry.is_directory() {
+                        visit(entry.name().to_string(), entry.size(), content);
+                    }
+                    // Files in the same block are decompressed from the same stream.  Skip the
+                    // rest of this file so that the next one starts at the right place.
+                    std::io::copy(content, &mut std::io::sink()).map_err(sevenz_rust::Error::io)?;
+                    Ok(true)
+                })?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Read the content of a file in the archive.
+    pub fn read_file(
+        self,
+        archive: impl Read + Seek,
+        what: ReadWhatFromArchive,
+    ) -> Result<Vec<u8>> {
+        match self {
+            ArchiveKind::Zip => {
+                let mut zip = ZipArchive::new(archive)?;
+                let zip_file = match what {
+                    ReadWhatFromArchive::Name(archive_path) => zip.by_name(archive_path)?,
+                    ReadWhatFromArchive::First => zip.by_index(0)?,
+                };
+                let size = zip_file.size();
+                read_file_content(zip_file, size)
+            }
+            ArchiveKind::Tar => read_file_from_tar(tar::Archive::new(archive), what),
+            ArchiveKind::TarGz => {
+                read_file_from_tar(tar::Archive::new(GzDecoder::new(archive)), what)
+            }
+            ArchiveKind::SevenZ => {
+                let mut reader = open_7z(archive)?;
+                // Files are not necessarily decompressed in the order they are listed.
+                let first_name;
+                let what = match what {
+                    ReadWhatFromArchive::First => {
+                        first_name = reader
+                            .archive()
+                            .files
+                            .iter()
+                            .find(|entry| !entry.is_directory())
+                            .map(|entry| entry.name().to_string())
+                            .ok_or_else(|| what.not_found())?;
+                        ReadWhatFromArchive::Name(&first_name)
+                    }
+                    what => what,
+                };
+                let mut result = None;
+                reader.for_each_entries(|entry, content| {
+                    if entry.is_directory() || !what.matches(entry.name()) {
+                        std::io::copy(content, &mut std::io::sink())
+                            .map_err(sevenz_rust::Error::io)?;
+                        return Ok(true);
+                    }
+                    result = Some(read_file_content(content, entry.size()));
+                    Ok(false)
+                })?;
+                result.unwrap_or_else(|| Err(what.not_found()))
+            }
+        }
+    }
+}
+
+/// Read the whole content of a file in an archive, refusing files larger than `MAX_FILE_SIZE`.
+pub fn read_file_content(reader: impl Read, size: u64) -> Result<Vec<u8>> {
+    if size > MAX_FILE_SIZE {
+        anyhow::bail!("File too large: {} bytes", size);
+    }
+    let mut content = Vec::with_capacity(size as usize);
+    // Do not trust the size recorded in the archive.
+    reader.take(MAX_FILE_SIZE).read_to_end(&mut content)?;
+    Ok(content)
+}
+
+fn open_7z<R: Read + Seek>(mut archive: R) -> Result<SevenZReader<R>> {
+    let len = archive.seek(SeekFrom::End(0))?;
+    archive.seek(SeekFrom::Start(0))?;
+    Ok(SevenZReader::new(archive, len, Password::empty())?)
+}
+
+fn for_each_file_in_tar<R: Read>(
+    mut archive: tar::Archive<R>,
+    mut visit: impl FnMut(String, u64, &mut dyn Read),
+) -> Result<()> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let size = entry.size();
+        visit(name, size, &mut entry);
+    }
+    Ok(())
+}
+
+fn read_file_from_tar<R: Read>(
+    mut archive: tar::Archive<R>,
+    what: ReadWhatFromArchive,
+) -> Result<Vec<u8>> {
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_file() && what.matches(&entry.path()?.to_string_lossy()) {
+            let size = entry.size();
+            return read_file_content(entry, size);
+        }
+    }
+    Err(what.not_found())
+}
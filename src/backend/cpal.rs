@@ -12,13 +12,19 @@
 // not, see <https://www.gnu.org/licenses/>.
 
 use std::{
-    sync::{self, mpsc, Arc, Condvar, Mutex},
+    sync::{
+        self,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
     time::{Duration, Instant},
 };
 
+use anyhow::{anyhow, bail, Result};
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    Device, Host, Stream,
+    BufferSize, Device, FromSample, Host, SampleFormat, SizedSample, Stream, StreamConfig,
+    StreamError, SupportedStreamConfig,
 };
 use openmpt::module::Module;
 use seqlock::SeqLock;
@@ -27,9 +33,10 @@ use crate::{
     control::ModuleControl,
     module_file::apply_mod_settings,
     player::{ModuleInfo, MomentState, PlayState},
+    util::IsSomeAnd,
 };
 
-use super::{Backend, BackendEvent, DecodeStatus, ModuleProvider};
+use super::{Backend, BackendEvent, ControlEvent, DecodeStatus, ModuleProvider, PrefetchedModule};
 
 /// CPAL backend.  This struct is owned by the main thread.
 pub struct CpalBackend {
@@ -39,12 +46,28 @@ pub struct CpalBackend {
     shared: Arc<CpalBackendShared>,
     paused: bool,
     receiver: mpsc::Receiver<BackendEvent>,
+    /// Lets the streams report their errors.
+    sender: mpsc::Sender<BackendEvent>,
+    /// The sample rate given on the command line.  Used again when switching devices.
+    requested_sample_rate: usize,
+    /// The buffer size given on the command line, in frames.
+    requested_buffer_frames: Option<u32>,
 }
 
-struct CpalBackendShared {
-    pub sample_rate: usize,
+/// The state shared between the main thread, the audio callback and the waiter.  Also used by
+/// `NullBackend`, which reads from the module without a stream.
+pub(super) struct CpalBackendShared {
+    /// The sample rate of the current stream.  Changes if a device is switched to that does not
+    /// support the previous one.
+    pub sample_rate: AtomicUsize,
+    /// Counts the streams built.  Errors are only reported from the latest one.
+    pub stream_generation: AtomicUsize,
+    /// Stop instead of loading the next module when the current one ends.
+    pub stop_after_current: AtomicBool,
+    /// The output volume in percent, applied to the samples after decoding.
+    pub volume_percent: AtomicUsize,
     pub decode_status: SeqLock<DecodeStatus>,
-    pub module_and_provider: Mutex<ModuleAndProvider>,
+    module_and_provider: Mutex<ModuleAndProvider>,
     pub need_service_cond: Condvar,
 }
 
@@ -58,20 +81,44 @@ enum CurrentModuleState {
         moment_state: Arc<SeqLock<MomentState>>,
     },
     Exhausted,
+    /// The module ended with `stop_after_current` set.  The next module is loaded when resumed.
+    Stopped,
 }
 
 struct ModuleAndProvider {
     pub module: CurrentModuleState,
-    pub provider: Box<dyn ModuleProvider>,
+    /// The provider has its own lock so that the next module can be prefetched
+    /// without blocking the audio callback.
+    pub provider: Arc<Mutex<Box<dyn ModuleProvider>>>,
+    /// The module to play after the current one, opened in advance.
+    pub prefetched: Option<PrefetchedModule>,
+    /// True if the next module should be prefetched.
+    pub need_prefetch: bool,
     pub control: ModuleControl,
     pub on_event: Box<dyn Fn(BackendEvent) + Send>,
 }
 
 const CHANNELS: usize = 2;
 
+/// The time to move the volume to a new value, so that changing it does not click.
+const VOLUME_RAMP_SECONDS: f32 = 0.01;
+
 impl ModuleAndProvider {
+    fn next_module(&mut self) -> Option<Module> {
+        let mut provider = self.provider.lock().unwrap();
+        match self.prefetched.take() {
+            Some(prefetched) if provider.accept_prefetched(&prefetched) => {
+                log::debug!("Playing prefetched module.");
+                Some(prefetched.module)
+            }
+            _ => provider.poll_module(),
+        }
+    }
+
     pub fn reload(&mut self) {
-        self.module = if let Some(mut module) = self.provider.poll_module() {
+        self.need_prefetch = false;
+        self.module = if let Some(mut module) = self.next_module() {
+            self.need_prefetch = true;
             apply_mod_settings(&mut module, &self.control);
             let moment_state: Arc<SeqLock<MomentState>> = Default::default();
             let play_state = PlayState {
@@ -95,6 +142,114 @@ impl ModuleAndProvider {
             apply_mod_settings(module, &self.control);
         }
     }
+
+    pub fn handle_control_event(&mut self, event: ControlEvent) {
+        match event {
+            ControlEvent::Generic(f) => {
+                if let CurrentModuleState::Loaded {
+                    ref mut module,
+                    ref moment_state,
+                } = self.module
+                {
+                    f(module);
+                    // Show the new position at once, even if paused.
+                    *moment_state.lock_write() = MomentState::from_module(module);
+                }
+            }
+            ControlEvent::SelectSubsong(subsong) => {
+                if let CurrentModuleState::Loaded { ref mut module, .. } = self.module {
+                    if !module.select_subsong(subsong as i32) {
+                        log::warn!("Failed to select subsong {}", subsong);
+                        return;
+                    }
+                    let mut module_info = ModuleInfo::from_module(module);
+                    module_info.subsong = subsong;
+                    (self.on_event)(BackendEvent::SubsongSelected { module_info });
+                }
+            }
+        }
+    }
+}
+
+impl CpalBackendShared {
+    /// Create the shared state, and start the waiter, which loads the first module.
+    pub(super) fn start(
+        sample_rate: usize,
+        sample_format: &'static str,
+        module_provider: Box<dyn ModuleProvider>,
+        control: ModuleControl,
+        sender: mpsc::Sender<BackendEvent>,
+    ) -> Arc<Self> {
+        let shared = Arc::new(CpalBackendShared {
+            sample_rate: AtomicUsize::new(sample_rate),
+            stream_generation: AtomicUsize::new(0),
+            stop_after_current: AtomicBool::new(false),
+            volume_percent: AtomicUsize::new(100),
+            decode_status: SeqLock::new(DecodeStatus {
+                sample_rate,
+                sample_format,
+                ..Default::default()
+            }),
+            module_and_provider: Mutex::new(ModuleAndProvider {
+                module: CurrentModuleState::NotLoaded,
+                provider: Arc::new(Mutex::new(module_provider)),
+                prefetched: None,
+                need_prefetch: false,
+                control,
+                on_event: Box::new(move |ev| {
+                    sender.send(ev).unwrap();
+                }),
+            }),
+            need_service_cond: Condvar::new(),
+        });
+
+        let waiter = CpalWaiter {
+            shared: shared.clone(),
+        };
+
+        std::thread::Builder::new()
+            .name("CpalWaiter".to_string())
+            .spawn(move || {
+                waiter.run();
+            })
+            .unwrap();
+
+        shared
+    }
+
+    /// Let the waiter load the next module if the playback stopped after the current one.
+    /// Return true if it did.
+    pub(super) fn resume_if_stopped(&self) -> bool {
+        let mut map = self.module_and_provider.lock().unwrap();
+        if let CurrentModuleState::Stopped = map.module {
+            map.module = CurrentModuleState::NotLoaded;
+            self.need_service_cond.notify_all();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn volume_factor(&self) -> f32 {
+        self.volume_percent.load(Ordering::Relaxed) as f32 / 100.0
+    }
+
+    pub(super) fn reload(&self) {
+        let mut map = self.module_and_provider.lock().unwrap();
+        map.reload();
+        // Let the waiter prefetch the module after the new one.
+        self.need_service_cond.notify_all();
+    }
+
+    pub(super) fn update_control(&self, control: ModuleControl) {
+        let mut map = self.module_and_provider.lock().unwrap();
+        map.update_control(control);
+    }
+
+    pub(super) fn send_control_event(&self, event: ControlEvent) {
+        let mut map = self.module_and_provider.lock().unwrap();
+        map.handle_control_event(event);
+    }
 }
 
 struct CpalWaiter {
@@ -111,6 +266,15 @@ impl CpalWaiter {
                 CurrentModuleState::NotLoaded => {
                     map.reload();
                 }
+                CurrentModuleState::Loaded { .. } if map.need_prefetch => {
+                    map.need_prefetch = false;
+                    let provider = map.provider.clone();
+                    // Release the module so that the audio callback can keep reading from it.
+                    drop(map);
+                    let prefetched = provider.lock().unwrap().peek_next_module();
+                    map = self.shared.module_and_provider.lock().unwrap();
+                    map.prefetched = prefetched;
+                }
                 _ => {
                     map = self.shared.need_service_cond.wait(map).unwrap();
                 }
@@ -119,9 +283,19 @@ impl CpalWaiter {
     }
 }
 
-struct CpalBackendPrivate {
+/// The state owned by the audio callback.
+pub(super) struct CpalBackendPrivate {
     shared: Arc<CpalBackendShared>,
-    stream: sync::Weak<Stream>, // Have to close the loop with Option.
+    /// Set after the stream is built, so that the stream can pause itself.
+    stream: Arc<Mutex<sync::Weak<Stream>>>,
+    /// Samples are rendered here before converted if the device does not take f32 samples.
+    convert_buffer: Vec<f32>,
+    /// The name of the sample format of the stream, shown in `DecodeStatus`.
+    sample_format: &'static str,
+    /// The frames of the last buffer requested by the device.  Logged when it changes.
+    buffer_frames: usize,
+    /// The volume factor applied to the last frame.  Moves toward `volume_percent` gradually.
+    volume: f32,
 }
 
 unsafe impl Send for CpalBackendPrivate {}
@@ -134,7 +308,37 @@ enum ModuleReadResult {
 }
 
 impl CpalBackendPrivate {
-    pub fn on_data_requested(&mut self, data: &mut [f32], _info: &cpal::OutputCallbackInfo) {
+    /// Read from the module without a stream.  Reading after the playlist is exhausted only
+    /// renders silence.
+    pub(super) fn without_stream(shared: Arc<CpalBackendShared>) -> Self {
+        Self {
+            stream: Default::default(),
+            convert_buffer: Vec::new(),
+            sample_format: "f32",
+            buffer_frames: 0,
+            volume: shared.volume_factor(),
+            shared,
+        }
+    }
+
+    /// Fill the buffer of a stream that takes samples other than f32.
+    pub fn on_data_requested_converted<T: FromSample<f32>>(&mut self, data: &mut [T]) {
+        let mut buffer = std::mem::take(&mut self.convert_buffer);
+        buffer.resize(data.len(), 0f32);
+        self.on_data_requested(&mut buffer);
+        for (dst, src) in data.iter_mut().zip(buffer.iter()) {
+            *dst = T::from_sample_(*src);
+        }
+        self.convert_buffer = buffer;
+    }
+
+    pub fn on_data_requested(&mut self, data: &mut [f32]) {
+        let buffer_frames = data.len() / CHANNELS;
+        if buffer_frames != self.buffer_frames {
+            log::info!("The device requests {} frames per buffer", buffer_frames);
+            self.buffer_frames = buffer_frames;
+        }
+
         let result = self.read_as_much_as_possible_and_dont_block(data);
 
         let actual_read_samples = if let ModuleReadResult::Read { frames, .. } = result {
@@ -144,6 +348,7 @@ impl CpalBackendPrivate {
         };
 
         data[actual_read_samples..].fill(0f32);
+        self.apply_volume(&mut data[..actual_read_samples]);
 
         match result {
             ModuleReadResult::WouldBlock => {
@@ -162,43 +367,90 @@ impl CpalBackendPrivate {
     fn read_as_much_as_possible_and_dont_block(&mut self, buf: &mut [f32]) -> ModuleReadResult {
         match self.shared.module_and_provider.try_lock() {
             Err(_) => ModuleReadResult::WouldBlock,
-            Ok(mut map) => match map.module {
-                CurrentModuleState::NotLoaded => ModuleReadResult::NotLoaded,
-                CurrentModuleState::Exhausted => ModuleReadResult::Exhausted,
-                CurrentModuleState::Loaded {
-                    ref mut module,
-                    ref moment_state,
-                } => {
-                    let before_reading = Instant::now();
-                    let actual_read_frames =
-                        module.read_interleaved_float_stereo(self.shared.sample_rate as i32, buf);
-                    let elapsed = before_reading.elapsed();
-
-                    if actual_read_frames == 0 {
-                        map.module = CurrentModuleState::NotLoaded;
-                        self.shared.need_service_cond.notify_all();
-                    } else {
-                        let new_moment_state = MomentState::from_module(module);
-                        {
-                            let mut moment_state = moment_state.lock_write();
-                            *moment_state = new_moment_state;
-                        }
+            Ok(mut map) => {
+                let ab_loop = map.control.ab_loop;
+                match map.module {
+                    CurrentModuleState::NotLoaded => ModuleReadResult::NotLoaded,
+                    // Pause the stream like at the end of the playlist.
+                    CurrentModuleState::Exhausted | CurrentModuleState::Stopped => {
+                        ModuleReadResult::Exhausted
                     }
+                    CurrentModuleState::Loaded {
+                        ref mut module,
+                        ref moment_state,
+                    } => {
+                        let before_reading = Instant::now();
+                        let sample_rate = self.shared.sample_rate.load(Ordering::Relaxed);
+                        let actual_read_frames =
+                            module.read_interleaved_float_stereo(sample_rate as i32, buf);
+                        let elapsed = before_reading.elapsed();
+
+                        if actual_read_frames == 0 {
+                            if self
+                                .shared
+                                .stop_after_current
+                                .swap(false, Ordering::Relaxed)
+                            {
+                                map.module = CurrentModuleState::Stopped;
+                                (map.on_event)(BackendEvent::StoppedAfterCurrent);
+                            } else {
+                                map.module = CurrentModuleState::NotLoaded;
+                                self.shared.need_service_cond.notify_all();
+                            }
+                        } else {
+                            if let Some((loop_a, loop_b)) = ab_loop {
+                                if module.get_position_seconds() >= loop_b {
+                                    module.set_position_seconds(loop_a);
+                                }
+                            }
+
+                            let new_moment_state = MomentState::from_module(module);
+                            {
+                                let mut moment_state = moment_state.lock_write();
+                                *moment_state = new_moment_state;
+                            }
+                        }
 
-                    ModuleReadResult::Read {
-                        frames: actual_read_frames,
-                        elapsed,
+                        ModuleReadResult::Read {
+                            frames: actual_read_frames,
+                            elapsed,
+                        }
                     }
                 }
-            },
+            }
+        }
+    }
+
+    /// Scale the samples by the volume, ramping from the volume of the last frame.
+    fn apply_volume(&mut self, data: &mut [f32]) {
+        let target = self.shared.volume_factor();
+        if self.volume == 1.0 && target == 1.0 {
+            return;
+        }
+        let sample_rate = self.shared.sample_rate.load(Ordering::Relaxed);
+        let step = 1.0 / (sample_rate as f32 * VOLUME_RAMP_SECONDS);
+        for frame in data.chunks_mut(CHANNELS) {
+            self.volume = if self.volume < target {
+                (self.volume + step).min(target)
+            } else {
+                (self.volume - step).max(target)
+            };
+            for sample in frame.iter_mut() {
+                *sample *= self.volume;
+            }
         }
     }
 
     fn stop_self(&mut self) {
-        if let Some(stream) = self.stream.upgrade() {
-            stream.pause().unwrap();
-        } else {
-            panic!("The Stream no longer exists.  Did the main thread quit?");
+        // Not set yet if the stream starts as soon as it is built.  It is paused next time.
+        let Ok(stream) = self.stream.try_lock() else {
+            return;
+        };
+        if let Some(stream) = stream.upgrade() {
+            // The device may be gone, in which case the error callback reports it.
+            if let Err(e) = stream.pause() {
+                log::warn!("Cannot pause the stream: {}", e);
+            }
         }
     }
 
@@ -208,14 +460,15 @@ impl CpalBackendPrivate {
         read_frames: usize,
         decode_time: Duration,
     ) {
+        let sample_rate = self.shared.sample_rate.load(Ordering::Relaxed);
         let decode_micros = decode_time.as_micros();
-        let buf_time_micros = read_frames * 1000 * 1000 / self.shared.sample_rate;
+        let buf_time_micros = read_frames * 1000 * 1000 / sample_rate;
         let read_samples = read_frames * CHANNELS;
         let cpu_util = if read_frames == 0 {
             0f64
         } else {
             // Equal to elapsed_micros / buf_time_micros, but more precise.
-            decode_time.as_nanos() as f64 * self.shared.sample_rate as f64
+            decode_time.as_nanos() as f64 * sample_rate as f64
                 / (read_frames as f64 * 1_000_000_000_f64)
         };
         log::trace!(
@@ -229,6 +482,8 @@ impl CpalBackendPrivate {
         {
             let mut decode_status = self.shared.decode_status.lock_write();
             *decode_status = DecodeStatus {
+                sample_rate,
+                sample_format: self.sample_format,
                 buffer_samples,
                 decode_time,
                 cpu_util,
@@ -237,128 +492,499 @@ impl CpalBackendPrivate {
     }
 }
 
+fn build_output_stream<T, F, E>(
+    device: &Device,
+    config: &StreamConfig,
+    mut cpal_writer: CpalBackendPrivate,
+    mut on_data_requested: F,
+    on_error: E,
+) -> Result<Stream>
+where
+    T: SizedSample,
+    F: FnMut(&mut CpalBackendPrivate, &mut [T]) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _info: &cpal::OutputCallbackInfo| {
+            on_data_requested(&mut cpal_writer, data);
+        },
+        on_error,
+        None,
+    )?;
+    Ok(stream)
+}
+
+/// Find the audio host of the given name, ignoring case, e.g. `ALSA` or `JACK`.  Use the default
+/// host if `name` is `None`.
+fn select_host(name: Option<&str>) -> Result<Host> {
+    let Some(name) = name else {
+        return Ok(cpal::default_host());
+    };
+    let host_ids = cpal::available_hosts();
+    match host_ids
+        .iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+    {
+        Some(id) => Ok(cpal::host_from_id(*id)?),
+        None => {
+            let host_names = host_ids.iter().map(|id| id.name()).collect::<Vec<_>>();
+            bail!(
+                "Audio host {:?} is not available.  Available hosts: {}",
+                name,
+                host_names.join(", ")
+            )
+        }
+    }
+}
+
+/// True if the host (or the default host) has a default output device to play through.
+pub fn has_output_device(host_name: Option<&str>) -> bool {
+    select_host(host_name)
+        .ok()
+        .is_some_and2(|host| host.default_output_device().is_some())
+}
+
+/// Print the output devices of the host (or the default host), with the channels, sample formats
+/// and sample rates each supports.
+pub fn list_output_devices(host_name: Option<&str>) -> Result<()> {
+    let host = select_host(host_name)?;
+    println!("Host: {}", host.id().name());
+    let default_name = host
+        .default_output_device()
+        .and_then(|device| device.name().ok());
+    for device in host.output_devices()? {
+        let name = device.name()?;
+        let default_mark = if Some(&name) == default_name.as_ref() {
+            " (default)"
+        } else {
+            ""
+        };
+        println!("{}{}", name, default_mark);
+        match device.supported_output_configs() {
+            Ok(configs) => {
+                for config in configs {
+                    println!(
+                        "    {} channels, {}, {}-{} Hz",
+                        config.channels(),
+                        config.sample_format(),
+                        config.min_sample_rate().0,
+                        config.max_sample_rate().0
+                    );
+                }
+            }
+            Err(e) => println!("    Cannot get the supported configs: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Find the output device whose name contains `name`, ignoring case.  An exact match wins over
+/// other matches.  Use the default device if `name` is `None`.
+fn select_output_device(host: &Host, name: Option<&str>) -> Result<Device> {
+    let Some(name) = name else {
+        return host
+            .default_output_device()
+            .ok_or_else(|| anyhow!("No default output device"));
+    };
+
+    let devices = host
+        .output_devices()?
+        .filter_map(|device| device.name().ok().map(|device_name| (device_name, device)))
+        .collect::<Vec<_>>();
+    let lower_name = name.to_lowercase();
+    let exact = devices
+        .iter()
+        .position(|(device_name, _)| device_name == name);
+    let partial = || {
+        devices
+            .iter()
+            .position(|(device_name, _)| device_name.to_lowercase().contains(&lower_name))
+    };
+    match exact.or_else(partial) {
+        Some(index) => Ok(devices.into_iter().nth(index).unwrap().1),
+        None => {
+            let device_names = devices
+                .iter()
+                .map(|(device_name, _)| device_name.as_str())
+                .collect::<Vec<_>>();
+            bail!(
+                "No output device matches {:?}.  Available devices: {}",
+                name,
+                device_names.join(", ")
+            )
+        }
+    }
+}
+
+/// Choose the config of `device` for the sample rate, or the nearest one it supports.
+fn choose_config(device: &Device, sample_rate: usize) -> Result<SupportedStreamConfig> {
+    const CHANNELS: cpal::ChannelCount = 2;
+    /// Supported sample formats, most preferred first.
+    /// libopenmpt renders f32 samples, which are converted to other formats if necessary.
+    const SAMPLE_FORMATS: [SampleFormat; 3] =
+        [SampleFormat::F32, SampleFormat::I16, SampleFormat::U16];
+
+    let supported_configs = device.supported_output_configs()?.collect::<Vec<_>>();
+
+    // Prefer the requested sample rate over the sample format.  If no config supports the
+    // requested rate, use the nearest one and let libopenmpt render at that rate instead.
+    let (config, actual_sample_rate) = SAMPLE_FORMATS
+        .iter()
+        .enumerate()
+        .flat_map(|(format_index, sample_format)| {
+            supported_configs
+                .iter()
+                .filter(move |config| {
+                    config.channels() == CHANNELS && config.sample_format() == *sample_format
+                })
+                .map(move |config| {
+                    let cpal::SampleRate(min_rate) = config.min_sample_rate();
+                    let cpal::SampleRate(max_rate) = config.max_sample_rate();
+                    let rate = sample_rate.clamp(min_rate as usize, max_rate as usize);
+                    (format_index, config, rate)
+                })
+        })
+        .min_by_key(|(format_index, _, rate)| (rate.abs_diff(sample_rate), *format_index))
+        .map(|(_, config, rate)| (*config, rate))
+        .ok_or_else(|| anyhow!("No suitable config"))?;
+
+    if actual_sample_rate != sample_rate {
+        log::warn!(
+            "Sample rate {} is not supported by the device.  Using {} instead.",
+            sample_rate,
+            actual_sample_rate
+        );
+    }
+
+    let config = config.with_sample_rate(cpal::SampleRate(actual_sample_rate as u32));
+    log::info!("Using output config: {:?}", config);
+    Ok(config)
+}
+
+/// The name of one of the sample formats chosen by `choose_config`.
+fn sample_format_name(sample_format: SampleFormat) -> &'static str {
+    match sample_format {
+        SampleFormat::F32 => "f32",
+        SampleFormat::I16 => "i16",
+        SampleFormat::U16 => "u16",
+        _ => "?",
+    }
+}
+
+/// Build a stream that plays the modules in `shared` on `device`.  The stream may not start
+/// until `play` is called.  Errors are sent to `sender` while `generation` is the
+/// `stream_generation` of `shared`.
+// The stream is only paused from the callback, like `CpalBackendPrivate` being `Send` assumes.
+#[allow(clippy::arc_with_non_send_sync)]
+fn build_stream(
+    device: &Device,
+    config: SupportedStreamConfig,
+    shared: &Arc<CpalBackendShared>,
+    generation: usize,
+    sender: mpsc::Sender<BackendEvent>,
+    buffer_size: BufferSize,
+) -> Result<Arc<Stream>> {
+    let sample_format = config.sample_format();
+    let mut config: StreamConfig = config.into();
+    config.buffer_size = buffer_size;
+
+    let error_shared = shared.clone();
+    let on_error = move |err: StreamError| {
+        // A replaced stream may still fail while it is being dropped.
+        if error_shared.stream_generation.load(Ordering::Relaxed) == generation {
+            // The receiver is gone if the player is quitting.
+            let _ = sender.send(BackendEvent::StreamError(err.to_string()));
+        }
+    };
+
+    let stream_slot = Arc::new(Mutex::new(sync::Weak::new()));
+    let cpal_writer = CpalBackendPrivate {
+        shared: shared.clone(),
+        stream: stream_slot.clone(),
+        convert_buffer: Vec::new(),
+        sample_format: sample_format_name(sample_format),
+        buffer_frames: 0,
+        volume: shared.volume_factor(),
+    };
+
+    let stream = match sample_format {
+        SampleFormat::F32 => build_output_stream(
+            device,
+            &config,
+            cpal_writer,
+            CpalBackendPrivate::on_data_requested,
+            on_error,
+        ),
+        SampleFormat::I16 => build_output_stream(
+            device,
+            &config,
+            cpal_writer,
+            CpalBackendPrivate::on_data_requested_converted::<i16>,
+            on_error,
+        ),
+        SampleFormat::U16 => build_output_stream(
+            device,
+            &config,
+            cpal_writer,
+            CpalBackendPrivate::on_data_requested_converted::<u16>,
+            on_error,
+        ),
+        _ => unreachable!("Unexpected sample format: {}", sample_format),
+    }?;
+    let stream = Arc::new(stream);
+    *stream_slot.lock().unwrap() = Arc::downgrade(&stream);
+    Ok(stream)
+}
+
+/// Like `build_stream`, with buffers of `buffer_frames` frames if given.  Use the default buffer
+/// size of the device if it rejects that size.
+fn build_stream_with_buffer(
+    device: &Device,
+    config: SupportedStreamConfig,
+    shared: &Arc<CpalBackendShared>,
+    generation: usize,
+    sender: mpsc::Sender<BackendEvent>,
+    buffer_frames: Option<u32>,
+) -> Result<Arc<Stream>> {
+    if let Some(frames) = buffer_frames {
+        log::info!(
+            "Requesting {} frames per buffer.  Supported: {:?}",
+            frames,
+            config.buffer_size()
+        );
+        let fixed = BufferSize::Fixed(frames);
+        match build_stream(
+            device,
+            config.clone(),
+            shared,
+            generation,
+            sender.clone(),
+            fixed,
+        ) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => log::warn!(
+                "Cannot use {} frames per buffer: {}.  Using the default size instead.",
+                frames,
+                e
+            ),
+        }
+    }
+    build_stream(
+        device,
+        config,
+        shared,
+        generation,
+        sender,
+        BufferSize::Default,
+    )
+}
+
 impl CpalBackend {
     pub fn new(
+        host_name: Option<&str>,
+        device_name: Option<&str>,
         sample_rate: usize,
+        buffer_frames: Option<u32>,
         module_provider: Box<dyn ModuleProvider>,
         control: ModuleControl,
-    ) -> CpalBackend {
-        let host = cpal::default_host();
+    ) -> Result<CpalBackend> {
+        let host = select_host(host_name)?;
+        log::info!("Audio host: {}", host.id().name());
 
-        let device = host.default_output_device().expect("No default device");
+        let device = select_output_device(&host, device_name)?;
         log::info!("Output device: {:?}", device.name());
 
-        const CHANNELS: cpal::ChannelCount = 2;
-        const SAMPLE_FORMAT: cpal::SampleFormat = cpal::SampleFormat::F32;
-
-        let config = device
-            .supported_output_configs()
-            .unwrap()
-            .find(|config| {
-                let cpal::SampleRate(min_rate) = config.min_sample_rate();
-                let cpal::SampleRate(max_rate) = config.max_sample_rate();
-                let min_rate = min_rate as usize;
-                let max_rate = max_rate as usize;
-
-                config.channels() == CHANNELS
-                    && config.sample_format() == SAMPLE_FORMAT
-                    && min_rate <= sample_rate
-                    && sample_rate <= max_rate
-            })
-            .expect("No suitable config");
-
-        let config = config.with_sample_rate(cpal::SampleRate(sample_rate as u32));
-        log::info!("Using output config: {:?}", config);
+        let config = choose_config(&device, sample_rate)?;
+        let actual_sample_rate = config.sample_rate().0 as usize;
 
         let (be_sender, be_receiver) = mpsc::channel();
 
-        let shared = Arc::new(CpalBackendShared {
-            sample_rate,
-            decode_status: Default::default(),
-            module_and_provider: Mutex::new(ModuleAndProvider {
-                module: CurrentModuleState::NotLoaded,
-                provider: module_provider,
-                control,
-                on_event: Box::new(move |ev| {
-                    be_sender.send(ev).unwrap();
-                }),
-            }),
-            need_service_cond: Condvar::new(),
-        });
-
-        let waiter = CpalWaiter {
-            shared: shared.clone(),
-        };
-
-        std::thread::Builder::new()
-            .name("CpalWaiter".to_string())
-            .spawn(move || {
-                waiter.run();
-            })
-            .unwrap();
-
-        let stream = Arc::new_cyclic(|stream_weak| {
-            let mut cpal_writer = CpalBackendPrivate {
-                shared: shared.clone(),
-                stream: stream_weak.clone(),
-            };
+        let shared = CpalBackendShared::start(
+            actual_sample_rate,
+            sample_format_name(config.sample_format()),
+            module_provider,
+            control,
+            be_sender.clone(),
+        );
 
-            device
-                .build_output_stream(
-                    &config.into(),
-                    move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
-                        cpal_writer.on_data_requested(data, info);
-                    },
-                    |err| panic!("{}", err),
-                    None,
-                )
-                .unwrap()
-        });
+        let stream = build_stream_with_buffer(
+            &device,
+            config,
+            &shared,
+            0,
+            be_sender.clone(),
+            buffer_frames,
+        )?;
 
-        Self {
+        Ok(Self {
             host,
             device,
             stream,
             shared,
             paused: false,
             receiver: be_receiver,
+            sender: be_sender,
+            requested_sample_rate: sample_rate,
+            requested_buffer_frames: buffer_frames,
+        })
+    }
+
+    /// Build a stream on `device`, sharing the module with the current stream, and replace the
+    /// current stream with it.  Keep the current stream if the new one cannot be built.
+    fn play_on_device(&mut self, device: Device) -> Result<()> {
+        let config = choose_config(&device, self.requested_sample_rate)?;
+        let sample_rate = config.sample_rate().0 as usize;
+        let generation = self.shared.stream_generation.load(Ordering::Relaxed) + 1;
+
+        // Stop reading the module from the old stream before the new one starts.  The old stream
+        // fails to pause or play if its device is gone, which does not matter here.
+        if let Err(e) = self.stream.pause() {
+            log::debug!("Cannot pause the old stream: {}", e);
+        }
+        let stream = match build_stream_with_buffer(
+            &device,
+            config,
+            &self.shared,
+            generation,
+            self.sender.clone(),
+            self.requested_buffer_frames,
+        ) {
+            Ok(stream) => stream,
+            Err(e) => {
+                if !self.paused {
+                    if let Err(e) = self.stream.play() {
+                        log::debug!("Cannot resume the old stream: {}", e);
+                    }
+                }
+                return Err(e);
+            }
+        };
+        self.shared
+            .stream_generation
+            .store(generation, Ordering::Relaxed);
+        self.shared
+            .sample_rate
+            .store(sample_rate, Ordering::Relaxed);
+
+        self.stream = stream;
+        log::info!("Output device: {:?}", device.name());
+        self.device = device;
+        if self.paused {
+            // Some hosts start streams as soon as they are built.
+            if let Err(e) = self.stream.pause() {
+                log::warn!("Cannot pause the new stream: {}", e);
+            }
+        } else {
+            self.stream.play()?;
+        }
+        Ok(())
+    }
+
+    /// Play or pause the stream.  A failure, e.g. because the device is gone, is reported like
+    /// an error of the stream, so that the app tries to reopen the output.
+    fn set_playing(&self, playing: bool) {
+        let result = if playing {
+            self.stream.play().map_err(|e| e.to_string())
+        } else {
+            self.stream.pause().map_err(|e| e.to_string())
+        };
+        if let Err(e) = result {
+            let action = if playing { "play" } else { "pause" };
+            let message = format!("Cannot {} the stream: {}", action, e);
+            let _ = self.sender.send(BackendEvent::StreamError(message));
         }
     }
 }
 
 impl Backend for CpalBackend {
     fn start(&mut self) {
-        self.stream.play().unwrap();
+        self.set_playing(true);
     }
 
     fn pause_resume(&mut self) {
-        if self.paused {
-            self.stream.play().unwrap();
+        if self.shared.resume_if_stopped() {
+            self.set_playing(true);
             self.paused = false;
-        } else {
-            self.stream.pause().unwrap();
-            self.paused = true;
+            return;
         }
+        self.paused = !self.paused;
+        self.set_playing(!self.paused);
+    }
+
+    fn set_stop_after_current(&mut self, stop: bool) {
+        self.shared
+            .stop_after_current
+            .store(stop, Ordering::Relaxed);
+    }
+
+    fn set_volume(&mut self, percent: usize) {
+        self.shared.volume_percent.store(percent, Ordering::Relaxed);
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
     }
 
     fn reload(&mut self) {
-        let mut map = self.shared.module_and_provider.lock().unwrap();
-        map.reload();
+        self.shared.reload();
+        // The stream stops itself when the playlist is exhausted.
+        if !self.paused {
+            self.set_playing(true);
+        }
     }
 
     fn poll_event(&mut self) -> Option<BackendEvent> {
-        match self.receiver.try_recv() {
-            Ok(ev) => Some(ev),
-            Err(_) => None,
-        }
+        self.receiver.try_recv().ok()
     }
 
     fn update_control(&mut self, control: super::ModuleControl) {
-        let mut map = self.shared.module_and_provider.lock().unwrap();
-        map.update_control(control);
+        self.shared.update_control(control);
+    }
+
+    fn send_control_event(&mut self, event: ControlEvent) {
+        self.shared.send_control_event(event);
     }
 
     fn read_decode_status(&self) -> DecodeStatus {
         self.shared.decode_status.read()
     }
+
+    fn output_devices(&self) -> (Vec<String>, Option<usize>) {
+        let names = match self.host.output_devices() {
+            Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+            Err(e) => {
+                log::error!("Cannot list the output devices: {}", e);
+                vec![]
+            }
+        };
+        let current_name = self.device.name().ok();
+        let current = names
+            .iter()
+            .position(|name| Some(name) == current_name.as_ref());
+        (names, current)
+    }
+
+    /// Build a stream on the new device, sharing the module with the current stream.
+    fn switch_device(&mut self, name: &str) -> Result<()> {
+        let device = self
+            .host
+            .output_devices()?
+            .find(|device| {
+                device
+                    .name()
+                    .ok()
+                    .is_some_and2(|device_name| device_name == name)
+            })
+            .ok_or_else(|| anyhow!("The output device {:?} is gone", name))?;
+        self.play_on_device(device)
+    }
+
+    fn recover(&mut self) -> Result<()> {
+        let device = self
+            .host
+            .default_output_device()
+            .ok_or_else(|| anyhow!("No default output device"))?;
+        self.play_on_device(device)
+    }
 }
@@ -12,27 +12,78 @@
 // not, see <https://www.gnu.org/licenses/>.
 
 mod cpal;
+mod null;
 
 use std::time::Duration;
 
 use openmpt::module::Module;
 
-use crate::{control::ModuleControl, player::PlayState};
+use crate::{
+    control::ModuleControl,
+    player::{ModuleInfo, PlayState},
+};
 
-pub use self::cpal::CpalBackend;
+pub use self::cpal::{has_output_device, list_output_devices, CpalBackend};
+pub use self::null::NullBackend;
 
 pub trait ModuleProvider: Send {
     /// Get the next module after the current module has been played.
     fn poll_module(&mut self) -> Option<Module>;
+
+    /// Open the module that `poll_module` would return next, without advancing.
+    /// This may be slow, and is called while the current module is still playing.
+    fn peek_next_module(&mut self) -> Option<PrefetchedModule>;
+
+    /// Advance as if `poll_module` returned the prefetched module.
+    /// Return false if the prefetched module is no longer the next one to play.
+    fn accept_prefetched(&mut self, prefetched: &PrefetchedModule) -> bool;
+}
+
+/// A module opened ahead of time so that it can be played as soon as the current one ends.
+pub struct PrefetchedModule {
+    /// Identifies the item the module is opened from.  Only meaningful to the provider.
+    pub key: usize,
+    pub module: Module,
 }
 
 pub enum BackendEvent {
-    StartedPlaying { play_state: PlayState },
+    StartedPlaying {
+        play_state: PlayState,
+    },
+    /// Another subsong of the current module is selected.  The duration and orders may differ.
+    SubsongSelected {
+        module_info: ModuleInfo,
+    },
     PlayListExhausted,
+    /// The module ended and the playback stopped because `set_stop_after_current` was set.  The
+    /// next module is played when resumed.
+    StoppedAfterCurrent,
+    /// The stream failed, e.g. because the device was unplugged.  Call `Backend::recover` to
+    /// play on another device.
+    StreamError(String),
+}
+
+/// Events sent from the main thread to act upon the module being played.
+pub enum ControlEvent {
+    /// Run arbitrary code on the current module, if any, in the thread that owns the module.
+    Generic(Box<dyn FnOnce(&mut Module) + Send>),
+    /// Select the subsong of the given index and play it from the start.
+    SelectSubsong(usize),
+}
+
+impl ControlEvent {
+    pub fn generic(f: impl FnOnce(&mut Module) + Send + 'static) -> Self {
+        Self::Generic(Box::new(f))
+    }
 }
 
 #[derive(Default, Clone, Copy)]
 pub struct DecodeStatus {
+    /// The sample rate the device actually plays at, which may differ from the requested one.
+    pub sample_rate: usize,
+    /// The format of the samples the device takes, e.g. `f32` or `i16`.  Samples are converted
+    /// from f32 if necessary.
+    pub sample_format: &'static str,
     pub buffer_samples: usize,
     pub decode_time: Duration,
     pub cpu_util: f64,
@@ -42,8 +93,22 @@ pub struct DecodeStatus {
 pub trait Backend {
     fn start(&mut self);
     fn pause_resume(&mut self);
+    /// Stop when the current module ends instead of playing the next one.  Only applies once.
+    fn set_stop_after_current(&mut self, stop: bool);
+    /// Scale the output by the percentage, after decoding.  Independent of the gain of the module.
+    fn set_volume(&mut self, percent: usize);
+    fn is_paused(&self) -> bool;
     fn reload(&mut self);
     fn poll_event(&mut self) -> Option<BackendEvent>;
     fn update_control(&mut self, control: ModuleControl);
+    fn send_control_event(&mut self, event: ControlEvent);
     fn read_decode_status(&self) -> DecodeStatus;
+    /// The names of the output devices, and the index of the one being played through, if any.
+    fn output_devices(&self) -> (Vec<String>, Option<usize>);
+    /// Play through the output device of the given name from now on, continuing with the same
+    /// module.  Keep the current device if the new one cannot be opened.
+    fn switch_device(&mut self, name: &str) -> anyhow::Result<()>;
+    /// Play through the default output device after the stream failed, continuing with the same
+    /// module.
+    fn recover(&mut self) -> anyhow::Result<()>;
 }
@@ -0,0 +1,390 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+
+use crate::control::ModuleControl;
+
+use super::{
+    cpal::{CpalBackendPrivate, CpalBackendShared},
+    Backend, BackendEvent, ControlEvent, DecodeStatus, ModuleProvider,
+};
+
+/// Frames decoded at a time.
+const BUFFER_FRAMES: usize = 1024;
+
+/// How often to check whether the playback is resumed while paused.
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A backend that plays to no device.  Modules are decoded at the pace of real time and the
+/// samples are thrown away.  For machines without sound devices.
+pub struct NullBackend {
+    shared: Arc<CpalBackendShared>,
+    paused: Arc<AtomicBool>,
+    /// Tells the player thread to stop.
+    stopped: Arc<AtomicBool>,
+    receiver: mpsc::Receiver<BackendEvent>,
+}
+
+impl NullBackend {
+    /// Create the backend.  The playback starts paused until `start` is called.
+    pub fn new(
+        sample_rate: usize,
+        module_provider: Box<dyn ModuleProvider>,
+        control: ModuleControl,
+    ) -> NullBackend {
+        let (be_sender, be_receiver) = mpsc::channel();
+        let shared =
+            CpalBackendShared::start(sample_rate, "f32", module_provider, control, be_sender);
+        let paused = Arc::new(AtomicBool::new(true));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let mut reader = CpalBackendPrivate::without_stream(shared.clone());
+        let thread_paused = paused.clone();
+        let thread_stopped = stopped.clone();
+        std::thread::Builder::new()
+            .name("NullPlayer".to_string())
+            .spawn(move || {
+                let mut buf = vec![0f32; BUFFER_FRAMES * 2];
+                let buffer_time =
+                    Duration::from_secs_f64(BUFFER_FRAMES as f64 / sample_rate as f64);
+                while !thread_stopped.load(Ordering::Relaxed) {
+                    if thread_paused.load(Ordering::Relaxed) {
+                        std::thread::sleep(PAUSED_POLL_INTERVAL);
+                        continue;
+                    }
+                    let before_reading = Instant::now();
+                    reader.on_data_requested(&mut buf);
+                    std::thread::sleep(buffer_time.saturating_sub(before_reading.elapsed()));
+                }
+            })
+            .unwrap();
+
+        log::info!("Playing without an output device.");
+
+        Self {
+            shared,
+            paused,
+            stopped,
+            receiver: be_receiver,
+        }
+    }
+}
+
+impl Drop for NullBackend {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Backend for NullBackend {
+    fn start(&mut self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    fn pause_resume(&mut self) {
+        if self.shared.resume_if_stopped() {
+            self.paused.store(false, Ordering::Relaxed);
+            return;
+        }
+        self.paused.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    fn set_stop_after_current(&mut self, stop: bool) {
+        self.shared
+            .stop_after_current
+            .store(stop, Ordering::Relaxed);
+    }
+
+    fn set_volume(&mut self, percent: usize) {
+        self.shared.volume_percent.store(percent, Ordering::Relaxed);
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    fn reload(&mut self) {
+        self.shared.reload();
+    }
+
+    fn poll_event(&mut self) -> Option<BackendEvent> {
+        self.receiver.try_recv().ok()
+    }
+
+    fn update_control(&mut self, control: ModuleControl) {
+        self.shared.update_control(control);
+    }
+
+    fn send_control_event(&mut self, event: ControlEvent) {
+        self.shared.send_control_event(event);
+    }
+
+    fn read_decode_status(&self) -> DecodeStatus {
+        self.shared.decode_status.read()
+    }
+
+    fn output_devices(&self) -> (Vec<String>, Option<usize>) {
+        (vec![], None)
+    }
+
+    fn switch_device(&mut self, _name: &str) -> Result<()> {
+        bail!("Cannot switch devices with the null backend")
+    }
+
+    fn recover(&mut self) -> Result<()> {
+        // Nothing can fail without a stream.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Mutex};
+
+    use openmpt::module::{Logger, Module};
+
+    use super::*;
+    use crate::{
+        backend::PrefetchedModule,
+        playlist::{ModPath, PlayList, PlayListItem, PlayListModuleProvider},
+    };
+
+    /// Long enough for a module to be loaded and played to the end.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Long enough for a module to end if it were playing.
+    const QUIET_TIME: Duration = Duration::from_millis(300);
+
+    /// A ProTracker module with one pattern which breaks on the first row at speed 1, so it
+    /// plays for a tick.
+    fn short_module_bytes() -> Vec<u8> {
+        let mut bytes = vec![0u8; 1084 + 64 * 4 * 4];
+        // The song length.
+        bytes[950] = 1;
+        bytes[1080..1084].copy_from_slice(b"M.K.");
+        // Channel 1: set speed to 1.  Channel 2: break to the next pattern.
+        bytes[1084..1092].copy_from_slice(&[0, 0, 0x0f, 0x01, 0, 0, 0x0d, 0x00]);
+        bytes
+    }
+
+    fn short_module() -> Module {
+        Module::create_from_memory(&mut short_module_bytes(), Logger::None, &[]).unwrap()
+    }
+
+    /// Provides a number of short modules, without prefetching.
+    struct ShortModules {
+        remaining: usize,
+    }
+
+    impl ModuleProvider for ShortModules {
+        fn poll_module(&mut self) -> Option<Module> {
+            if self.remaining == 0 {
+                return None;
+            }
+            self.remaining -= 1;
+            Some(short_module())
+        }
+
+        fn peek_next_module(&mut self) -> Option<PrefetchedModule> {
+            None
+        }
+
+        fn accept_prefetched(&mut self, _prefetched: &PrefetchedModule) -> bool {
+            false
+        }
+    }
+
+    fn backend_with_modules(n_modules: usize) -> NullBackend {
+        NullBackend::new(
+            48000,
+            Box::new(ShortModules {
+                remaining: n_modules,
+            }),
+            ModuleControl::default(),
+        )
+    }
+
+    fn event_name(event: &BackendEvent) -> &'static str {
+        match event {
+            BackendEvent::StartedPlaying { .. } => "StartedPlaying",
+            BackendEvent::SubsongSelected { .. } => "SubsongSelected",
+            BackendEvent::PlayListExhausted => "PlayListExhausted",
+            BackendEvent::StoppedAfterCurrent => "StoppedAfterCurrent",
+            BackendEvent::StreamError(_) => "StreamError",
+        }
+    }
+
+    /// A directory of short modules under the system temporary directory, removed when dropped.
+    struct ModuleDir(PathBuf);
+
+    impl ModuleDir {
+        fn new(name: &str, file_names: &[&str]) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "tuimodplayer-test-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            for file_name in file_names {
+                std::fs::write(path.join(file_name), short_module_bytes()).unwrap();
+            }
+            Self(path)
+        }
+
+        fn mod_path(&self, file_name: &str) -> ModPath {
+            ModPath {
+                root_path: self.0.clone().into(),
+                file_path: self.0.join(file_name).into(),
+                archive_paths: vec![],
+                is_archived_single: false,
+            }
+        }
+    }
+
+    impl Drop for ModuleDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Wait up to `timeout` for the next event and return its name.
+    fn next_event(backend: &mut NullBackend, timeout: Duration) -> Option<&'static str> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Some(event) = backend.poll_event() {
+                return Some(event_name(&event));
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        None
+    }
+
+    /// Collect the names of the events up to `PlayListExhausted`.
+    fn events_until_exhausted(backend: &mut NullBackend) -> Vec<&'static str> {
+        let mut events = vec![];
+        while let Some(event) = next_event(backend, TIMEOUT) {
+            events.push(event);
+            if event == "PlayListExhausted" {
+                break;
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn plays_every_module_then_exhausts() {
+        let mut backend = backend_with_modules(3);
+        backend.start();
+
+        assert_eq!(
+            events_until_exhausted(&mut backend),
+            [
+                "StartedPlaying",
+                "StartedPlaying",
+                "StartedPlaying",
+                "PlayListExhausted"
+            ]
+        );
+    }
+
+    #[test]
+    fn playlist_advances_past_missing_files() {
+        let dir = ModuleDir::new("advance", &["first.mod", "last.mod"]);
+        let mut playlist = PlayList::new();
+        for file_name in ["first.mod", "missing.mod", "last.mod"] {
+            playlist.add_item(PlayListItem::new(dir.mod_path(file_name)));
+        }
+        playlist.stop_at_end = true;
+        let playlist = Arc::new(Mutex::new(playlist));
+        let mut backend = NullBackend::new(
+            48000,
+            Box::new(PlayListModuleProvider::new(playlist.clone())),
+            ModuleControl::default(),
+        );
+        backend.start();
+
+        assert_eq!(
+            events_until_exhausted(&mut backend),
+            ["StartedPlaying", "StartedPlaying", "PlayListExhausted"]
+        );
+        let playlist = playlist.lock().unwrap();
+        assert_eq!(playlist.now_playing_in_items, Some(2));
+        assert_eq!(playlist.n_failed(), 1);
+    }
+
+    #[test]
+    fn empty_provider_exhausts_at_once() {
+        let mut backend = backend_with_modules(0);
+        backend.start();
+
+        assert_eq!(next_event(&mut backend, TIMEOUT), Some("PlayListExhausted"));
+    }
+
+    #[test]
+    fn does_not_advance_while_paused() {
+        let mut backend = backend_with_modules(1);
+        assert!(backend.is_paused());
+
+        // The first module is loaded even before the playback starts.
+        assert_eq!(next_event(&mut backend, TIMEOUT), Some("StartedPlaying"));
+        assert_eq!(next_event(&mut backend, QUIET_TIME), None);
+
+        backend.pause_resume();
+        assert!(!backend.is_paused());
+        assert_eq!(next_event(&mut backend, TIMEOUT), Some("PlayListExhausted"));
+
+        backend.pause_resume();
+        assert!(backend.is_paused());
+    }
+
+    #[test]
+    fn reload_skips_to_next_module() {
+        let mut backend = backend_with_modules(2);
+        assert_eq!(next_event(&mut backend, TIMEOUT), Some("StartedPlaying"));
+
+        // Paused, so only `reload` advances the playlist.
+        backend.reload();
+        assert_eq!(next_event(&mut backend, TIMEOUT), Some("StartedPlaying"));
+        backend.reload();
+        assert_eq!(next_event(&mut backend, TIMEOUT), Some("PlayListExhausted"));
+    }
+
+    #[test]
+    fn stop_after_current_waits_for_resume() {
+        let mut backend = backend_with_modules(2);
+        backend.set_stop_after_current(true);
+        backend.start();
+
+        assert_eq!(next_event(&mut backend, TIMEOUT), Some("StartedPlaying"));
+        assert_eq!(
+            next_event(&mut backend, TIMEOUT),
+            Some("StoppedAfterCurrent")
+        );
+        assert_eq!(next_event(&mut backend, QUIET_TIME), None);
+
+        backend.pause_resume();
+        assert!(!backend.is_paused());
+        assert_eq!(next_event(&mut backend, TIMEOUT), Some("StartedPlaying"));
+        assert_eq!(next_event(&mut backend, TIMEOUT), Some("PlayListExhausted"));
+    }
+}
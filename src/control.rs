@@ -21,7 +21,9 @@ pub struct ModuleControl {
     pub stereo_separation: ControlField<i32>,
     pub filter_taps: ControlField<i32>,
     pub volume_ramping: ControlField<i32>,
-    pub repeat: bool,
+    pub repeat: RepeatMode,
+    /// Loop between these two positions (in seconds) of the current module.
+    pub ab_loop: Option<(f64, f64)>,
 }
 
 impl Default for ModuleControl {
@@ -33,7 +35,81 @@ impl Default for ModuleControl {
             stereo_separation: ControlField::new(&controls::STEREO_SEPARATION),
             filter_taps: ControlField::new(&controls::FILTER_TAPS),
             volume_ramping: ControlField::new(&controls::VOLUME_RAMPING),
-            repeat: false,
+            repeat: RepeatMode::default(),
+            ab_loop: None,
+        }
+    }
+}
+
+impl ModuleControl {
+    /// Set the tempo, pitch and the render settings back to the defaults.  The repeat mode and
+    /// the A-B loop are kept.
+    pub fn reset_fields(&mut self) {
+        self.tempo.reset();
+        self.pitch.reset();
+        self.gain.reset();
+        self.stereo_separation.reset();
+        self.filter_taps.reset();
+        self.volume_ramping.reset();
+    }
+}
+
+/// The value of the tempo control nearest to the percentage of the normal tempo, or `None` if
+/// the percentage is not positive.
+pub fn tempo_value_for_percent(percent: f64) -> Option<i32> {
+    if !(percent.is_finite() && percent > 0.0) {
+        return None;
+    }
+    Some((24.0 * (percent / 100.0).log2()).round() as i32)
+}
+
+/// The value of the pitch control nearest to the offset in semitones.
+pub fn pitch_value_for_semitones(semitones: f64) -> Option<i32> {
+    if !semitones.is_finite() {
+        return None;
+    }
+    // The pitch control moves in quarter tones.
+    Some((semitones * 2.0).round() as i32)
+}
+
+/// What to do when a module ends.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum RepeatMode {
+    /// Play the next module, and stop after the last one in the playlist.
+    Off,
+    /// Repeat the current module forever.
+    One,
+    /// Play the next module, and continue from the first one after the last one.
+    #[default]
+    All,
+}
+
+impl RepeatMode {
+    /// The mode after this one when cycling through modes in the UI.
+    pub fn next(self) -> Self {
+        match self {
+            RepeatMode::Off => RepeatMode::One,
+            RepeatMode::One => RepeatMode::All,
+            RepeatMode::All => RepeatMode::Off,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RepeatMode::Off => "off",
+            RepeatMode::One => "one",
+            RepeatMode::All => "list",
+        }
+    }
+
+    /// The inverse of `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            // The name of `All` in older sessions.
+            "all" => Some(RepeatMode::All),
+            _ => [RepeatMode::Off, RepeatMode::One, RepeatMode::All]
+                .into_iter()
+                .find(|mode| mode.name() == name),
         }
     }
 }
@@ -133,10 +209,19 @@ impl<T: Num + Copy + FromPrimitive> ControlField<T> {
         self.value = self.value.saturating_sub(self.spec.step).max(self.spec.low);
     }
 
+    pub fn reset(&mut self) {
+        self.value = self.spec.default;
+    }
+
     pub fn value(&self) -> i32 {
         self.value
     }
 
+    /// Set the raw value, clamped to the range of the spec.
+    pub fn set_value(&mut self, value: i32) {
+        self.value = value.clamp(self.spec.low, self.spec.high);
+    }
+
     pub fn output(&self) -> T {
         match self.spec.scale {
             ControlScale::Linear { factor, offset } => {
@@ -12,13 +12,18 @@
 // not, see <https://www.gnu.org/licenses/>.
 
 mod app;
+mod archive;
 mod backend;
 mod control;
 mod logging;
 mod module_file;
+#[cfg(all(feature = "mpris", target_os = "linux"))]
+mod mpris;
 mod options;
 mod player;
 mod playlist;
+mod render;
+mod session;
 mod ui;
 mod util;
 
@@ -13,15 +13,20 @@
 
 use std::{
     fs::File,
-    io::{Cursor, Read, Seek},
+    io::{Cursor, Read},
+    path::Path,
 };
 
-use openmpt::module::{stream::ModuleStream, Logger, Module};
+use openmpt::module::{ctls::Ctl, metadata::MetadataKey, stream::ModuleStream, Logger, Module};
 
 use anyhow::{Context, Result};
-use zip::ZipArchive;
+use xxhash_rust::xxh3::Xxh3;
 
-use crate::{control::ModuleControl, playlist::ModPath};
+use crate::{
+    archive::{ArchiveKind, ReadWhatFromArchive},
+    control::{ModuleControl, RepeatMode},
+    playlist::ModPath,
+};
 
 #[derive(Debug)]
 pub struct ModuleCreationError;
@@ -33,61 +38,129 @@ impl std::fmt::Display for ModuleCreationError {
     }
 }
 
-fn open_module(mut stream: impl ModuleStream) -> Result<Module, ModuleCreationError> {
-    Module::create(&mut stream, Logger::None, &[]).map_err(|_| ModuleCreationError)
+/// How to open a module.
+struct OpenOptions {
+    ctls: &'static [Ctl],
+    /// The level of our messages about the files being opened.
+    log_level: log::Level,
+    /// The level of the warnings libopenmpt reports while loading the module.
+    warning_level: log::Level,
+}
+
+const OPEN_FOR_PLAYING: OpenOptions = OpenOptions {
+    ctls: &[],
+    log_level: log::Level::Info,
+    warning_level: log::Level::Warn,
+};
+
+/// Skip samples and plugins so that modules open faster, but they cannot be played.  Do not flood
+/// the log when scanning the whole playlist.
+const OPEN_FOR_METADATA: OpenOptions = OpenOptions {
+    ctls: &[Ctl::SkipLoadingSamples(true), Ctl::SkipLoadingPlugins(true)],
+    log_level: log::Level::Debug,
+    warning_level: log::Level::Debug,
+};
+
+fn open_module(
+    mut stream: impl ModuleStream,
+    options: &OpenOptions,
+) -> Result<Module, ModuleCreationError> {
+    let mut module =
+        Module::create(&mut stream, Logger::None, options.ctls).map_err(|_| ModuleCreationError)?;
+    // openmpt-rs cannot forward libopenmpt's log to a callback, but the warnings generated while
+    // loading are also available as metadata.
+    if let Some(warnings) = module.get_metadata(MetadataKey::Warnings) {
+        for warning in warnings.lines().filter(|line| !line.trim().is_empty()) {
+            log::log!(target: "libopenmpt", options.warning_level, "{}", warning);
+        }
+    }
+    Ok(module)
 }
 
 pub fn open_module_from_mod_path(mod_path: &ModPath) -> Result<Module> {
+    open_module_from_mod_path_with_options(mod_path, &OPEN_FOR_PLAYING)
+}
+
+/// Open a module faster by skipping samples and plugins.  Only use it to read metadata.
+pub fn open_module_for_metadata(mod_path: &ModPath) -> Result<Module> {
+    open_module_from_mod_path_with_options(mod_path, &OPEN_FOR_METADATA)
+}
+
+fn open_module_from_mod_path_with_options(
+    mod_path: &ModPath,
+    options: &OpenOptions,
+) -> Result<Module> {
+    let log_level = options.log_level;
     let file = File::open(&mod_path.file_path)?;
 
     if mod_path.archive_paths.is_empty() {
-        log::info!(
+        log::log!(
+            log_level,
             "Opening root path as module: {}",
             mod_path.file_path.to_string_lossy()
         );
-        Ok(open_module(file)?)
+        Ok(open_module(file, options)?)
     } else {
-        log::info!(
+        log::log!(
+            log_level,
             "Opening file in archive: {}",
             mod_path.file_path.to_string_lossy()
         );
-        let mut content =
-            read_file_from_archive(file, ReadWhatFromArchive::Name(&mod_path.archive_paths[0]))?;
+        let content = read_from_archives(file, mod_path)?;
+        let cursor = Cursor::new(content);
+        Ok(open_module(cursor, options)?)
+    }
+}
 
-        for archive_path in mod_path.archive_paths[1..].iter() {
-            let cursor = Cursor::new(content);
-            content = read_file_from_archive(cursor, ReadWhatFromArchive::Name(archive_path))
-                .context("Opening inner archive")?;
-        }
+/// Extract the module file from the (possibly nested) archives in `mod_path`.  `file` is the
+/// outermost archive.
+fn read_from_archives(file: File, mod_path: &ModPath) -> Result<Vec<u8>> {
+    let mut archive_name = Path::new(&mod_path.file_path);
+    let mut content = archive_kind(archive_name)?
+        .read_file(file, ReadWhatFromArchive::Name(&mod_path.archive_paths[0]))?;
+    archive_name = Path::new(&mod_path.archive_paths[0]);
 
-        if mod_path.is_archived_single {
-            let cursor = Cursor::new(content);
-            content = read_file_from_archive(cursor, ReadWhatFromArchive::First)
-                .context("Opening archived single")?;
-        }
+    for archive_path in mod_path.archive_paths[1..].iter() {
+        let cursor = Cursor::new(content);
+        content = archive_kind(archive_name)?
+            .read_file(cursor, ReadWhatFromArchive::Name(archive_path))
+            .context("Opening inner archive")?;
+        archive_name = Path::new(archive_path);
+    }
 
+    if mod_path.is_archived_single {
         let cursor = Cursor::new(content);
-        Ok(open_module(cursor)?)
+        content = archive_kind(archive_name)?
+            .read_file(cursor, ReadWhatFromArchive::First)
+            .context("Opening archived single")?;
     }
+
+    Ok(content)
 }
 
-enum ReadWhatFromArchive<'a> {
-    Name(&'a str),
-    First,
+/// Hash the content of the module file, extracting it from archives if needed.  Files in the file
+/// system are read in chunks rather than all at once.
+pub fn hash_module_file(mod_path: &ModPath) -> Result<u64> {
+    let mut file = File::open(&mod_path.file_path)?;
+    let mut hasher = Xxh3::new();
+    if mod_path.archive_paths.is_empty() {
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+        }
+    } else {
+        hasher.update(&read_from_archives(file, mod_path)?);
+    }
+    Ok(hasher.digest())
 }
 
-fn read_file_from_archive(archive: impl Read + Seek, what: ReadWhatFromArchive) -> Result<Vec<u8>> {
-    let mut zip = ZipArchive::new(archive)?;
-    let mut zip_file = match what {
-        ReadWhatFromArchive::Name(archive_path) => zip.by_name(archive_path)?,
-        ReadWhatFromArchive::First => zip.by_index(0)?,
-    };
-    let zip_file_size = zip_file.size();
-    let size = usize::try_from(zip_file_size)
-        .map_err(|_| anyhow::anyhow!("File too large: {}", zip_file_size))?;
-    let mut content = Vec::with_capacity(size);
-    zip_file.read_to_end(&mut content)?;
-    Ok(content)
+fn archive_kind(archive_name: &Path) -> Result<ArchiveKind> {
+    ArchiveKind::from_path(archive_name)
+        .with_context(|| format!("Unsupported archive: {}", archive_name.to_string_lossy()))
 }
 
 pub fn apply_mod_settings(module: &mut Module, control: &ModuleControl) {
@@ -97,5 +170,9 @@ pub fn apply_mod_settings(module: &mut Module, control: &ModuleControl) {
     module.set_render_stereo_separation(control.stereo_separation.output());
     module.set_render_interpolation_filter_length(control.filter_taps.output());
     module.set_render_volume_ramping(control.volume_ramping.output());
-    module.set_repeat_count(if control.repeat { -1 } else { 0 });
+    module.set_repeat_count(if control.repeat == RepeatMode::One {
+        -1
+    } else {
+        0
+    });
 }
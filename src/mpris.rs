@@ -0,0 +1,300 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+//! The MPRIS2 D-Bus interface, so that media keys and tools like `playerctl` can control the
+//! player.  See <https://specifications.freedesktop.org/mpris-spec/latest/>.
+
+use std::{
+    collections::HashMap,
+    sync::{mpsc, Arc, Mutex},
+};
+
+use anyhow::Result;
+use seqlock::SeqLock;
+use zbus::{
+    blocking::{connection, Connection},
+    interface,
+    zvariant::{ObjectPath, Value},
+};
+
+use crate::player::MomentState;
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.tuimodplayer";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/// Requests from MPRIS clients, to be carried out by the UI thread.
+pub enum MprisCommand {
+    PlayPause,
+    Play,
+    Pause,
+    Next,
+    Previous,
+}
+
+/// The module being played, as reported to MPRIS clients.
+#[derive(Clone)]
+pub struct Track {
+    /// The ID of the playlist item.
+    pub id: usize,
+    pub title: String,
+    /// Duration in seconds.  Not reported if not positive.
+    pub duration: f64,
+    pub moment_state: Arc<SeqLock<MomentState>>,
+}
+
+#[derive(Default)]
+struct Status {
+    track: Option<Track>,
+    paused: bool,
+}
+
+impl Status {
+    fn playback_status(&self) -> &'static str {
+        match (&self.track, self.paused) {
+            (None, _) => "Stopped",
+            (Some(_), true) => "Paused",
+            (Some(_), false) => "Playing",
+        }
+    }
+
+    fn metadata(&self) -> HashMap<String, Value<'static>> {
+        let mut metadata = HashMap::new();
+        if let Some(ref track) = self.track {
+            if let Ok(track_id) =
+                ObjectPath::try_from(format!("/org/tuimodplayer/track/{}", track.id))
+            {
+                metadata.insert("mpris:trackid".to_string(), Value::from(track_id));
+            }
+            metadata.insert("xesam:title".to_string(), Value::from(track.title.clone()));
+            if track.duration > 0.0 {
+                metadata.insert(
+                    "mpris:length".to_string(),
+                    Value::from(seconds_to_micros(track.duration)),
+                );
+            }
+        }
+        metadata
+    }
+}
+
+fn seconds_to_micros(seconds: f64) -> i64 {
+    (seconds * 1_000_000.0) as i64
+}
+
+/// The `org.mpris.MediaPlayer2` interface.
+struct MprisRoot;
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl MprisRoot {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        "TUIModPlayer".to_string()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec![]
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+/// The `org.mpris.MediaPlayer2.Player` interface.
+struct MprisPlayer {
+    commands: mpsc::Sender<MprisCommand>,
+    status: Arc<Mutex<Status>>,
+}
+
+impl MprisPlayer {
+    fn send(&self, command: MprisCommand) {
+        // The receiver is only gone when the player is quitting.
+        let _ = self.commands.send(command);
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl MprisPlayer {
+    fn play_pause(&self) {
+        self.send(MprisCommand::PlayPause);
+    }
+
+    fn play(&self) {
+        self.send(MprisCommand::Play);
+    }
+
+    fn pause(&self) {
+        self.send(MprisCommand::Pause);
+    }
+
+    /// There is no stopped state in this player.  Pause instead.
+    fn stop(&self) {
+        self.send(MprisCommand::Pause);
+    }
+
+    fn next(&self) {
+        self.send(MprisCommand::Next);
+    }
+
+    fn previous(&self) {
+        self.send(MprisCommand::Previous);
+    }
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        self.status.lock().unwrap().playback_status().to_string()
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, Value<'static>> {
+        self.status.lock().unwrap().metadata()
+    }
+
+    /// The position in microseconds.
+    #[zbus(property)]
+    fn position(&self) -> i64 {
+        self.status
+            .lock()
+            .unwrap()
+            .track
+            .as_ref()
+            .map(|track| seconds_to_micros(track.moment_state.read().position))
+            .unwrap_or(0)
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// Owns the D-Bus connection.  zbus serves incoming calls in its own executor thread, and the
+/// calls are forwarded to the UI thread as `MprisCommand`s.
+pub struct MprisServer {
+    connection: Connection,
+    commands: mpsc::Receiver<MprisCommand>,
+    status: Arc<Mutex<Status>>,
+}
+
+impl MprisServer {
+    /// Connect to the session bus and register the player.
+    pub fn start() -> Result<Self> {
+        let (sender, commands) = mpsc::channel();
+        let status = Arc::new(Mutex::new(Status::default()));
+        let player = MprisPlayer {
+            commands: sender,
+            status: status.clone(),
+        };
+        let connection = connection::Builder::session()?
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, MprisRoot)?
+            .serve_at(OBJECT_PATH, player)?
+            .build()?;
+        log::info!("Registered MPRIS player {}", BUS_NAME);
+        Ok(Self {
+            connection,
+            commands,
+            status,
+        })
+    }
+
+    pub fn poll_command(&self) -> Option<MprisCommand> {
+        self.commands.try_recv().ok()
+    }
+
+    /// Update what clients see, and notify them of the change.
+    pub fn update(&self, track: Option<Track>, paused: bool) {
+        let (playback_status, metadata) = {
+            let mut status = self.status.lock().unwrap();
+            status.track = track;
+            status.paused = paused;
+            (status.playback_status(), status.metadata())
+        };
+        let changed = HashMap::from([
+            ("PlaybackStatus", Value::from(playback_status)),
+            ("Metadata", Value::from(metadata)),
+        ]);
+        let body = (PLAYER_INTERFACE, changed, Vec::<&str>::new());
+        if let Err(e) = self.connection.emit_signal(
+            None::<&str>,
+            OBJECT_PATH,
+            "org.freedesktop.DBus.Properties",
+            "PropertiesChanged",
+            &body,
+        ) {
+            log::warn!("Failed to notify MPRIS clients: {}", e);
+        }
+    }
+}
@@ -15,6 +15,8 @@ use std::num::IntErrorKind;
 
 use clap::Parser;
 
+use crate::playlist::SortKey;
+
 /// The default sample rate.
 ///
 /// libopenmpt recommends 48000 because
@@ -27,11 +29,25 @@ pub const MIN_SAMPLE_RATE: usize = 8000;
 /// Maximum sample rate supported by libopenmpt.
 pub const MAX_SAMPLE_RATE: usize = 192000;
 
+/// The default of `--min-size`.  No real module is this small.
+pub const DEFAULT_MIN_SIZE: u64 = 64;
+
+/// The file the playlist is saved to if `--playlist` is not given.
+pub const DEFAULT_PLAYLIST_FILE: &str = "tuimodplayer.m3u";
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BackendKind {
+    /// Play through the sound device with cpal.
+    Cpal,
+    /// Play to no device.
+    Null,
+}
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 pub struct Options {
     /// Paths to individual mods, archives or directories.
-    /// For archives and directories, it will search for mod files inside.
+    /// For archives (zip, tar, tar.gz, 7z) and directories, it will search for mod files inside.
     #[arg(name = "PATH")]
     pub paths: Vec<String>,
 
@@ -42,6 +58,31 @@ pub struct Options {
     )]
     pub sample_rate: usize,
 
+    /// The backend to play through.  `null` decodes the modules without playing them, e.g. on
+    /// machines without sound devices.  Without this option, `null` is used if there is no
+    /// output device.
+    #[arg(long, value_enum)]
+    pub backend: Option<BackendKind>,
+
+    /// The audio host (sound system) to play through, e.g. `ALSA` or `JACK`, instead of the
+    /// default one.  Only the hosts built into the program are available.
+    #[arg(long, value_name = "NAME")]
+    pub audio_host: Option<String>,
+
+    /// Play through the output device whose name contains this, ignoring case, instead of the
+    /// default one.
+    #[arg(long, value_name = "NAME")]
+    pub device: Option<String>,
+
+    /// Ask the device for buffers of this many frames.  Larger buffers may stop crackling on a
+    /// loaded machine, at the cost of latency.  The default size is used if the device rejects it.
+    #[arg(long, value_name = "N")]
+    pub buffer_frames: Option<u32>,
+
+    /// Print the output devices and their supported sample rates, and exit.
+    #[arg(long)]
+    pub list_devices: bool,
+
     /// If set, the player will search for modules in nested archives.
     ///
     /// By default, it will only search one level in each archive in the filesystem,
@@ -49,9 +90,125 @@ pub struct Options {
     #[arg(short = 'd', long)]
     pub deep_archive_search: bool,
 
+    /// Skip files and directories matching the glob, e.g. "*.bak" or "__MACOSX".  Can be given
+    /// multiple times.
+    ///
+    /// A glob matches if it matches either the whole path or the file name.  Entries in archives
+    /// are skipped if any component of their paths matches.
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Only add modules matching the glob, e.g. "*.xm".  Can be given multiple times, and a
+    /// module is added if any of them matches.
+    ///
+    /// Like `--exclude`, a glob matches either the whole path or the file name.  Directories and
+    /// archives are still searched.
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Do not search deeper than this many levels of directories below each path.  1 only
+    /// searches the files directly in the directory.
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Follow symbolic links when searching directories.  Files reachable through several links
+    /// are only added once.
+    #[arg(long)]
+    pub follow_symlinks: bool,
+
+    /// Skip module files smaller than this many bytes.  Such files are usually placeholders or
+    /// broken downloads.  0 adds files of any size.
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MIN_SIZE)]
+    pub min_size: u64,
+
     /// Shuffle the playlist on startup.
     #[arg(short = 's', long)]
     pub shuffle: bool,
+
+    /// Shuffle the playlist on startup with the given seed, so that the order is the same each
+    /// time.  Implies `--shuffle`.
+    #[arg(long, value_name = "N")]
+    pub shuffle_seed: Option<u64>,
+
+    /// Sort the playlist on startup.  Overrides `--shuffle`.
+    #[arg(long, value_enum)]
+    pub sort: Option<SortKey>,
+
+    /// The colors of the UI.  Either a TOML theme file or the name of a preset: `light` or `mono`.
+    #[arg(long, value_name = "FILE")]
+    pub theme: Option<String>,
+
+    /// The key binding file.  Defaults to `tuimodplayer/keys.toml` in the configuration directory,
+    /// e.g. `~/.config/tuimodplayer/keys.toml`.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<String>,
+
+    /// Load the playlist from an M3U file instead of scanning.
+    ///
+    /// The playlist is also saved to this file when pressing Ctrl-S.
+    #[arg(long, value_name = "FILE")]
+    pub playlist: Option<String>,
+
+    /// The file to export the playlist to when pressing `w`.
+    ///
+    /// Defaults to `tuimodplayer.m3u` next to the first PATH.
+    #[arg(long, value_name = "FILE")]
+    pub playlist_out: Option<String>,
+
+    /// Continue from the first module after the last one, even if the last session was set to
+    /// stop at the end or to repeat one module.
+    #[arg(long)]
+    pub loop_playlist: bool,
+
+    /// Pause the playback after this many minutes.
+    #[arg(long, value_name = "MINUTES")]
+    pub sleep: Option<u64>,
+
+    /// Quit instead of pausing when the sleep timer expires.
+    #[arg(long)]
+    pub sleep_quit: bool,
+
+    /// Render all modules in the playlist into this WAV file instead of playing them.
+    ///
+    /// The sample rate is `--sample-rate`.  Each module is rendered once.
+    #[arg(long, value_name = "FILE.wav")]
+    pub render_out: Option<String>,
+
+    /// Render each module in the playlist into its own WAV file in this directory instead of
+    /// playing them.
+    ///
+    /// The files are named after the modules, e.g. `song.mod.wav`.  Modules that fail to open
+    /// are skipped and listed at the end, and the program exits with an error.
+    #[arg(long, value_name = "DIR", conflicts_with = "render_out")]
+    pub render_to: Option<String>,
+
+    /// Write the title of the playing module to this file whenever a module starts, e.g. for
+    /// streaming overlays.  The file is emptied when the playback ends.
+    #[arg(long, value_name = "PATH")]
+    pub now_playing_file: Option<String>,
+
+    /// Do not register as an MPRIS player on D-Bus.  Only has effect if built with the `mpris`
+    /// feature.
+    #[arg(long)]
+    pub no_mpris: bool,
+
+    /// Show the title of the playing module in the title bar of the terminal.
+    #[arg(long)]
+    pub set_title: bool,
+
+    /// Play without the text user interface, logging to stderr, until the playlist is exhausted
+    /// or Ctrl-C is pressed.
+    #[arg(long)]
+    pub no_ui: bool,
+
+    /// Do not resume the last session even if it was started with the same paths.
+    #[arg(long)]
+    pub no_resume: bool,
+
+    /// Start with the default tempo, pitch, gain and other control settings instead of those of
+    /// the last session.
+    #[arg(long)]
+    pub no_restore: bool,
 }
 
 enum RangeParseError {
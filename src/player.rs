@@ -28,8 +28,17 @@ pub struct ModuleInfo {
     pub title: String,
     pub n_orders: usize,
     pub n_patterns: usize,
+    /// Instrument names, or sample names if there are no instruments.
     pub message: Vec<String>,
     pub message_width: usize,
+    /// The song message, i.e. the comment text embedded in the module.
+    pub song_message: Vec<String>,
+    pub song_message_width: usize,
+    /// Duration in seconds.  Some modules report zero or negative values.
+    pub duration: f64,
+    pub n_subsongs: usize,
+    /// The index of the selected subsong.  libopenmpt selects the first one when loading.
+    pub subsong: usize,
 }
 
 impl ModuleInfo {
@@ -53,33 +62,81 @@ impl ModuleInfo {
             }
         };
         let message_width = message.iter().map(|s| screen_width(s)).max().unwrap_or(0);
+        let song_message = module
+            .get_metadata(MetadataKey::Message)
+            .map(|text| text.lines().map(str::to_string).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let song_message_width = song_message
+            .iter()
+            .map(|s| screen_width(s))
+            .max()
+            .unwrap_or(0);
+        let duration = module.get_duration_seconds();
+        let n_subsongs = module.get_num_subsongs() as usize;
         Self {
             title,
             n_orders,
             n_patterns,
             message,
             message_width,
+            song_message,
+            song_message_width,
+            duration,
+            n_subsongs,
+            subsong: 0,
         }
     }
 }
 
-#[derive(Default, Clone, Copy)]
+/// VU levels are only recorded for this many channels.  Enough for IT.
+pub const MAX_VU_CHANNELS: usize = 64;
+
+#[derive(Clone, Copy)]
 pub struct MomentState {
     pub order: usize,
     pub pattern: usize,
     pub row: usize,
     pub speed: usize,
     pub tempo: usize,
+    /// Position in seconds.
+    pub position: f64,
+    /// The number of channels of the module.  May exceed `MAX_VU_CHANNELS`.
+    pub n_channels: usize,
+    /// The VU level of each channel.  Usually within 0.0 to 1.0, but may exceed 1.0.
+    pub channel_vu: [f32; MAX_VU_CHANNELS],
+}
+
+impl Default for MomentState {
+    fn default() -> Self {
+        Self {
+            order: 0,
+            pattern: 0,
+            row: 0,
+            speed: 0,
+            tempo: 0,
+            position: 0.0,
+            n_channels: 0,
+            channel_vu: [0.0; MAX_VU_CHANNELS],
+        }
+    }
 }
 
 impl MomentState {
     pub fn from_module(module: &mut Module) -> Self {
+        let n_channels = module.get_num_channels() as usize;
+        let mut channel_vu = [0.0; MAX_VU_CHANNELS];
+        for (i, vu) in channel_vu.iter_mut().take(n_channels).enumerate() {
+            *vu = module.get_current_channel_vu_mono(i as i32);
+        }
         Self {
             order: module.get_current_order() as _,
             pattern: module.get_current_pattern() as _,
             row: module.get_current_row() as _,
             speed: module.get_current_speed() as _,
             tempo: module.get_current_tempo() as _,
+            position: module.get_position_seconds(),
+            n_channels,
+            channel_vu,
         }
     }
 }
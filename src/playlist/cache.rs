@@ -0,0 +1,186 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::{Context, Result};
+
+use super::{item::duration_from_seconds, ModMetadata, ModPath};
+
+/// Metadata of modules scanned in earlier runs, so that unchanged files need not be opened again.
+///
+/// Entries are keyed by `ModPath::display_full_name`.  An entry is only used if the size and the
+/// modification time of the file on the disk (the archive, for archived modules) are unchanged.
+///
+/// The cache file has one entry per line, with tab-separated fields:
+/// `name size mtime duration format title`.  The duration is in seconds.  An empty duration or
+/// title means it is unknown.
+#[derive(Default)]
+pub struct MetadataCache {
+    entries: HashMap<String, CacheEntry>,
+}
+
+struct CacheEntry {
+    stamp: FileStamp,
+    metadata: ModMetadata,
+}
+
+/// The size and the modification time (in seconds since the epoch) of a file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    size: u64,
+    mtime: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let mtime = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_secs();
+        Some(Self {
+            size: metadata.len(),
+            mtime,
+        })
+    }
+}
+
+fn cache_file_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("tuimodplayer").join("metadata"))
+}
+
+impl MetadataCache {
+    /// Load the cache saved by earlier runs.  Start with an empty cache if it is missing or
+    /// unreadable.
+    pub fn load() -> Self {
+        let Some(path) = cache_file_path() else {
+            return Self::default();
+        };
+        if !path.is_file() {
+            return Self::default();
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(content) => {
+                let cache = Self::parse(&content);
+                log::debug!("Loaded {} cached metadata entries", cache.entries.len());
+                cache
+            }
+            Err(e) => {
+                log::warn!(
+                    "Cannot read the metadata cache {}: {}",
+                    path.to_string_lossy(),
+                    e
+                );
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = cache_file_path().context("Cannot determine the cache directory")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, self.to_string())?;
+        log::debug!("Saved metadata cache to {}", path.to_string_lossy());
+        Ok(())
+    }
+
+    /// Skip malformed lines so that a corrupt cache only costs rescanning those files.
+    fn parse(content: &str) -> Self {
+        let mut cache = Self::default();
+        for line in content.lines() {
+            match parse_entry(line) {
+                Some((name, entry)) => {
+                    cache.entries.insert(name, entry);
+                }
+                None => log::debug!("Skip malformed metadata cache line: {}", line),
+            }
+        }
+        cache
+    }
+
+    /// The cached metadata of the module, if its file is unchanged since it was cached.
+    pub fn get(&self, mod_path: &ModPath) -> Option<ModMetadata> {
+        let entry = self.entries.get(&mod_path.display_full_name())?;
+        let stamp = FileStamp::of(Path::new(&mod_path.file_path))?;
+        (entry.stamp == stamp).then(|| entry.metadata.clone())
+    }
+
+    pub fn insert(&mut self, mod_path: &ModPath, metadata: ModMetadata) {
+        let name = mod_path.display_full_name();
+        // Such names cannot be written in the cache file.
+        if name.contains(['\t', '\n']) {
+            return;
+        }
+        if let Some(stamp) = FileStamp::of(Path::new(&mod_path.file_path)) {
+            self.entries.insert(name, CacheEntry { stamp, metadata });
+        }
+    }
+}
+
+fn parse_entry(line: &str) -> Option<(String, CacheEntry)> {
+    let mut fields = line.splitn(6, '\t');
+    let name = fields.next()?.to_string();
+    let size = fields.next()?.parse().ok()?;
+    let mtime = fields.next()?.parse().ok()?;
+    let duration = match fields.next()? {
+        "" => None,
+        seconds => Some(duration_from_seconds(seconds.parse().ok()?)?),
+    };
+    let format = fields.next()?.to_string();
+    let title = fields.next()?;
+    let entry = CacheEntry {
+        stamp: FileStamp { size, mtime },
+        metadata: ModMetadata {
+            title: (!title.is_empty()).then(|| title.to_string()),
+            duration,
+            format,
+        },
+    };
+    Some((name, entry))
+}
+
+/// Replace characters that would break the line format.
+fn sanitize_field(field: &str) -> String {
+    field.replace(['\t', '\n', '\r'], " ")
+}
+
+impl std::fmt::Display for MetadataCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, entry) in self.entries.iter() {
+            let metadata = &entry.metadata;
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                name,
+                entry.stamp.size,
+                entry.stamp.mtime,
+                metadata
+                    .duration
+                    .map(|duration| duration.as_secs_f64().to_string())
+                    .unwrap_or_default(),
+                sanitize_field(&metadata.format),
+                sanitize_field(metadata.title.as_deref().unwrap_or_default()),
+            )?;
+        }
+        Ok(())
+    }
+}
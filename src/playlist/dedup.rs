@@ -0,0 +1,71 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+use crate::module_file::hash_module_file;
+
+use super::{ModPath, PlayList};
+
+/// Items to hash before updating the playlist.  The lock is taken once per batch.
+const BATCH_SIZE: usize = 64;
+
+/// Hash the content of the items in the playlist and mark those with the same content as an
+/// earlier item as duplicates, until all items are hashed or `stop` is set.
+///
+/// Items hashed in an earlier run are not hashed again.  The playlist is not locked while
+/// reading files.
+pub fn find_duplicates(playlist: Arc<Mutex<PlayList>>, stop: Arc<AtomicBool>) {
+    let pending: Vec<(usize, ModPath)> = {
+        let playlist = playlist.lock().unwrap();
+        playlist
+            .items
+            .iter()
+            .filter(|item| item.content_hash.is_none())
+            .map(|item| (item.id, item.mod_path.clone()))
+            .collect()
+    };
+    log::info!("Hashing {} items to find duplicates...", pending.len());
+
+    for batch in pending.chunks(BATCH_SIZE) {
+        let mut hashed = Vec::with_capacity(batch.len());
+        for (id, mod_path) in batch {
+            if stop.load(Ordering::SeqCst) {
+                log::info!("Stopped finding duplicates.");
+                return;
+            }
+            match hash_module_file(mod_path) {
+                Ok(content_hash) => hashed.push((*id, content_hash)),
+                Err(e) => {
+                    log::debug!("Cannot hash {}: {}", mod_path.display_full_name(), e);
+                }
+            }
+        }
+
+        let mut playlist = playlist.lock().unwrap();
+        for (id, content_hash) in hashed {
+            playlist.set_content_hash(id, content_hash);
+        }
+        playlist.mark_duplicates();
+        // Items may now match `dup:`.
+        if let Some(filter_string) = playlist.get_filter_string() {
+            playlist.update_filter(filter_string);
+        }
+    }
+
+    let n_duplicates = playlist.lock().unwrap().mark_duplicates();
+    log::info!("Found {} duplicates.", n_duplicates);
+}
@@ -0,0 +1,106 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use anyhow::{Context, Result};
+
+use super::{ModPath, PlayListItem};
+
+/// The modules starred by the user, kept across sessions.
+///
+/// Entries are keyed by `ModPath::display_full_name`, one per line.  Like the play counts, the
+/// file is kept in the data directory because it cannot be recreated.
+#[derive(Default)]
+pub struct Favorites {
+    names: HashSet<String>,
+    dirty: bool,
+}
+
+fn favorites_file_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("tuimodplayer").join("favorites"))
+}
+
+impl Favorites {
+    /// Load the favorites saved by earlier runs.  Start afresh if the file is missing or
+    /// unreadable.
+    pub fn load() -> Self {
+        let Some(path) = favorites_file_path() else {
+            return Self::default();
+        };
+        if !path.is_file() {
+            return Self::default();
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(content) => {
+                let names = content
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect::<HashSet<_>>();
+                log::debug!("Loaded {} favorites", names.len());
+                Self {
+                    names,
+                    dirty: false,
+                }
+            }
+            Err(e) => {
+                log::warn!(
+                    "Cannot read the favorites {}: {}",
+                    path.to_string_lossy(),
+                    e
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// Save the favorites if any was added or removed since they were loaded.
+    pub fn save(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let path = favorites_file_path().context("Cannot determine the data directory")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut names = self.names.iter().map(String::as_str).collect::<Vec<_>>();
+        names.sort_unstable();
+        let mut content = names.join("\n");
+        content.push('\n');
+        std::fs::write(&path, content)?;
+        self.dirty = false;
+        log::debug!("Saved favorites to {}", path.to_string_lossy());
+        Ok(())
+    }
+
+    /// Star a newly added item if it was starred before.
+    pub fn apply_to(&self, item: &mut PlayListItem) {
+        item.favorite = self.names.contains(&item.mod_path.display_full_name());
+    }
+
+    /// Star or unstar the module.
+    pub fn set(&mut self, mod_path: &ModPath, favorite: bool) {
+        let name = mod_path.display_full_name();
+        // Such names cannot be written in the file.
+        if name.contains('\n') {
+            return;
+        }
+        let changed = if favorite {
+            self.names.insert(name)
+        } else {
+            self.names.remove(&name)
+        };
+        self.dirty |= changed;
+    }
+}
@@ -0,0 +1,339 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::{ops::Range, path::Path};
+
+use anyhow::Result;
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use regex::{Regex, RegexBuilder};
+
+use crate::util::IsSomeAnd;
+
+use super::{ModPath, PlayListItem};
+
+/// How the text of the filter is matched against the name and the title of each item.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum FilterMode {
+    #[default]
+    Substring,
+    Regex,
+    Fuzzy,
+}
+
+impl FilterMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Substring => Self::Regex,
+            Self::Regex => Self::Fuzzy,
+            Self::Fuzzy => Self::Substring,
+        }
+    }
+}
+
+/// Decides which items are shown in the filtered view of the playlist.
+///
+/// Terms like `ext:mod,s3m` restrict the module formats, and terms like `dir:purple_motion`
+/// restrict the directories.  The term `dup:` only shows the items marked as duplicates, the term
+/// `fav:` only shows the starred items, and the terms `played:` and `unplayed:` only show the
+/// items played or never played before.  The rest of the filter string is matched against the
+/// name and the title of each item.  An item must pass all of them.
+pub struct Filter {
+    /// Lower-case extensions without the dot.  Any extension is allowed if empty.
+    extensions: Vec<String>,
+    /// Lower-case directory names.  Each must be a directory the item is in, either on the disk
+    /// or inside an archive.
+    directories: Vec<String>,
+    duplicates_only: bool,
+    favorites_only: bool,
+    /// If set, whether the items must have been played before.
+    played: Option<bool>,
+    text: TextFilter,
+}
+
+enum TextFilter {
+    /// The text split into whitespace-separated terms.  An item matches if its name or title
+    /// contains all positive terms (case-insensitively) and none of the negative terms, i.e.
+    /// those starting with `-`.  A filter string of a single term is never negative, so that
+    /// `-remix` still finds "-remix".
+    Terms {
+        include: Vec<String>,
+        exclude: Vec<String>,
+        /// Finds the positive terms in the text, in order to highlight them.
+        highlight: Option<Regex>,
+    },
+    /// A case-insensitive regular expression.  An item matches if its name or title does.
+    Regex(Regex),
+    /// Subsequence matching with scores, like fzf.
+    Fuzzy {
+        pattern: String,
+        matcher: Box<SkimMatcherV2>,
+    },
+}
+
+impl Filter {
+    /// Fail if the text is not a valid regular expression in `FilterMode::Regex`.
+    pub fn parse(filter_string: &str, mode: FilterMode) -> Result<Self> {
+        let mut extensions = vec![];
+        let mut directories = vec![];
+        let mut duplicates_only = false;
+        let mut favorites_only = false;
+        let mut played = None;
+        let mut text_terms = vec![];
+        for term in filter_string.split_whitespace() {
+            if let Some(exts) = term.strip_prefix("ext:") {
+                extensions.extend(
+                    exts.split(',')
+                        .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                        .filter(|ext| !ext.is_empty()),
+                );
+            } else if term == "dup:" {
+                duplicates_only = true;
+            } else if term == "fav:" {
+                favorites_only = true;
+            } else if term == "played:" {
+                played = Some(true);
+            } else if term == "unplayed:" {
+                played = Some(false);
+            } else if let Some(dir) = term.strip_prefix("dir:") {
+                if !dir.is_empty() {
+                    directories.push(dir.to_lowercase());
+                }
+            } else {
+                text_terms.push(term);
+            }
+        }
+
+        let text = match mode {
+            FilterMode::Fuzzy => TextFilter::Fuzzy {
+                pattern: text_terms.join(" "),
+                matcher: Box::new(SkimMatcherV2::default().ignore_case()),
+            },
+            FilterMode::Regex => TextFilter::Regex(
+                RegexBuilder::new(&text_terms.join(" "))
+                    .case_insensitive(true)
+                    .build()?,
+            ),
+            FilterMode::Substring => {
+                let mut include = vec![];
+                let mut exclude = vec![];
+                let negation_allowed = filter_string.split_whitespace().nth(1).is_some();
+                for term in text_terms {
+                    let term = term.to_lowercase();
+                    match term.strip_prefix('-').filter(|_| negation_allowed) {
+                        // A lone `-` is probably the start of a negative term being typed.
+                        Some("") => {}
+                        Some(negated) => exclude.push(negated.to_string()),
+                        None => include.push(term),
+                    }
+                }
+                let highlight = (!include.is_empty()).then(|| {
+                    let alternatives = include.iter().map(|term| regex::escape(term));
+                    RegexBuilder::new(&alternatives.collect::<Vec<_>>().join("|"))
+                        .case_insensitive(true)
+                        .build()
+                        .expect("escaped terms should form a valid regex")
+                });
+                TextFilter::Terms {
+                    include,
+                    exclude,
+                    highlight,
+                }
+            }
+        };
+
+        Ok(Self {
+            extensions,
+            directories,
+            duplicates_only,
+            favorites_only,
+            played,
+            text,
+        })
+    }
+
+    /// A filter that lets every item pass.
+    pub fn match_all() -> Self {
+        Self {
+            extensions: vec![],
+            directories: vec![],
+            duplicates_only: false,
+            favorites_only: false,
+            played: None,
+            text: TextFilter::Terms {
+                include: vec![],
+                exclude: vec![],
+                highlight: None,
+            },
+        }
+    }
+
+    pub fn is_fuzzy(&self) -> bool {
+        matches!(self.text, TextFilter::Fuzzy { .. })
+    }
+
+    /// Return `None` if the item does not match.  Otherwise return a score.  Higher scores mean
+    /// better matches.  Scores are all zero unless the filter is fuzzy.
+    pub fn score(&self, item: &PlayListItem) -> Option<i64> {
+        if self.duplicates_only && !item.duplicate {
+            return None;
+        }
+        if self.favorites_only && !item.favorite {
+            return None;
+        }
+        if self
+            .played
+            .is_some_and2(|played| *played != (item.play_count > 0))
+        {
+            return None;
+        }
+        if !self.extensions.is_empty() && !self.extensions.contains(&item.mod_path.extension()) {
+            return None;
+        }
+        if !self.directories.is_empty() {
+            let item_directories = directory_names(&item.mod_path);
+            if !self
+                .directories
+                .iter()
+                .all(|dir| item_directories.contains(dir))
+            {
+                return None;
+            }
+        }
+
+        let name = item.mod_path.display_name();
+        let title = item
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.title.as_ref());
+        match &self.text {
+            TextFilter::Terms {
+                include, exclude, ..
+            } => {
+                let name = name.to_lowercase();
+                let title = title.map(|title| title.to_lowercase());
+                let contains = |term: &String| {
+                    name.contains(term.as_str())
+                        || title.as_ref().is_some_and2(|t| t.contains(term.as_str()))
+                };
+                (include.iter().all(contains) && !exclude.iter().any(contains)).then_some(0)
+            }
+            TextFilter::Regex(regex) => {
+                (regex.is_match(&name) || title.is_some_and2(|t| regex.is_match(t))).then_some(0)
+            }
+            TextFilter::Fuzzy { pattern, matcher } => {
+                let name_score = matcher.fuzzy_match(&name, pattern);
+                let title_score = title.and_then(|title| matcher.fuzzy_match(title, pattern));
+                name_score.max(title_score)
+            }
+        }
+    }
+
+    /// The byte ranges of the parts of `text` matched by the text of the filter, in order, so
+    /// that they can be highlighted.
+    pub fn match_ranges(&self, text: &str) -> Vec<Range<usize>> {
+        let regex = match &self.text {
+            TextFilter::Terms { highlight, .. } => highlight.as_ref(),
+            TextFilter::Regex(regex) => Some(regex),
+            TextFilter::Fuzzy { pattern, matcher } => {
+                let Some((_, indices)) = matcher.fuzzy_indices(text, pattern) else {
+                    return vec![];
+                };
+                // The indices are of characters rather than bytes.
+                return text
+                    .char_indices()
+                    .enumerate()
+                    .filter(|(char_index, _)| indices.contains(char_index))
+                    .map(|(_, (start, ch))| start..start + ch.len_utf8())
+                    .collect();
+            }
+        };
+        regex.map_or(vec![], |regex| {
+            regex
+                .find_iter(text)
+                .map(|m| m.range())
+                .filter(|range| !range.is_empty())
+                .collect()
+        })
+    }
+}
+
+/// The lower-case names of the directories containing the file of `mod_path`, and of the
+/// directories inside its archives.
+fn directory_names(mod_path: &ModPath) -> Vec<String> {
+    let paths = std::iter::once(Path::new(&mod_path.file_path))
+        .chain(mod_path.archive_paths.iter().map(Path::new));
+    paths
+        .filter_map(|path| path.parent())
+        .flat_map(|dir| dir.iter())
+        .map(|component| component.to_string_lossy().to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(file_path: &str, archive_paths: &[&str], is_archived_single: bool) -> PlayListItem {
+        PlayListItem::new(ModPath {
+            root_path: "mods".into(),
+            file_path: file_path.into(),
+            archive_paths: archive_paths.iter().map(|path| path.to_string()).collect(),
+            is_archived_single,
+        })
+    }
+
+    fn matches(filter_string: &str, item: &PlayListItem) -> bool {
+        Filter::parse(filter_string, FilterMode::Substring)
+            .unwrap()
+            .score(item)
+            .is_some()
+    }
+
+    #[test]
+    fn ext_matches_file_extension() {
+        let song = item("mods/song.MOD", &[], false);
+        assert!(matches("ext:mod", &song));
+        assert!(matches("ext:s3m,.mod", &song));
+        assert!(!matches("ext:xm", &song));
+    }
+
+    #[test]
+    fn ext_matches_name_in_archive() {
+        let song = item("mods/pack.zip", &["dir/song.s3m"], false);
+        assert!(matches("ext:s3m", &song));
+        assert!(!matches("ext:zip", &song));
+    }
+
+    #[test]
+    fn ext_matches_name_in_nested_archive() {
+        let song = item("mods/pack.zip", &["inner.tar", "song.it"], false);
+        assert!(matches("ext:it", &song));
+        assert!(!matches("ext:tar", &song));
+    }
+
+    #[test]
+    fn ext_matches_archived_single_by_second_extension() {
+        let song = item("mods/pack.zip", &["song.xm.zip"], true);
+        assert!(matches("ext:xm", &song));
+        assert!(!matches("ext:zip", &song));
+    }
+
+    #[test]
+    fn ext_and_text_terms_must_both_match() {
+        let song = item("mods/pack.zip", &["chip_song.mod"], false);
+        assert!(matches("ext:mod chip", &song));
+        assert!(matches("chip ext:mod,xm", &song));
+        assert!(!matches("ext:xm chip", &song));
+        assert!(!matches("ext:mod cover", &song));
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::{collections::HashSet, path::Path};
+
+use super::{ModPath, PlayListItem};
+
+/// A row of the playlist when it is shown grouped by directory.
+pub enum GroupRow {
+    /// The header of consecutive items in the same directory.
+    Header {
+        dir: String,
+        /// The view index of the first item in the group.
+        first: usize,
+        n_items: usize,
+        collapsed: bool,
+    },
+    /// An item, by its view index.
+    Item(usize),
+}
+
+/// Maps the rows shown in the grouped view to the items in the view of the playlist.
+pub struct GroupedView {
+    pub rows: Vec<GroupRow>,
+    /// For each view index, the row showing the item.  Items in collapsed groups are shown by the
+    /// header of the group.
+    pub row_of_item: Vec<usize>,
+}
+
+impl GroupedView {
+    /// Group the items, given in the order of the view.  Only consecutive items in the same
+    /// directory form a group, so a directory may have several groups if the playlist is sorted or
+    /// shuffled.
+    pub fn build<'a>(
+        items: impl Iterator<Item = &'a PlayListItem>,
+        collapsed_groups: &HashSet<String>,
+    ) -> Self {
+        let mut rows = vec![];
+        let mut row_of_item = vec![];
+        // The directory, the row of the header and whether it is collapsed.
+        let mut current_group: Option<(String, usize, bool)> = None;
+
+        for (view_index, item) in items.enumerate() {
+            let dir = group_dir(&item.mod_path);
+            if current_group
+                .as_ref()
+                .map(|(current_dir, _, _)| current_dir)
+                != Some(&dir)
+            {
+                let collapsed = collapsed_groups.contains(&dir);
+                rows.push(GroupRow::Header {
+                    dir: dir.clone(),
+                    first: view_index,
+                    n_items: 0,
+                    collapsed,
+                });
+                current_group = Some((dir, rows.len() - 1, collapsed));
+            }
+
+            let (_, header_row, collapsed) = current_group.as_ref().unwrap();
+            let (header_row, collapsed) = (*header_row, *collapsed);
+            if let GroupRow::Header { n_items, .. } = &mut rows[header_row] {
+                *n_items += 1;
+            }
+            if collapsed {
+                row_of_item.push(header_row);
+            } else {
+                rows.push(GroupRow::Item(view_index));
+                row_of_item.push(rows.len() - 1);
+            }
+        }
+
+        Self { rows, row_of_item }
+    }
+
+    /// The rows the cursor can be on, with the view index of the item each row selects.  The
+    /// header of a collapsed group selects the first item in it.  Other headers are skipped.
+    pub fn selectable_rows(&self) -> Vec<(usize, usize)> {
+        self.rows
+            .iter()
+            .enumerate()
+            .filter_map(|(row, group_row)| match group_row {
+                GroupRow::Item(view_index) => Some((row, *view_index)),
+                GroupRow::Header {
+                    first,
+                    collapsed: true,
+                    ..
+                } => Some((row, *first)),
+                GroupRow::Header { .. } => None,
+            })
+            .collect()
+    }
+}
+
+/// The directory an item is grouped by, i.e. the directory containing its file, or the archive
+/// it is in.  For an entry in an archive, this is the archive followed by the directory of the
+/// entry inside it, if any, in the form of `ModPath::display_full_name`.
+pub fn group_dir(mod_path: &ModPath) -> String {
+    if let Some((entry, archives)) = mod_path.archive_paths.split_last() {
+        let mut dir = mod_path.file_path.to_string_lossy().into_owned();
+        let entry_dir = Path::new(entry)
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty());
+        for component in archives
+            .iter()
+            .map(String::as_str)
+            .chain(entry_dir.map(|parent| parent.to_str().unwrap_or_default()))
+        {
+            dir.push(':');
+            dir.push_str(component);
+        }
+        return dir;
+    }
+
+    let dir = Path::new(&mod_path.file_path)
+        .parent()
+        .unwrap_or(Path::new(""));
+    if dir.as_os_str().is_empty() {
+        ".".to_string()
+    } else {
+        dir.to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mod_path(file_path: &str, archive_paths: &[&str]) -> ModPath {
+        ModPath {
+            root_path: "mods".into(),
+            file_path: file_path.into(),
+            archive_paths: archive_paths.iter().map(|path| path.to_string()).collect(),
+            is_archived_single: false,
+        }
+    }
+
+    #[test]
+    fn group_dir_of_file() {
+        assert_eq!(group_dir(&mod_path("mods/a/song.mod", &[])), "mods/a");
+        assert_eq!(group_dir(&mod_path("song.mod", &[])), ".");
+    }
+
+    #[test]
+    fn group_dir_of_entry_in_archive() {
+        assert_eq!(
+            group_dir(&mod_path("mods/pack.zip", &["song.mod"])),
+            "mods/pack.zip"
+        );
+        assert_eq!(
+            group_dir(&mod_path("mods/pack.zip", &["dir/sub/song.mod"])),
+            "mods/pack.zip:dir/sub"
+        );
+    }
+
+    #[test]
+    fn group_dir_of_entry_in_nested_archive() {
+        assert_eq!(
+            group_dir(&mod_path("mods/pack.zip", &["inner.tar", "dir/song.mod"])),
+            "mods/pack.zip:inner.tar:dir"
+        );
+    }
+
+    #[test]
+    fn archives_in_same_directory_form_separate_groups() {
+        let items = [
+            mod_path("mods/a.zip", &["1.mod"]),
+            mod_path("mods/a.zip", &["2.mod"]),
+            mod_path("mods/b.zip", &["1.mod"]),
+            mod_path("mods/c.mod", &[]),
+        ]
+        .map(PlayListItem::new);
+        let grouped_view = GroupedView::build(items.iter(), &HashSet::new());
+        let headers = grouped_view
+            .rows
+            .iter()
+            .filter_map(|row| match row {
+                GroupRow::Header { dir, n_items, .. } => Some((dir.as_str(), *n_items)),
+                GroupRow::Item(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(headers, [("mods/a.zip", 2), ("mods/b.zip", 1), ("mods", 1)]);
+        assert_eq!(grouped_view.row_of_item, [1, 2, 4, 6]);
+    }
+}
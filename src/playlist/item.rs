@@ -11,7 +11,23 @@
 // You should have received a copy of the GNU General Public License along with TUIModPlayer. If
 // not, see <https://www.gnu.org/licenses/>.
 
-use std::{ffi::OsString, path::Path};
+use std::{
+    ffi::{OsStr, OsString},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use openmpt::module::{metadata::MetadataKey, Module};
+
+use crate::{archive::strip_archive_suffix, util::IsSomeAnd};
+
+use super::loading::extension_is_archive;
+
+/// The prefix of `ModPath::to_uri` for modules inside archives.
+const ARCHIVE_URI_SCHEME: &str = "zip://";
+
+/// Separates the archive file and the paths inside it in `ModPath::to_uri`.
+const ARCHIVE_URI_SEPARATOR: char = '!';
 
 #[derive(Clone)]
 pub struct ModPath {
@@ -43,13 +59,181 @@ impl ModPath {
             format!("{}:{}", file_path, self.archive_paths.join(":"))
         }
     }
+
+    /// The lower-case extension that identifies the module format.
+    ///
+    /// For archived singles like `song.mod.zip`, this is the extension before the archive
+    /// extension.
+    pub fn extension(&self) -> String {
+        let name = self.display_name();
+        let mod_name = if self.is_archived_single {
+            strip_archive_suffix(&name).unwrap_or(&name)
+        } else {
+            &name
+        };
+        Path::new(mod_name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    }
+
+    /// Parse a string in the form returned by `display_full_name`.
+    ///
+    /// Colons only separate path components after an archive, so a colon inside an ordinary
+    /// file name is kept.  If the last component is itself an archive, it is an archived single.
+    pub fn from_full_name(root_path: &OsStr, full_name: &str) -> Self {
+        let mut components = vec![];
+        let mut current: Option<String> = None;
+        for segment in full_name.split(':') {
+            let joined = match current.take() {
+                None => segment.to_string(),
+                Some(mut s) => {
+                    s.push(':');
+                    s.push_str(segment);
+                    s
+                }
+            };
+            if extension_is_archive(Path::new(&joined)) {
+                components.push(joined);
+            } else {
+                current = Some(joined);
+            }
+        }
+        components.extend(current);
+
+        let mut components = components.into_iter();
+        let file_path = components.next().unwrap_or_default();
+        let archive_paths = components.collect::<Vec<_>>();
+        let is_archived_single = archive_paths
+            .last()
+            .is_some_and2(|last| extension_is_archive(Path::new(last)));
+
+        Self {
+            root_path: root_path.into(),
+            file_path: file_path.into(),
+            archive_paths,
+            is_archived_single,
+        }
+    }
+
+    /// A URI-like form used in playlist files.
+    ///
+    /// Files in the file system are written as is.  Modules inside archives are written as
+    /// `zip://archive.zip!inner.zip!song.mod`, which, unlike `display_full_name`, can be parsed
+    /// back without guessing where the archive path ends.
+    pub fn to_uri(&self) -> String {
+        let file_path = self.file_path.to_string_lossy();
+        if self.archive_paths.is_empty() {
+            file_path.to_string()
+        } else {
+            let mut uri = format!("{}{}", ARCHIVE_URI_SCHEME, file_path);
+            for archive_path in self.archive_paths.iter() {
+                uri.push(ARCHIVE_URI_SEPARATOR);
+                uri.push_str(archive_path);
+            }
+            uri
+        }
+    }
+
+    /// Parse a string returned by `to_uri`.
+    ///
+    /// Strings without the `zip://` prefix are parsed with `from_full_name`.
+    pub fn from_uri(root_path: &OsStr, uri: &str) -> Self {
+        let Some(rest) = uri.strip_prefix(ARCHIVE_URI_SCHEME) else {
+            return Self::from_full_name(root_path, uri);
+        };
+
+        let mut components = rest.split(ARCHIVE_URI_SEPARATOR).map(str::to_string);
+        let file_path = components.next().unwrap_or_default();
+        let archive_paths = components.collect::<Vec<_>>();
+        let is_archived_single = archive_paths
+            .last()
+            .is_some_and2(|last| extension_is_archive(Path::new(last)));
+
+        Self {
+            root_path: root_path.into(),
+            file_path: file_path.into(),
+            archive_paths,
+            is_archived_single,
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct ModMetadata {
-    pub title: String,
+    /// `None` if the module has no title.
+    pub title: Option<String>,
+    /// `None` if the module reports zero or negative duration.
+    pub duration: Option<Duration>,
+    /// The format reported by libopenmpt, e.g. `xm`.
+    pub format: String,
+}
+
+impl ModMetadata {
+    /// Read the metadata of an opened module.
+    pub fn from_module(module: &mut Module) -> Self {
+        let title = module
+            .get_metadata(MetadataKey::ModuleTitle)
+            .filter(|title| !title.trim().is_empty());
+        let duration = duration_from_seconds(module.get_duration_seconds());
+        let format = module
+            .get_metadata(MetadataKey::TypeExt)
+            .unwrap_or_default();
+        Self {
+            title,
+            duration,
+            format,
+        }
+    }
+}
+
+/// Convert a duration reported by libopenmpt.  Return `None` if it is not positive.
+pub fn duration_from_seconds(seconds: f64) -> Option<Duration> {
+    if seconds > 0.0 {
+        Duration::try_from_secs_f64(seconds).ok()
+    } else {
+        None
+    }
 }
 
 pub struct PlayListItem {
+    /// Unique in the playlist.  Assigned by `PlayList::add_item`.
+    pub id: usize,
     pub mod_path: ModPath,
     pub metadata: Option<ModMetadata>,
+    /// True if the module failed to open.  Such items are skipped when advancing.
+    pub failed: bool,
+    /// The hash of the file content.  Only known after looking for duplicates.
+    pub content_hash: Option<u64>,
+    /// True if an item added earlier has the same content.
+    pub duplicate: bool,
+    /// How many times the module has been played, including earlier sessions.
+    pub play_count: u32,
+    pub last_played: Option<SystemTime>,
+    /// Starred by the user, in this or earlier sessions.
+    pub favorite: bool,
+}
+
+impl PlayListItem {
+    pub fn new(mod_path: ModPath) -> Self {
+        Self {
+            id: 0,
+            mod_path,
+            metadata: None,
+            failed: false,
+            content_hash: None,
+            duplicate: false,
+            play_count: 0,
+            last_played: None,
+            favorite: false,
+        }
+    }
+
+    /// The title of the module if known, or the file name otherwise.
+    pub fn title(&self) -> String {
+        self.metadata
+            .as_ref()
+            .and_then(|metadata| metadata.title.clone())
+            .unwrap_or_else(|| self.mod_path.display_name())
+    }
 }
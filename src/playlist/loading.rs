@@ -15,21 +15,27 @@ use anyhow::Result;
 use lazy_static::lazy_static;
 
 use std::{
-    collections::HashSet,
+    collections::{HashSet, VecDeque},
     ffi::{OsStr, OsString},
     fs::File,
-    io::{BufReader, Cursor, Read, Seek},
-    path::Path,
+    io::{BufRead, BufReader, Cursor, Read, Seek},
+    path::{Path, PathBuf},
+    sync::{mpsc, Mutex},
 };
-use zip::read::ZipFile;
 
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use walkdir::WalkDir;
 
+use crate::archive::{read_file_content, strip_archive_suffix, ArchiveKind};
+use crate::options::Options;
 use crate::playlist::PlayListItem;
 use crate::util::IsSomeAnd;
 
 use super::{ModPath, PlayList};
 
+/// At most this many threads open archives while a directory is walked.
+const MAX_ARCHIVE_WORKERS: usize = 4;
+
 pub const SUPPORTED_EXTENSIONS: &[&str] = &[
     "mptm", "mod", "s3m", "xm", "it", "669", "amf", "ams", "c67", "dbm", "digi", "dmf", "dsm",
     "dsym", "dtm", "far", "fmt", "imf", "ice", "j2b", "m15", "mdl", "med", "mms", "mt2", "mtm",
@@ -50,56 +56,308 @@ fn is_supported_mod(ext: &OsStr) -> bool {
     SUPPORTED_EXTENSIONS_OSSTR.contains(&ext.to_ascii_lowercase())
 }
 
-fn is_supported_archive(ext: &OsStr) -> bool {
-    ext.to_ascii_lowercase() == "zip"
-}
-
-fn get_stem_path(path: &Path) -> Option<&Path> {
-    path.file_stem().map(Path::new)
-}
-
 pub fn extension_is_supported(path: &Path) -> bool {
     path.extension().is_some_and2(|e| is_supported_mod(e))
 }
 
+/// True for archived singles like `song.mod.zip`.
 pub fn extension2_is_supported(path: &Path) -> bool {
-    get_stem_path(path).is_some_and2(|stem_path| extension_is_supported(stem_path))
+    path.file_name()
+        .map(|name| name.to_string_lossy())
+        .is_some_and2(|name| {
+            strip_archive_suffix(name).is_some_and2(|stem| extension_is_supported(Path::new(stem)))
+        })
 }
 
 pub fn extension_is_archive(path: &Path) -> bool {
-    path.extension().is_some_and2(|e| is_supported_archive(e))
+    ArchiveKind::from_path(path).is_some()
 }
 
-pub fn load_from_path(playlist: &mut PlayList, root_path: &str, deep_archive_search: bool) {
-    let mut loader = RecursiveModuleLoader::new(deep_archive_search, |mod_path| {
-        playlist.add_item(PlayListItem {
-            mod_path,
-            metadata: None,
-        })
+/// How to search paths for modules.
+#[derive(Clone)]
+pub struct ScanOptions {
+    /// If false, the loader will not look into nested archives.
+    /// Instead, it will use filename heuristics to identify archives of single module.
+    pub deep_archive_search: bool,
+    /// Globs of files, directories and archive entries to skip.
+    pub exclude: Vec<String>,
+    /// Globs of modules to add.  All modules are added if empty.
+    pub include: Vec<String>,
+    /// How many levels of directories to descend into.  Unlimited if `None`.
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: bool,
+    /// Modules smaller than this many bytes are skipped.  0 disables the check.
+    pub min_size: u64,
+}
+
+impl ScanOptions {
+    pub fn from_options(options: &Options) -> Self {
+        Self {
+            deep_archive_search: options.deep_archive_search,
+            exclude: options.exclude.clone(),
+            include: options.include.clone(),
+            max_depth: options.max_depth,
+            follow_symlinks: options.follow_symlinks,
+            min_size: options.min_size,
+        }
+    }
+}
+
+pub fn load_from_path(playlist: &mut PlayList, root_path: &str, scan_options: &ScanOptions) {
+    scan_path(root_path, scan_options, |mod_path| {
+        playlist.add_item(PlayListItem::new(mod_path))
     });
+}
+
+/// Call `sink` with each module found in the path, in a deterministic order.
+pub fn scan_path(root_path: &str, scan_options: &ScanOptions, sink: impl FnMut(ModPath)) {
+    let mut loader = RecursiveModuleLoader::new(scan_options, sink);
 
     let time1 = std::time::Instant::now();
     loader.load_from_root_path(Path::new(root_path));
     let duration = time1.elapsed();
-    log::debug!("It took {}ms to open {}", duration.as_millis(), root_path);
+    log::debug!(
+        "It took {}ms to scan {} files and open {} archives in {}",
+        duration.as_millis(),
+        loader.n_files,
+        loader.n_archives,
+        root_path
+    );
+    if loader.n_excluded > 0 {
+        log::debug!(
+            "Excluded {} files, directories or archive entries in {}",
+            loader.n_excluded,
+            root_path
+        );
+    }
+    if loader.n_not_included > 0 {
+        log::debug!(
+            "Skipped {} modules not matching --include in {}",
+            loader.n_not_included,
+            root_path
+        );
+    }
+    if loader.n_too_small > 0 {
+        log::info!(
+            "Skipped {} modules smaller than {} bytes in {}",
+            loader.n_too_small,
+            loader.options.min_size,
+            root_path
+        );
+    }
+    if loader.n_duplicates > 0 {
+        log::debug!(
+            "Skipped {} files reached again through symbolic links in {}",
+            loader.n_duplicates,
+            root_path
+        );
+    }
 }
 
-struct RecursiveModuleLoader<F: FnMut(ModPath)> {
+/// Compile the `--exclude` or `--include` globs.  Invalid globs are reported and ignored.
+fn build_glob_set(patterns: &[String], kind: &str) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        match Glob::new(pattern) {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(e) => log::error!("Invalid {} pattern {:?}: {}", kind, pattern, e),
+        }
+    }
+    builder.build().unwrap_or_else(|e| {
+        log::error!("Cannot compile {} patterns: {}", kind, e);
+        GlobSet::empty()
+    })
+}
+
+/// Append the entries of an M3U file (or a plain text file with one path per line) to the
+/// playlist.  Returns the number of entries added.
+///
+/// Entries are parsed with `ModPath::from_uri`.  Relative paths are resolved against the
+/// directory containing the M3U file.  Lines starting with `#` are skipped, and so are entries
+/// whose files no longer exist.
+pub fn load_from_m3u(playlist: &mut PlayList, path: &Path) -> Result<usize> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut n_added = 0;
+    let mut n_missing = 0;
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut mod_path = ModPath::from_uri(path.as_os_str(), line);
+        if Path::new(&mod_path.file_path).is_relative() {
+            mod_path.file_path = base_dir.join(&mod_path.file_path).into();
+        }
+
+        if !Path::new(&mod_path.file_path).is_file() {
+            log::warn!("File no longer exists: {}", mod_path.display_full_name());
+            n_missing += 1;
+            continue;
+        }
+
+        playlist.add_item(PlayListItem::new(mod_path));
+        n_added += 1;
+    }
+
+    log::info!(
+        "Loaded {} items from {} ({} missing)",
+        n_added,
+        path.to_string_lossy(),
+        n_missing
+    );
+
+    Ok(n_added)
+}
+
+/// The `ScanOptions` that also apply inside archives, with the globs compiled.  Cloned for each
+/// archive worker.
+#[derive(Clone)]
+struct ArchiveScanOptions {
     /// If false, the loader will not look into nested archives.
     /// Instead, it will use filename heuristics to identify archives of single module.
     deep_archive_search: bool,
+    /// Files, directories and archive entries to skip.
+    exclude: GlobSet,
+    /// Modules to add.  Everything is added if empty.
+    include: GlobSet,
+    /// Modules smaller than this are skipped unless it is 0.
+    min_size: u64,
+}
+
+impl ArchiveScanOptions {
+    fn new(scan_options: &ScanOptions) -> Self {
+        Self {
+            deep_archive_search: scan_options.deep_archive_search,
+            exclude: build_glob_set(&scan_options.exclude, "exclude"),
+            include: build_glob_set(&scan_options.include, "include"),
+            min_size: scan_options.min_size,
+        }
+    }
+}
+
+struct RecursiveModuleLoader<F: FnMut(ModPath)> {
+    options: ArchiveScanOptions,
+    /// The number of entries skipped because of `exclude`.
+    n_excluded: usize,
+    /// The number of modules skipped because of `include`.
+    n_not_included: usize,
+    max_depth: Option<usize>,
+    follow_symlinks: bool,
+    /// Canonical paths of the files found when following symbolic links, so that a file
+    /// reachable through several links is only visited once.
+    visited_files: HashSet<PathBuf>,
+    /// The number of files skipped because they were visited before.
+    n_duplicates: usize,
+    /// The number of modules skipped because of `min_size`.
+    n_too_small: usize,
+    /// The number of files found in directories.
+    n_files: usize,
+    /// The number of archives opened, including nested ones.
+    n_archives: usize,
     /// Call-back function to visit each generated `ModPath`.
     sink: F,
 }
 
 impl<F: FnMut(ModPath)> RecursiveModuleLoader<F> {
-    pub fn new(deep_archive_search: bool, sink: F) -> Self {
+    pub fn new(scan_options: &ScanOptions, sink: F) -> Self {
         Self {
-            deep_archive_search,
+            options: ArchiveScanOptions::new(scan_options),
+            n_excluded: 0,
+            n_not_included: 0,
+            max_depth: scan_options.max_depth,
+            follow_symlinks: scan_options.follow_symlinks,
+            visited_files: HashSet::new(),
+            n_duplicates: 0,
+            n_too_small: 0,
+            n_files: 0,
+            n_archives: 0,
             sink,
         }
     }
 
+    /// A loader for a worker thread that only looks into archives.
+    fn for_archive_worker(options: ArchiveScanOptions, sink: F) -> Self {
+        Self {
+            options,
+            n_excluded: 0,
+            n_not_included: 0,
+            max_depth: None,
+            follow_symlinks: false,
+            visited_files: HashSet::new(),
+            n_duplicates: 0,
+            n_too_small: 0,
+            n_files: 0,
+            n_archives: 0,
+            sink,
+        }
+    }
+
+    /// Return false if the file was visited before through another symbolic link.
+    fn visit_file_once(&mut self, path: &Path) -> bool {
+        if !self.follow_symlinks {
+            // Without following links, WalkDir never yields a file twice.
+            return true;
+        }
+        let Ok(canonical_path) = path.canonicalize() else {
+            return true;
+        };
+        if self.visited_files.insert(canonical_path) {
+            true
+        } else {
+            self.n_duplicates += 1;
+            false
+        }
+    }
+
+    /// Return false if the module is smaller than `min_size`.  `get_size` is only called if the
+    /// check is enabled, so that no `stat` is needed otherwise.  Files whose sizes cannot be read
+    /// are kept.
+    fn is_large_enough(&mut self, get_size: impl FnOnce() -> Option<u64>) -> bool {
+        if self.options.min_size == 0 {
+            return true;
+        }
+        if get_size().is_some_and2(|size| *size < self.options.min_size) {
+            self.n_too_small += 1;
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Return false if `include` is given and none of its globs matches the module.
+    fn is_included(&mut self, path: &Path) -> bool {
+        if self.options.include.is_empty() || Self::glob_matches(&self.options.include, path) {
+            true
+        } else {
+            self.n_not_included += 1;
+            false
+        }
+    }
+
+    /// True if a glob in the set matches the whole path or the file name.
+    fn glob_matches(globs: &GlobSet, path: &Path) -> bool {
+        !globs.is_empty()
+            && (globs.is_match(path) || path.file_name().is_some_and2(|name| globs.is_match(name)))
+    }
+
+    /// True if an exclude glob matches the name of an archive entry or any directory it is in.
+    fn is_excluded_in_archive(&self, name: &str) -> bool {
+        let name_path = Path::new(name);
+        Self::glob_matches(&self.options.exclude, name_path)
+            || name_path.ancestors().skip(1).any(|dir| {
+                dir.file_name()
+                    .is_some_and2(|dir_name| self.options.exclude.is_match(dir_name))
+            })
+    }
+
     pub fn load_from_root_path(&mut self, root_path: &Path) {
         if root_path.is_file() {
             self.load_from_file(root_path, root_path);
@@ -117,7 +375,7 @@ impl<F: FnMut(ModPath)> RecursiveModuleLoader<F> {
 
         if extension_is_archive(path) {
             self.load_from_fs_archive_file(root_path, path);
-        } else {
+        } else if self.is_included(path) && self.is_large_enough(|| file_size(path)) {
             (self.sink)(ModPath {
                 root_path: root_path.into(),
                 file_path: path.into(),
@@ -128,6 +386,9 @@ impl<F: FnMut(ModPath)> RecursiveModuleLoader<F> {
     }
 
     pub fn load_from_fs_archive_file(&mut self, root_path: &Path, path: &Path) {
+        let Some(kind) = ArchiveKind::from_path(path) else {
+            return;
+        };
         match buf_open(path) {
             Ok(buf_reader) => {
                 let template = ModPath {
@@ -136,7 +397,7 @@ impl<F: FnMut(ModPath)> RecursiveModuleLoader<F> {
                     archive_paths: Vec::new(),
                     is_archived_single: false,
                 };
-                self.load_from_archive(template, buf_reader);
+                self.load_from_archive(template, kind, buf_reader);
             }
             Err(e) => {
                 log::debug!("Skip unopenable archive file: {:?} Error: {}", path, e);
@@ -144,51 +405,61 @@ impl<F: FnMut(ModPath)> RecursiveModuleLoader<F> {
         }
     }
 
-    pub fn load_from_archive(&mut self, template: ModPath, file: impl Read + Seek) {
-        match zip::ZipArchive::new(file) {
-            Ok(ref mut zip) => {
-                for i in 0..zip.len() {
-                    match zip.by_index(i) {
-                        Ok(zip_file) => {
-                            self.load_from_file_in_archive(&template, zip_file);
-                        }
-                        Err(e) => {
-                            log::debug!(
-                                "Skip zip entry: {}:{} Error: {}",
-                                template.display_full_name(),
-                                i,
-                                e
-                            );
-                        }
-                    }
+    pub fn load_from_archive(
+        &mut self,
+        template: ModPath,
+        kind: ArchiveKind,
+        file: impl Read + Seek,
+    ) {
+        self.n_archives += 1;
+        let result = if self.options.deep_archive_search {
+            kind.for_each_file(file, |name, size, content| {
+                self.load_from_file_in_archive(&template, name, size, content);
+            })
+        } else {
+            // The content is only needed for searching nested archives.
+            kind.list_files(file).map(|files| {
+                for (name, size) in files {
+                    self.load_from_file_in_archive(&template, name, size, &mut std::io::empty());
                 }
-            }
-            Err(e) => {
-                log::debug!(
-                    "Skip invalid zip: {} Error: {}",
-                    template.display_full_name(),
-                    e
-                );
-            }
+            })
+        };
+        if let Err(e) = result {
+            log::debug!(
+                "Skip invalid archive: {} Error: {}",
+                template.display_full_name(),
+                e
+            );
         }
     }
 
-    pub fn load_from_file_in_archive(&mut self, template: &ModPath, mut zip_file: ZipFile) {
-        let name = zip_file.name().to_string();
+    pub fn load_from_file_in_archive(
+        &mut self,
+        template: &ModPath,
+        name: String,
+        size: u64,
+        content: &mut dyn Read,
+    ) {
+        if self.is_excluded_in_archive(&name) {
+            self.n_excluded += 1;
+            return;
+        }
         let name_path = Path::new(&name);
         if extension_is_supported(name_path) {
+            if !self.is_included(name_path) || !self.is_large_enough(|| Some(size)) {
+                return;
+            }
             let mut mod_path = template.clone();
             mod_path.archive_paths.push(name);
             (self.sink)(mod_path);
-        } else if extension_is_archive(name_path) {
-            if self.deep_archive_search {
+        } else if let Some(kind) = ArchiveKind::from_path(name_path) {
+            if self.options.deep_archive_search {
                 let mut sub_template = template.clone();
                 sub_template.archive_paths.push(name.clone());
-                let mut content = Vec::new();
-                match zip_file.read_to_end(&mut content) {
-                    Ok(_) => {
-                        let cursor = Cursor::new(content);
-                        self.load_from_archive(sub_template, cursor);
+                match read_file_content(content, size) {
+                    Ok(buffer) => {
+                        let cursor = Cursor::new(buffer);
+                        self.load_from_archive(sub_template, kind, cursor);
                     }
                     Err(e) => {
                         log::debug!(
@@ -199,7 +470,7 @@ impl<F: FnMut(ModPath)> RecursiveModuleLoader<F> {
                         );
                     }
                 }
-            } else if extension2_is_supported(name_path) {
+            } else if extension2_is_supported(name_path) && self.is_included(name_path) {
                 let mut mod_path = template.clone();
                 mod_path.archive_paths.push(name);
                 mod_path.is_archived_single = true;
@@ -207,37 +478,326 @@ impl<F: FnMut(ModPath)> RecursiveModuleLoader<F> {
             }
         } else {
             log::debug!(
-                "Unrecognised zip content: {}:{}",
+                "Unrecognised archive content: {}:{}",
                 template.display_full_name(),
                 name
             );
         }
     }
 
+    /// Walk the directory in order, while worker threads open the archives found.  The modules
+    /// are passed to the sink in the order of the walk as soon as the archives before them are
+    /// opened.
     pub fn load_from_dir(&mut self, root_path: &Path, dir_path: &Path) {
         debug_assert!(dir_path.is_dir()); // Really? What about TOC-TOU?
 
-        WalkDir::new(dir_path)
-            .into_iter()
-            .filter_map(|r| r.ok())
-            .for_each(|de| {
-                let file_path = de.path();
+        let n_workers = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(MAX_ARCHIVE_WORKERS);
+        let options = self.options.clone();
+
+        let mut pending = PendingResults::default();
+        let (job_sender, job_receiver) = mpsc::channel::<(usize, PathBuf)>();
+        let job_receiver = Mutex::new(job_receiver);
+        let (result_sender, result_receiver) = mpsc::channel::<(usize, ArchiveScanResult)>();
+
+        std::thread::scope(|scope| {
+            for _ in 0..n_workers {
+                let result_sender = result_sender.clone();
+                let job_receiver = &job_receiver;
+                let options = &options;
+                scope.spawn(move || loop {
+                    // Release the lock before opening the archive.
+                    let job = job_receiver.lock().unwrap().recv();
+                    let Ok((index, path)) = job else {
+                        break;
+                    };
+                    let result = scan_archive(options.clone(), root_path, &path);
+                    if result_sender.send((index, result)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(result_sender);
+
+            self.walk_dir(dir_path, |this, file_path| {
                 if extension_is_supported(file_path) {
-                    (self.sink)(ModPath {
+                    if !this.is_included(file_path)
+                        || !this.is_large_enough(|| file_size(file_path))
+                    {
+                        return;
+                    }
+                    pending.push(Some(vec![ModPath {
                         root_path: root_path.into(),
                         file_path: file_path.into(),
                         archive_paths: vec![],
                         is_archived_single: false,
-                    })
+                    }]));
                 } else if extension_is_archive(file_path) {
-                    self.load_from_fs_archive_file(root_path, file_path)
+                    // The workers only stop when the sender is dropped.
+                    let _ = job_sender.send((pending.next_index(), file_path.to_path_buf()));
+                    pending.push(None);
+                }
+                for (index, result) in result_receiver.try_iter() {
+                    this.accept_archive_result(&mut pending, index, result);
+                }
+                this.emit_ready(&mut pending);
+            });
+            drop(job_sender);
+
+            for (index, result) in result_receiver {
+                self.accept_archive_result(&mut pending, index, result);
+                self.emit_ready(&mut pending);
+            }
+        });
+    }
+
+    fn accept_archive_result(
+        &mut self,
+        pending: &mut PendingResults,
+        index: usize,
+        result: ArchiveScanResult,
+    ) {
+        self.n_excluded += result.n_excluded;
+        self.n_not_included += result.n_not_included;
+        self.n_too_small += result.n_too_small;
+        self.n_archives += result.n_archives;
+        pending.set(index, result.mod_paths);
+    }
+
+    /// Pass the modules that are no longer behind unopened archives to the sink.
+    fn emit_ready(&mut self, pending: &mut PendingResults) {
+        while let Some(mod_paths) = pending.pop_ready() {
+            for mod_path in mod_paths {
+                (self.sink)(mod_path);
+            }
+        }
+    }
+
+    /// Call `visit` with each file under `dir_path`, skipping excluded ones and files visited
+    /// before.
+    fn walk_dir(&mut self, dir_path: &Path, mut visit: impl FnMut(&mut Self, &Path)) {
+        // Filter entries before visiting them so that excluded directories are not descended into.
+        let exclude = self.options.exclude.clone();
+        let mut n_excluded = 0;
+        let mut walk_dir = WalkDir::new(dir_path).follow_links(self.follow_symlinks);
+        if let Some(max_depth) = self.max_depth {
+            walk_dir = walk_dir.max_depth(max_depth);
+        }
+        walk_dir
+            .into_iter()
+            .filter_entry(|de| {
+                let excluded = de.depth() > 0 && Self::glob_matches(&exclude, de.path());
+                if excluded {
+                    n_excluded += 1;
+                }
+                !excluded
+            })
+            .filter_map(|r| match r {
+                Ok(de) => Some(de),
+                Err(e) => {
+                    // Including symbolic link loops.
+                    log::debug!("Skip unreadable directory entry: {}", e);
+                    None
                 }
             })
+            .for_each(|de| {
+                let file_path = de.path();
+                if !self.visit_file_once(file_path) {
+                    return;
+                }
+                if de.file_type().is_file() {
+                    self.n_files += 1;
+                }
+                visit(self, file_path);
+            });
+        self.n_excluded += n_excluded;
     }
 }
 
+/// The modules found from each file in a directory walk, waiting to be passed to the sink in the
+/// order of the walk.
+#[derive(Default)]
+struct PendingResults {
+    /// `None` for archives not opened yet.
+    queue: VecDeque<Option<Vec<ModPath>>>,
+    /// The index of the front of `queue` in the walk.
+    front_index: usize,
+}
+
+impl PendingResults {
+    fn next_index(&self) -> usize {
+        self.front_index + self.queue.len()
+    }
+
+    fn push(&mut self, mod_paths: Option<Vec<ModPath>>) {
+        self.queue.push_back(mod_paths);
+    }
+
+    fn set(&mut self, index: usize, mod_paths: Vec<ModPath>) {
+        self.queue[index - self.front_index] = Some(mod_paths);
+    }
+
+    /// Take the modules from the front, unless the front is an archive not opened yet.
+    fn pop_ready(&mut self) -> Option<Vec<ModPath>> {
+        if !self.queue.front().is_some_and2(|front| front.is_some()) {
+            return None;
+        }
+        self.front_index += 1;
+        self.queue.pop_front().flatten()
+    }
+}
+
+/// What a worker thread found in an archive.
+struct ArchiveScanResult {
+    mod_paths: Vec<ModPath>,
+    n_excluded: usize,
+    n_not_included: usize,
+    n_too_small: usize,
+    n_archives: usize,
+}
+
+fn scan_archive(options: ArchiveScanOptions, root_path: &Path, path: &Path) -> ArchiveScanResult {
+    let mut mod_paths = vec![];
+    let mut loader =
+        RecursiveModuleLoader::for_archive_worker(options, |mod_path| mod_paths.push(mod_path));
+    loader.load_from_fs_archive_file(root_path, path);
+    let n_excluded = loader.n_excluded;
+    let n_not_included = loader.n_not_included;
+    let n_too_small = loader.n_too_small;
+    let n_archives = loader.n_archives;
+    ArchiveScanResult {
+        mod_paths,
+        n_excluded,
+        n_not_included,
+        n_too_small,
+        n_archives,
+    }
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|metadata| metadata.len())
+}
+
 fn buf_open(path: &Path) -> Result<BufReader<File>> {
     let file = File::open(path)?;
     let buf_reader = BufReader::new(file);
     Ok(buf_reader)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// A directory under the system temporary directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "tuimodplayer-test-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        /// Create a file and the directories containing it.
+        fn create_file(&self, relative_path: &str) -> PathBuf {
+            let path = self.0.join(relative_path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, b"not really a module").unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn scan_options(exclude: &[&str]) -> ScanOptions {
+        ScanOptions {
+            deep_archive_search: false,
+            exclude: exclude.iter().map(|glob| glob.to_string()).collect(),
+            include: vec![],
+            max_depth: None,
+            follow_symlinks: false,
+            min_size: 0,
+        }
+    }
+
+    /// Load from `root` and return the full names of the modules found relative to `root`, and
+    /// the loader's numbers of excluded entries and of files visited.
+    fn load(root: &Path, scan_options: &ScanOptions) -> (Vec<String>, usize, usize) {
+        let mut names = vec![];
+        let mut loader = RecursiveModuleLoader::new(scan_options, |mod_path: ModPath| {
+            let relative_path = Path::new(&mod_path.file_path).strip_prefix(root).unwrap();
+            let mut name = relative_path.to_string_lossy().replace('\\', "/");
+            for archive_path in mod_path.archive_paths.iter() {
+                name.push(':');
+                name.push_str(archive_path);
+            }
+            names.push(name);
+        });
+        loader.load_from_root_path(root);
+        let (n_excluded, n_files) = (loader.n_excluded, loader.n_files);
+        names.sort();
+        (names, n_excluded, n_files)
+    }
+
+    #[test]
+    fn exclude_prunes_whole_directories() {
+        let dir = TempDir::new("exclude-dirs");
+        dir.create_file("a.mod");
+        dir.create_file("keep/d.s3m");
+        dir.create_file("__MACOSX/b.mod");
+        dir.create_file("__MACOSX/sub/c.mod");
+        dir.create_file("keep/__MACOSX/e.xm");
+
+        let (names, n_excluded, n_files) = load(&dir.0, &scan_options(&["__MACOSX"]));
+        assert_eq!(names, ["a.mod", "keep/d.s3m"]);
+        // Each excluded directory counts once, and the files in it are never visited.
+        assert_eq!(n_excluded, 2);
+        assert_eq!(n_files, 2);
+    }
+
+    #[test]
+    fn exclude_matches_file_names_and_whole_paths() {
+        let dir = TempDir::new("exclude-files");
+        dir.create_file("a.mod");
+        dir.create_file("b.mod.bak");
+        dir.create_file("sub/c.mod");
+        let pattern = format!("{}/sub/*", dir.0.to_string_lossy());
+
+        let (names, n_excluded, _) = load(&dir.0, &scan_options(&["*.bak", &pattern]));
+        assert_eq!(names, ["a.mod"]);
+        assert_eq!(n_excluded, 2);
+    }
+
+    #[test]
+    fn exclude_applies_to_entries_in_archives() {
+        let dir = TempDir::new("exclude-archive");
+        let mut zip = zip::ZipWriter::new(File::create(dir.0.join("pack.zip")).unwrap());
+        for name in [
+            "song.mod",
+            "__MACOSX/._song.mod",
+            "old/tune.s3m",
+            "x.mod.bak",
+        ] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(b"not really a module").unwrap();
+        }
+        zip.finish().unwrap();
+
+        let (names, n_excluded, _) = load(&dir.0, &scan_options(&["__MACOSX", "*.bak"]));
+        assert_eq!(names, ["pack.zip:old/tune.s3m", "pack.zip:song.mod"]);
+        assert_eq!(n_excluded, 2);
+    }
+}
@@ -11,10 +11,25 @@
 // You should have received a copy of the GNU General Public License along with TUIModPlayer. If
 // not, see <https://www.gnu.org/licenses/>.
 
+mod cache;
+mod dedup;
+mod favorites;
+mod filter;
+mod grouping;
 mod item;
 mod loading;
+mod persist;
+mod play_stats;
 mod playing;
+mod scanning;
 
-pub use item::{ModPath, PlayListItem};
-pub use loading::load_from_path;
-pub use playing::{PlayList, PlayListModuleProvider};
+pub use dedup::find_duplicates;
+pub use favorites::Favorites;
+pub use filter::FilterMode;
+pub use grouping::GroupRow;
+pub use item::{ModMetadata, ModPath, PlayListItem};
+pub use loading::{load_from_m3u, load_from_path, scan_path, ScanOptions};
+pub use persist::{save_entries_to_m3u, save_to_m3u};
+pub use play_stats::PlayStats;
+pub use playing::{ListOrder, PlayList, PlayListModuleProvider, SortKey};
+pub use scanning::scan_metadata;
@@ -0,0 +1,71 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+
+use super::{ModPath, PlayList};
+
+const M3U_HEADER: &str = "#EXTM3U";
+const M3U_EXTINF: &str = "#EXTINF:";
+
+/// Write all items to an extended M3U file.
+///
+/// Entries inside zip archives are written in the form of `ModPath::to_uri`,
+/// i.e. `zip://archive.zip!inner/path.mod`.
+pub fn save_to_m3u(playlist: &PlayList, path: &Path) -> Result<()> {
+    save_entries_to_m3u(
+        playlist
+            .items
+            .iter()
+            .map(|item| (item.title(), &item.mod_path)),
+        path,
+    )
+}
+
+/// Like `save_to_m3u`, but write the given titles and paths instead of a whole playlist.
+pub fn save_entries_to_m3u<'a>(
+    entries: impl IntoIterator<Item = (String, &'a ModPath)>,
+    path: &Path,
+) -> Result<()> {
+    let file = File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    writeln!(writer, "{}", M3U_HEADER)?;
+    for (title, mod_path) in entries {
+        writeln!(writer, "{}-1,{}", M3U_EXTINF, title)?;
+        writeln!(writer, "{}", absolute_uri(mod_path)?)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Like `ModPath::to_uri`, but make the file path absolute so that the M3U file can be moved.
+fn absolute_uri(mod_path: &ModPath) -> Result<String> {
+    let file_path = Path::new(&mod_path.file_path);
+    if file_path.is_absolute() {
+        Ok(mod_path.to_uri())
+    } else {
+        let absolute = ModPath {
+            file_path: std::env::current_dir()?.join(file_path).into(),
+            ..mod_path.clone()
+        };
+        Ok(absolute.to_uri())
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+
+use super::{ModPath, PlayListItem};
+
+/// How many times each module has been played, and when it was last played, across sessions.
+///
+/// Entries are keyed by `ModPath::display_full_name`.  Like the favorites, the file is kept in the
+/// data directory because the listening history cannot be recreated.  It has one entry per line
+/// with tab-separated fields: `name count last_played`.  `last_played` is in seconds since the
+/// epoch.
+#[derive(Default)]
+pub struct PlayStats {
+    entries: HashMap<String, PlayStat>,
+    dirty: bool,
+}
+
+#[derive(Clone, Copy)]
+struct PlayStat {
+    count: u32,
+    last_played: u64,
+}
+
+fn play_stats_file_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("tuimodplayer").join("play_stats"))
+}
+
+impl PlayStats {
+    /// Load the statistics saved by earlier runs.  Start afresh if the file is missing or
+    /// unreadable.
+    pub fn load() -> Self {
+        let Some(path) = play_stats_file_path() else {
+            return Self::default();
+        };
+        if !path.is_file() {
+            return Self::default();
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(content) => {
+                let play_stats = Self::parse(&content);
+                log::debug!("Loaded play counts of {} modules", play_stats.entries.len());
+                play_stats
+            }
+            Err(e) => {
+                log::warn!(
+                    "Cannot read the play counts {}: {}",
+                    path.to_string_lossy(),
+                    e
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// Save the statistics if anything was played since they were loaded.
+    pub fn save(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let path = play_stats_file_path().context("Cannot determine the data directory")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, self.to_string())?;
+        self.dirty = false;
+        log::debug!("Saved play counts to {}", path.to_string_lossy());
+        Ok(())
+    }
+
+    /// Skip malformed lines so that a corrupt file only loses the counts on those lines.
+    fn parse(content: &str) -> Self {
+        let mut play_stats = Self::default();
+        for line in content.lines() {
+            let mut fields = line.split('\t');
+            let entry = (|| {
+                let name = fields.next()?.to_string();
+                let count = fields.next()?.parse().ok()?;
+                let last_played = fields.next()?.parse().ok()?;
+                Some((name, PlayStat { count, last_played }))
+            })();
+            match entry {
+                Some((name, stat)) => {
+                    play_stats.entries.insert(name, stat);
+                }
+                None => log::debug!("Skip malformed play count line: {}", line),
+            }
+        }
+        play_stats
+    }
+
+    /// Fill in the play count and the time last played of a newly added item.
+    pub fn apply_to(&self, item: &mut PlayListItem) {
+        if let Some(stat) = self.entries.get(&item.mod_path.display_full_name()) {
+            item.play_count = stat.count;
+            item.last_played = Some(UNIX_EPOCH + Duration::from_secs(stat.last_played));
+        }
+    }
+
+    /// Count one more play of the module now.
+    pub fn record_played(&mut self, mod_path: &ModPath, time: SystemTime) {
+        let name = mod_path.display_full_name();
+        // Such names cannot be written in the file.
+        if name.contains(['\t', '\n']) {
+            return;
+        }
+        let last_played = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        let stat = self.entries.entry(name).or_insert(PlayStat {
+            count: 0,
+            last_played,
+        });
+        stat.count += 1;
+        stat.last_played = last_played;
+        self.dirty = true;
+    }
+}
+
+impl std::fmt::Display for PlayStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, stat) in self.entries.iter() {
+            writeln!(f, "{}\t{}\t{}", name, stat.count, stat.last_played)?;
+        }
+        Ok(())
+    }
+}
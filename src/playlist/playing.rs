@@ -11,24 +11,149 @@
 // You should have received a copy of the GNU General Public License along with TUIModPlayer. If
 // not, see <https://www.gnu.org/licenses/>.
 
+use anyhow::Result;
 use openmpt::module::Module;
-use rand::prelude::SliceRandom;
-use std::sync::{Arc, Mutex};
+use rand::{prelude::SliceRandom, Rng};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
 
 use crate::{
-    backend::ModuleProvider,
+    backend::{ModuleProvider, PrefetchedModule},
     module_file::open_module_from_mod_path,
     util::{add_modulo_unsigned, sub_modulo_unsigned, IsSomeAnd},
 };
 
-use super::PlayListItem;
+use super::{
+    filter::{Filter, FilterMode},
+    grouping::{group_dir, GroupedView},
+    Favorites, ModMetadata, ModPath, PlayListItem, PlayStats,
+};
+
+/// The maximum number of entries in the playback history.
+const HISTORY_CAPACITY: usize = 1000;
+
+/// The number of recent failures to open modules kept to show in the UI.
+const RECENT_FAILURES_CAPACITY: usize = 5;
+
+/// In random mode, do not pick any of this many recently played items again.  For small lists,
+/// only half of the items are avoided.
+const RANDOM_AVOID_RECENT: usize = 100;
+
+/// The key to sort the playlist by.
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortKey {
+    /// The file name, or the name in the archive.
+    Name,
+    /// The title of the module.  Only known after the module has been opened.  The file name is
+    /// used for other modules.
+    Title,
+    /// The full path, including paths in archives.
+    Path,
+    /// The extension, i.e. the module format.
+    Ext,
+    /// The duration of the module.  Only known after the module has been opened.  Modules of
+    /// unknown durations are placed last.
+    Duration,
+    /// The number of times the module has been played.  The least played ones, and among them
+    /// the ones played longest ago, are placed first.
+    PlayCount,
+}
+
+impl SortKey {
+    /// The key after this one when cycling through keys in the UI.
+    /// `None` means going back to the original order.
+    pub fn next(self) -> Option<Self> {
+        match self {
+            SortKey::Name => Some(SortKey::Title),
+            SortKey::Title => Some(SortKey::Path),
+            SortKey::Path => Some(SortKey::Ext),
+            SortKey::Ext => Some(SortKey::Duration),
+            SortKey::Duration => Some(SortKey::PlayCount),
+            SortKey::PlayCount => None,
+        }
+    }
+}
+
+impl std::fmt::Display for SortKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SortKey::Name => "name",
+            SortKey::Title => "title",
+            SortKey::Path => "path",
+            SortKey::Ext => "ext",
+            SortKey::Duration => "duration",
+            SortKey::PlayCount => "play count",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How the items in the playlist are ordered.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ListOrder {
+    /// The order in which the items were added.
+    Original,
+    Shuffled,
+    Sorted(SortKey),
+}
+
+impl std::fmt::Display for ListOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListOrder::Original => f.write_str("ordered"),
+            ListOrder::Shuffled => f.write_str("shuffled"),
+            ListOrder::Sorted(sort_key) => write!(f, "sorted by {}", sort_key),
+        }
+    }
+}
 
 pub struct PlayList {
     pub items: Vec<PlayListItem>,
     pub now_playing_in_items: Option<usize>,
     pub now_playing_in_view: Option<usize>,
     pub next_to_play: Option<usize>,
+    /// The item selected by the user in the view.  `None` means following the playing item.
+    pub cursor: Option<usize>,
+    /// If true, `poll_module` returns `None` after the last item instead of starting over.
+    pub stop_at_end: bool,
     view: ListView,
+    next_id: usize,
+    order: ListOrder,
+    /// IDs of items to play before continuing from the playing item.
+    queue: VecDeque<usize>,
+    /// IDs of items played, the last being the playing item.
+    history: VecDeque<usize>,
+    /// How the text of the filter is matched.
+    filter_mode: FilterMode,
+    /// The last filter that could be parsed.  Used while the filter string is an invalid regular
+    /// expression, so that the view does not change until it is valid again.
+    valid_filter: Option<Filter>,
+    /// Why the filter string cannot be parsed, if it cannot.
+    filter_error: Option<String>,
+    /// Play a random item after each one instead of the following one.  `items` is not changed.
+    random: bool,
+    /// The ID of the item to play after the playing one in random mode.  Picked in advance so that
+    /// it can be prefetched.
+    random_next_id: Option<usize>,
+    /// Show the items grouped by directory.
+    grouped: bool,
+    /// The directories of the groups whose items are hidden in the grouped view.
+    collapsed_groups: HashSet<String>,
+    /// The directory the view, and thus the playback, is restricted to, if any.
+    scope: Option<String>,
+    /// Play counts kept across sessions.  Applied to items when they are added.
+    pub play_stats: PlayStats,
+    /// Starred modules kept across sessions.  Applied to items when they are added.
+    pub favorites: Favorites,
+    /// The numbers of items scanned and of all items, while the metadata scanner has items left.
+    pub metadata_scan_progress: Option<(usize, usize)>,
+    /// The `display_full_name`s of the last modules that failed to open, with the errors, the
+    /// latest last.
+    pub recent_failures: VecDeque<(String, String)>,
 }
 
 enum ListView {
@@ -39,6 +164,7 @@ enum ListView {
     },
 }
 
+#[derive(Clone, Copy)]
 enum MoveDir {
     Forward,
     Backward,
@@ -51,7 +177,25 @@ impl PlayList {
             now_playing_in_items: None,
             now_playing_in_view: None,
             next_to_play: None,
+            cursor: None,
+            stop_at_end: false,
             view: ListView::Direct,
+            next_id: 0,
+            order: ListOrder::Original,
+            queue: VecDeque::new(),
+            history: VecDeque::new(),
+            filter_mode: FilterMode::default(),
+            valid_filter: None,
+            filter_error: None,
+            random: false,
+            random_next_id: None,
+            grouped: false,
+            collapsed_groups: HashSet::new(),
+            scope: None,
+            play_stats: PlayStats::default(),
+            favorites: Favorites::default(),
+            metadata_scan_progress: None,
+            recent_failures: VecDeque::new(),
         }
     }
 
@@ -93,66 +237,299 @@ impl PlayList {
         }
     }
 
+    fn items_index_to_view_index(&self, items_index: usize) -> Option<usize> {
+        match &self.view {
+            ListView::Direct => Some(items_index).filter(|i| *i < self.items.len()),
+            ListView::Filtered { filtered_items, .. } => {
+                filtered_items.iter().position(|item| *item == items_index)
+            }
+        }
+    }
+
+    /// The filter string, or `None` if the view is not filtered by one.  The view may still be
+    /// restricted by the scope.
     pub fn get_filter_string(&self) -> Option<String> {
         match &self.view {
-            ListView::Direct => None,
-            ListView::Filtered { filter_string, .. } => Some(filter_string.clone()),
+            ListView::Filtered { filter_string, .. } if !filter_string.is_empty() => {
+                Some(filter_string.clone())
+            }
+            _ => None,
         }
     }
 
-    pub fn add_item(&mut self, item: PlayListItem) {
+    /// The titles and paths of the items in the view, e.g. to export them.
+    pub fn view_entries(&self) -> Vec<(String, ModPath)> {
+        (0..self.len())
+            .filter_map(|i| self.get_item(i))
+            .map(|item| (item.title(), item.mod_path.clone()))
+            .collect()
+    }
+
+    pub fn add_item(&mut self, mut item: PlayListItem) {
+        self.play_stats.apply_to(&mut item);
+        self.favorites.apply_to(&mut item);
+        item.id = self.next_id;
+        self.next_id += 1;
         self.items.push(item);
     }
 
-    pub fn poll_module(&mut self) -> Option<Module> {
-        if self.next_to_play.is_none() {
-            self.goto_next_module(1);
+    /// Move all items of another playlist to the end of this playlist.
+    pub fn append(&mut self, other: PlayList) {
+        for item in other.items {
+            self.add_item(item);
         }
+        match self.order {
+            // Keep the order, including items moved by the user.  The new items are at the end.
+            ListOrder::Original | ListOrder::Shuffled => self.rearrange_items(|_| {}),
+            ListOrder::Sorted(sort_key) => self.sort_by(sort_key),
+        }
+    }
 
-        let mut retries = 0;
+    /// Swap the items at the view indices `from` and `to`.  The cursor follows the item at `from`.
+    /// Return false if either index is out of range.
+    pub fn move_item(&mut self, from: usize, to: usize) -> bool {
+        let len = self.len();
+        if from >= len || to >= len {
+            return false;
+        }
 
-        let maybe_module = loop {
-            if let Some(index) = self.next_to_play {
-                self.now_playing_in_view = self.next_to_play.take();
-                self.now_playing_in_items = self
-                    .now_playing_in_view
-                    .map(|view_index| self.view_index_to_items_index(view_index));
+        // Both items are visible in the view, so the filtered view still contains the same
+        // set of items indices after swapping.  Only the indices need to be swapped.
+        let from_items = self.view_index_to_items_index(from);
+        let to_items = self.view_index_to_items_index(to);
+        self.items.swap(from_items, to_items);
 
-                let item = self.get_item(index).unwrap_or_else(|| {
-                    panic!("next_to_play points to non-existing item: {}", index)
-                });
+        let swap_index = |index: Option<usize>, a: usize, b: usize| match index {
+            Some(i) if i == a => Some(b),
+            Some(i) if i == b => Some(a),
+            _ => index,
+        };
+        self.now_playing_in_items = swap_index(self.now_playing_in_items, from_items, to_items);
+        self.now_playing_in_view = swap_index(self.now_playing_in_view, from, to);
+        self.next_to_play = swap_index(self.next_to_play, from, to);
+        self.cursor = Some(to);
 
-                match open_module_from_mod_path(&item.mod_path) {
-                    Ok(module) => {
-                        break Some(module);
-                    }
-                    Err(e) => {
-                        log::error!(
-                            "Error loading module {:?}: {}",
-                            item.mod_path.root_path.to_string_lossy(),
-                            e
-                        );
-                    }
-                }
+        true
+    }
+
+    /// Remove the item at `view_index`.  Return true if it was the playing item, in which case
+    /// the item after it becomes the next to play.
+    pub fn remove_item(&mut self, view_index: usize) -> bool {
+        if view_index >= self.len() {
+            return false;
+        }
 
-                retries += 1;
-                if retries >= self.len() {
-                    break None;
+        let items_index = self.view_index_to_items_index(view_index);
+        let removed = self.items.remove(items_index);
+        self.queue.retain(|id| *id != removed.id);
+        log::info!(
+            "Removed from playlist: {}",
+            removed.mod_path.display_full_name()
+        );
+
+        if let ListView::Filtered { filtered_items, .. } = &mut self.view {
+            filtered_items.remove(view_index);
+            for i in filtered_items.iter_mut() {
+                if *i > items_index {
+                    *i -= 1;
                 }
+            }
+        }
 
-                // Try the next in the playlist.
-                self.goto_next_module(1);
+        let was_playing = self.now_playing_in_items == Some(items_index);
+        self.now_playing_in_items = index_after_removal(self.now_playing_in_items, items_index);
+        self.now_playing_in_view = index_after_removal(self.now_playing_in_view, view_index);
+        self.next_to_play = index_after_removal(self.next_to_play, view_index);
+        self.cursor = self
+            .cursor
+            .map(|c| if c > view_index { c - 1 } else { c })
+            .filter(|c| *c < self.len());
+
+        if was_playing && !self.is_empty() {
+            // The item after the removed one has taken its place.
+            self.next_to_play = Some(if view_index < self.len() {
+                view_index
             } else {
-                log::info!("No more mods to play!");
-                break None;
+                0
+            });
+        }
+
+        was_playing
+    }
+
+    pub fn poll_module(&mut self) -> Option<Module> {
+        self.poll_with(|item| {
+            let mut module = open_module_from_mod_path(&item.mod_path)?;
+            item.metadata = Some(ModMetadata::from_module(&mut module));
+            Ok(module)
+        })
+    }
+
+    /// Advance to the next item and open it with `open`.  Items that fail to open are marked and
+    /// skipped.
+    fn poll_with<T>(&mut self, mut open: impl FnMut(&mut PlayListItem) -> Result<T>) -> Option<T> {
+        if self.next_to_play.is_none() && !self.goto_next_queued() && !self.advance() {
+            log::info!("Reached the end of the playlist.");
+            return None;
+        }
+
+        let Some(view_index) = self.next_to_play.take() else {
+            log::info!("No more mods to play!");
+            return None;
+        };
+        let mut items_index = self.view_index_to_items_index(view_index);
+
+        // The candidates are the items in the view that have not failed, and the first item,
+        // which may have been chosen by the user even if it failed before.  Each failed item is
+        // marked, and `after_failed` never returns marked items, so each candidate is tried at
+        // most once.
+        let n_not_failed = (0..self.len())
+            .filter(|i| !self.get_item(*i).is_some_and2(|item| item.failed))
+            .count();
+        let n_candidates = n_not_failed + usize::from(self.items[items_index].failed);
+
+        for _ in 0..n_candidates {
+            let item = &mut self.items[items_index];
+            match open(item) {
+                Ok(module) => {
+                    let id = item.id;
+                    // Only change the playing item when something is actually played, so that
+                    // `now_playing_in_items` and `now_playing_in_view` stay consistent.
+                    self.set_now_playing(items_index);
+                    self.push_history(id);
+                    return Some(module);
+                }
+                Err(e) => {
+                    log::error!(
+                        "Error loading module {:?}: {}",
+                        item.mod_path.root_path.to_string_lossy(),
+                        e
+                    );
+                    item.failed = true;
+                    let name = item.mod_path.display_full_name();
+                    self.push_failure(name, e.to_string());
+                }
             }
+
+            items_index = self.after_failed(items_index)?;
+        }
+
+        None
+    }
+
+    /// The index in `items` of the item to try after the one at `items_index` failed to open.
+    fn after_failed(&self, items_index: usize) -> Option<usize> {
+        if self.random {
+            return self
+                .pick_random_id(&mut rand::thread_rng())
+                .and_then(|id| self.items.iter().position(|item| item.id == id))
+                .filter(|i| !self.items[*i].failed);
+        }
+        let view_index = self.items_index_to_view_index(items_index)?;
+        let len = self.len();
+        if self.stop_at_end && view_index + 1 >= len {
+            return None;
+        }
+        self.skip_failed((view_index + 1) % len, MoveDir::Forward)
+            .map(|view_index| self.view_index_to_items_index(view_index))
+    }
+
+    /// Make the item at the index in `items` the playing one, and count it as played.
+    fn set_now_playing(&mut self, items_index: usize) {
+        self.now_playing_in_items = Some(items_index);
+        self.now_playing_in_view = self.items_index_to_view_index(items_index);
+
+        let now = SystemTime::now();
+        let item = &mut self.items[items_index];
+        item.play_count += 1;
+        item.last_played = Some(now);
+        self.play_stats.record_played(&item.mod_path, now);
+    }
+
+    fn push_history(&mut self, id: usize) {
+        if self.history.len() >= HISTORY_CAPACITY {
+            self.history.pop_front();
+        }
+        self.history.push_back(id);
+        if self.random {
+            self.random_next_id = self.pick_random_id(&mut rand::thread_rng());
+        }
+    }
+
+    pub fn is_random(&self) -> bool {
+        self.random
+    }
+
+    pub fn toggle_random(&mut self) {
+        self.random = !self.random;
+        self.random_next_id = if self.random {
+            self.pick_random_id(&mut rand::thread_rng())
+        } else {
+            None
         };
+    }
 
-        maybe_module
+    /// Pick a random item in the view, avoiding recently played ones if possible.
+    fn pick_random_id(&self, rng: &mut impl Rng) -> Option<usize> {
+        let n_avoided = RANDOM_AVOID_RECENT.min(self.len() / 2);
+        let recent = self
+            .history
+            .iter()
+            .rev()
+            .take(n_avoided)
+            .collect::<HashSet<_>>();
+        let candidates = (0..self.len())
+            .filter_map(|view_index| self.get_item(view_index))
+            .filter(|item| !item.failed && !recent.contains(&item.id))
+            .map(|item| item.id)
+            .collect::<Vec<_>>();
+        candidates.choose(rng).copied().or_else(|| {
+            self.get_item(rng.gen_range(0..self.len().max(1)))
+                .map(|item| item.id)
+        })
     }
 
-    fn move_rel(&mut self, steps: usize, dir: MoveDir) -> bool {
-        let maybe_next = if self.is_empty() {
+    /// The view index of the item to play after the playing one, ignoring the queue and
+    /// `stop_at_end`.
+    fn following_view_index(&self) -> Option<usize> {
+        if self.random {
+            // Fall back to the next item if the picked one is filtered out.
+            let picked = self.random_next_id.and_then(|id| self.view_index_of_id(id));
+            if picked.is_some() {
+                return picked;
+            }
+        }
+        self.rel_view_index(1, MoveDir::Forward)
+    }
+
+    /// Set the item after the playing one, or a random one in random mode, as the next to play.
+    pub fn goto_following(&mut self) -> bool {
+        let maybe_next = self.following_view_index();
+        self.next_to_play = maybe_next;
+        maybe_next.is_some()
+    }
+
+    /// Set the item played before the playing one as the next to play, skipping items no longer
+    /// in the view.  Return false if there is no such item in the history.
+    pub fn goto_history_back(&mut self) -> bool {
+        // The last entry is the playing item itself.
+        let mut end = self.history.len().saturating_sub(1);
+        while end > 0 {
+            end -= 1;
+            if let Some(view_index) = self.view_index_of_id(self.history[end]) {
+                // The item will be pushed again when it is played.
+                self.history.truncate(end);
+                self.next_to_play = Some(view_index);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The view index of the item `steps` away from the playing item, skipping items that failed
+    /// to open.
+    fn rel_view_index(&self, steps: usize, dir: MoveDir) -> Option<usize> {
+        if self.is_empty() {
             None
         } else if let Some(n) = self.now_playing_in_view {
             let len = self.len();
@@ -160,19 +537,235 @@ impl PlayList {
                 MoveDir::Forward => add_modulo_unsigned(n, steps % len, len),
                 MoveDir::Backward => sub_modulo_unsigned(n, steps % len, len),
             };
-            Some(result)
+            self.skip_failed(result, dir)
         } else {
             let result = match dir {
                 MoveDir::Forward => 0,
                 MoveDir::Backward => self.len() - 1,
             };
-            Some(result)
-        };
+            self.skip_failed(result, dir)
+        }
+    }
+
+    /// The first item from `start` on in the direction `dir` that has not failed to open.
+    /// `None` if all items in the view have failed.
+    fn skip_failed(&self, start: usize, dir: MoveDir) -> Option<usize> {
+        let len = self.len();
+        (0..len)
+            .map(|i| match dir {
+                MoveDir::Forward => add_modulo_unsigned(start, i, len),
+                MoveDir::Backward => sub_modulo_unsigned(start, i, len),
+            })
+            .find(|view_index| !self.get_item(*view_index).is_some_and2(|item| item.failed))
+    }
+
+    /// Mark the item with the given ID as failed to open.
+    pub fn mark_failed(&mut self, id: usize, error: String) {
+        if let Some(item) = self.items.iter_mut().find(|item| item.id == id) {
+            item.failed = true;
+            let name = item.mod_path.display_full_name();
+            self.push_failure(name, error);
+        }
+    }
+
+    fn push_failure(&mut self, name: String, error: String) {
+        if self.recent_failures.len() >= RECENT_FAILURES_CAPACITY {
+            self.recent_failures.pop_front();
+        }
+        self.recent_failures.push_back((name, error));
+    }
+
+    /// The number of items that failed to open.
+    pub fn n_failed(&self) -> usize {
+        self.items.iter().filter(|item| item.failed).count()
+    }
+
+    /// Give the items that failed to open another chance.  Return the number of such items.
+    pub fn clear_failed(&mut self) -> usize {
+        self.recent_failures.clear();
+        let mut n_cleared = 0;
+        for item in self.items.iter_mut().filter(|item| item.failed) {
+            item.failed = false;
+            n_cleared += 1;
+        }
+        n_cleared
+    }
+
+    pub fn set_content_hash(&mut self, id: usize, content_hash: u64) {
+        if let Some(item) = self.items.iter_mut().find(|item| item.id == id) {
+            item.content_hash = Some(content_hash);
+        }
+    }
+
+    /// Mark the items with the same content hash as an item added earlier as duplicates, so that
+    /// one copy of each module is not marked.  Return the number of duplicates.
+    pub fn mark_duplicates(&mut self) -> usize {
+        let mut first_ids = HashMap::new();
+        for item in self.items.iter() {
+            if let Some(content_hash) = item.content_hash {
+                let first_id = first_ids.entry(content_hash).or_insert(item.id);
+                *first_id = (*first_id).min(item.id);
+            }
+        }
+        let mut n_duplicates = 0;
+        for item in self.items.iter_mut() {
+            item.duplicate = item
+                .content_hash
+                .is_some_and2(|content_hash| first_ids[content_hash] != item.id);
+            if item.duplicate {
+                n_duplicates += 1;
+            }
+        }
+        n_duplicates
+    }
+
+    /// Remove the items marked as duplicates, except the playing one.  Return the number of items
+    /// removed.
+    pub fn remove_duplicates(&mut self) -> usize {
+        let now_playing_id = self.now_playing_in_items.map(|i| self.items[i].id);
+        let n_before = self.items.len();
+        self.retain_items(|item| !item.duplicate || Some(item.id) == now_playing_id);
+        n_before - self.items.len()
+    }
 
+    fn move_rel(&mut self, steps: usize, dir: MoveDir) -> bool {
+        let maybe_next = self.rel_view_index(steps, dir);
         self.next_to_play = maybe_next;
         maybe_next.is_some()
     }
 
+    /// The view index of the first queued item in the view.
+    fn queued_view_index(&self) -> Option<usize> {
+        self.queue.iter().find_map(|id| self.view_index_of_id(*id))
+    }
+
+    /// True if the playing item is the last one and the playlist should not start over.
+    /// Random mode never reaches the end.
+    fn should_stop(&self) -> bool {
+        self.stop_at_end
+            && !self.random
+            && self
+                .now_playing_in_view
+                .is_some_and2(|n| n + 1 >= self.len())
+    }
+
+    /// True if the playing item is the last one in the scope, after which the playback continues
+    /// with the whole playlist.
+    fn scope_ends(&self) -> bool {
+        self.scope.is_some()
+            && !self.random
+            && self
+                .now_playing_in_view
+                .is_some_and2(|n| n + 1 >= self.len())
+    }
+
+    /// Set the item after the playing one as the next to play, unless `should_stop`.
+    fn advance(&mut self) -> bool {
+        if self.scope_ends() {
+            log::info!("Finished playing the directory.  Continuing with the whole playlist.");
+            self.exit_scope();
+        }
+        !self.should_stop() && self.goto_following()
+    }
+
+    /// The view index of the item `poll_module` will try first, without advancing.
+    fn peek_next_view_index(&self) -> Option<usize> {
+        self.next_to_play
+            .or_else(|| self.queued_view_index())
+            .or_else(|| {
+                // The item after the scope is not in the view yet.
+                if self.should_stop() || self.scope_ends() {
+                    None
+                } else {
+                    self.following_view_index()
+                }
+            })
+    }
+
+    /// Set the first queued item in the view as the next to play, and remove it and the queued
+    /// items before it (which are filtered out) from the queue.  Return false if there is none.
+    pub fn goto_next_queued(&mut self) -> bool {
+        while let Some(id) = self.queue.pop_front() {
+            if let Some(view_index) = self.view_index_of_id(id) {
+                self.next_to_play = Some(view_index);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Add the item at the view index to the end of the queue, or remove it if already queued.
+    pub fn toggle_queued(&mut self, view_index: usize) {
+        let Some(id) = self.get_item(view_index).map(|item| item.id) else {
+            return;
+        };
+        match self.queue.iter().position(|queued_id| *queued_id == id) {
+            Some(position) => {
+                self.queue.remove(position);
+            }
+            None => self.queue.push_back(id),
+        }
+    }
+
+    /// Star the item at the view index, or unstar it if starred.
+    pub fn toggle_favorite(&mut self, view_index: usize) {
+        if view_index >= self.len() {
+            return;
+        }
+        let items_index = self.view_index_to_items_index(view_index);
+        let item = &mut self.items[items_index];
+        item.favorite = !item.favorite;
+        self.favorites.set(&item.mod_path, item.favorite);
+    }
+
+    /// The position in the queue (starting from 1) of the item with the given ID.
+    pub fn queue_position(&self, id: usize) -> Option<usize> {
+        self.queue
+            .iter()
+            .position(|queued_id| *queued_id == id)
+            .map(|position| position + 1)
+    }
+
+    /// Set the metadata of the item with the given ID, if it is still in the playlist.
+    pub fn set_metadata(&mut self, id: usize, metadata: ModMetadata) {
+        if let Some(item) = self.items.iter_mut().find(|item| item.id == id) {
+            item.metadata = Some(metadata);
+        }
+    }
+
+    /// The ID and the path of the item `poll_module` will try first.
+    pub fn peek_next_item(&self) -> Option<(usize, ModPath)> {
+        self.peek_next_view_index()
+            .and_then(|view_index| self.get_item(view_index))
+            .map(|item| (item.id, item.mod_path.clone()))
+    }
+
+    /// The title of the item `poll_module` will try first, or `None` if the playback stops or
+    /// leaves the scope after the playing item.
+    pub fn peek_next_title(&self) -> Option<String> {
+        self.peek_next_view_index()
+            .and_then(|view_index| self.get_item(view_index))
+            .map(|item| item.title())
+    }
+
+    /// Advance to the item with the given ID if it is the one `poll_module` would try first.
+    pub fn advance_to(&mut self, id: usize) -> bool {
+        match self.peek_next_view_index() {
+            Some(view_index) if self.get_item(view_index).is_some_and2(|item| item.id == id) => {
+                if self.next_to_play.take().is_none() {
+                    // It may be a queued item.  Dequeue it as `goto_next_queued` would.
+                    if let Some(position) = self.queue.iter().position(|q| *q == id) {
+                        self.queue.drain(..=position);
+                    }
+                }
+                self.set_now_playing(self.view_index_to_items_index(view_index));
+                self.push_history(id);
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub fn goto_next_module(&mut self, steps: usize) -> bool {
         self.move_rel(steps, MoveDir::Forward)
     }
@@ -181,36 +774,284 @@ impl PlayList {
         self.move_rel(steps, MoveDir::Backward)
     }
 
-    pub fn shuffle(&mut self) {
-        let mut rng = rand::thread_rng();
-        self.items.shuffle(&mut rng);
+    /// Play the first item in the view next, skipping items that failed to open.
+    pub fn goto_first(&mut self) -> bool {
+        self.next_to_play = if self.is_empty() {
+            None
+        } else {
+            self.skip_failed(0, MoveDir::Forward)
+        };
+        self.next_to_play.is_some()
+    }
+
+    /// Play the last item in the view next, skipping items that failed to open.
+    pub fn goto_last(&mut self) -> bool {
+        self.next_to_play = match self.len().checked_sub(1) {
+            Some(last) => self.skip_failed(last, MoveDir::Backward),
+            None => None,
+        };
+        self.next_to_play.is_some()
+    }
+
+    /// Move the cursor by `offset` items, stopping at both ends of the view.
+    /// If the cursor is not active, it starts from the playing item.
+    ///
+    /// In the grouped view, the cursor moves by rows instead, and a collapsed group counts as one
+    /// row.
+    pub fn move_cursor_by(&mut self, offset: isize) {
+        if self.is_empty() {
+            self.cursor = None;
+            return;
+        }
+        let start = self.cursor.or(self.now_playing_in_view).unwrap_or(0);
+        let new_cursor = if self.grouped {
+            let grouped_view = self.grouped_view();
+            let selectable_rows = grouped_view.selectable_rows();
+            let start_row = grouped_view.row_of_item[start];
+            let start_position = selectable_rows
+                .iter()
+                .position(|(row, _)| *row == start_row)
+                .unwrap_or(0);
+            let new_position = start_position
+                .saturating_add_signed(offset)
+                .min(selectable_rows.len() - 1);
+            selectable_rows[new_position].1
+        } else {
+            start.saturating_add_signed(offset).min(self.len() - 1)
+        };
+        self.cursor = Some(new_cursor);
+    }
+
+    pub fn get_scope(&self) -> Option<&str> {
+        self.scope.as_deref()
+    }
+
+    /// Restrict the view, and thus the playback, to the directory of the item at `view_index`,
+    /// like a filter.
+    pub fn enter_scope(&mut self, view_index: usize) {
+        if let Some(item) = self.get_item(view_index) {
+            self.set_scope(Some(group_dir(&item.mod_path)));
+        }
+    }
+
+    /// Show and play the whole playlist again.
+    pub fn exit_scope(&mut self) {
+        self.set_scope(None);
+    }
+
+    fn set_scope(&mut self, scope: Option<String>) {
+        let next_to_play_id = self
+            .next_to_play
+            .and_then(|view_index| self.get_item(view_index))
+            .map(|item| item.id);
+        self.scope = scope;
+        self.update_filter(self.get_filter_string().unwrap_or_default());
+        self.next_to_play = next_to_play_id.and_then(|id| self.view_index_of_id(id));
+    }
+
+    pub fn is_grouped(&self) -> bool {
+        self.grouped
+    }
+
+    /// Switch between the flat view and the view grouped by directory.
+    pub fn toggle_grouped(&mut self) {
+        self.grouped = !self.grouped;
+    }
+
+    /// Collapse the group of the item at `view_index`, or expand it if collapsed.
+    pub fn toggle_group_collapsed(&mut self, view_index: usize) {
+        let Some(item) = self.get_item(view_index) else {
+            return;
+        };
+        let dir = group_dir(&item.mod_path);
+        if !self.collapsed_groups.remove(&dir) {
+            self.collapsed_groups.insert(dir);
+        }
+    }
+
+    /// The rows of the grouped view, built from the items in the view.
+    pub fn grouped_view(&self) -> GroupedView {
+        GroupedView::build(
+            (0..self.len()).map(|view_index| self.get_item(view_index).unwrap()),
+            &self.collapsed_groups,
+        )
+    }
+
+    pub fn move_cursor_to_first(&mut self) {
+        self.cursor = if self.is_empty() { None } else { Some(0) };
+    }
+
+    pub fn move_cursor_to_last(&mut self) {
+        self.cursor = self.len().checked_sub(1);
+    }
+
+    /// Let the cursor follow the playing item again.
+    pub fn reset_cursor(&mut self) {
+        self.cursor = None;
+    }
+
+    /// Play the item at the index of `items` next.  Return false if the item is not in the view.
+    pub fn play_items_index(&mut self, items_index: usize) -> bool {
+        match self.items_index_to_view_index(items_index) {
+            Some(view_index) => {
+                self.next_to_play = Some(view_index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Play the item at the view index next.  Return false if the index is out of range.
+    ///
+    /// Like the other indices of the view, `next_to_play` is mapped to the item when it is
+    /// polled, so this picks the right item even if the view is filtered.
+    pub fn play_view_index(&mut self, view_index: usize) -> bool {
+        if view_index < self.len() {
+            self.next_to_play = Some(view_index);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Play the item under the cursor next.  Return false if the cursor is not active.
+    pub fn play_cursor(&mut self) -> bool {
+        match self.cursor {
+            Some(cursor) => self.play_view_index(cursor),
+            None => false,
+        }
+    }
+
+    pub fn shuffle(&mut self, rng: &mut impl Rng) {
+        self.rearrange_items(|items| items.shuffle(rng));
+        self.order = ListOrder::Shuffled;
+    }
+
+    /// Put the items back in the order in which they were added.
+    pub fn restore_original_order(&mut self) {
+        // IDs are assigned in increasing order as items are added.
+        self.rearrange_items(|items| items.sort_by_key(|item| item.id));
+        self.order = ListOrder::Original;
+    }
+
+    fn view_index_of_id(&self, id: usize) -> Option<usize> {
+        self.items
+            .iter()
+            .position(|item| item.id == id)
+            .and_then(|items_index| self.items_index_to_view_index(items_index))
+    }
+
+    /// Rearrange or remove `items` in any way, keeping the playing item, the next item and the
+    /// cursor on the same items, and rebuilding the filtered view.
+    fn rearrange_items(&mut self, rearrange: impl FnOnce(&mut Vec<PlayListItem>)) {
+        let id_at_view = |view_index: Option<usize>| {
+            view_index
+                .and_then(|v| self.get_item(v))
+                .map(|item| item.id)
+        };
+        let now_playing_id = self.now_playing_in_items.map(|i| self.items[i].id);
+        let next_to_play_id = id_at_view(self.next_to_play);
+        let cursor_id = id_at_view(self.cursor);
+
+        rearrange(&mut self.items);
+
+        if !self.queue.is_empty() {
+            let ids = self
+                .items
+                .iter()
+                .map(|item| item.id)
+                .collect::<HashSet<_>>();
+            self.queue.retain(|id| ids.contains(id));
+        }
+
+        self.now_playing_in_items =
+            now_playing_id.and_then(|id| self.items.iter().position(|item| item.id == id));
+        self.cursor = None;
+        self.update_filter(self.get_filter_string().unwrap_or_default());
+        self.next_to_play = next_to_play_id.and_then(|id| self.view_index_of_id(id));
+        self.cursor = cursor_id.and_then(|id| self.view_index_of_id(id));
+    }
+
+    /// Keep only the items for which `f` returns true.  The caller should keep the playing item.
+    pub fn retain_items(&mut self, f: impl FnMut(&PlayListItem) -> bool) {
+        self.rearrange_items(|items| items.retain(f));
+    }
+
+    pub fn sort_by(&mut self, sort_key: SortKey) {
+        self.rearrange_items(|items| match sort_key {
+            SortKey::Name => {
+                items.sort_by_cached_key(|item| item.mod_path.display_name().to_lowercase())
+            }
+            SortKey::Title => items.sort_by_cached_key(|item| item.title().to_lowercase()),
+            SortKey::Path => items.sort_by_cached_key(|item| item.mod_path.display_full_name()),
+            SortKey::Ext => items.sort_by_cached_key(|item| {
+                (
+                    item.mod_path.extension(),
+                    item.mod_path.display_name().to_lowercase(),
+                )
+            }),
+            SortKey::Duration => items.sort_by_cached_key(|item| {
+                let duration = item
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.duration);
+                (
+                    duration.is_none(),
+                    duration,
+                    item.mod_path.display_name().to_lowercase(),
+                )
+            }),
+            SortKey::PlayCount => items.sort_by_cached_key(|item| {
+                (
+                    item.play_count,
+                    item.last_played,
+                    item.mod_path.display_name().to_lowercase(),
+                )
+            }),
+        });
+        self.order = ListOrder::Sorted(sort_key);
+    }
+
+    pub fn get_order(&self) -> ListOrder {
+        self.order
     }
 
     pub fn update_filter(&mut self, string: String) {
-        if string.is_empty() {
+        let cursor_in_items = self
+            .cursor
+            .map(|view_index| self.view_index_to_items_index(view_index));
+
+        if string.is_empty() && self.scope.is_none() {
             self.view = ListView::Direct;
             self.now_playing_in_view = self.now_playing_in_items;
+            self.valid_filter = None;
+            self.filter_error = None;
         } else {
             let filter_string = string;
-            let lower_string = filter_string.to_lowercase();
-            let case_insensitive_contains =
-                |string2: &String| string2.to_lowercase().contains(&lower_string);
-            let filtered_items = self
+            match Filter::parse(&filter_string, self.filter_mode) {
+                Ok(filter) => {
+                    self.valid_filter = Some(filter);
+                    self.filter_error = None;
+                }
+                Err(e) => {
+                    log::debug!("Invalid filter {:?}: {}", filter_string, e);
+                    self.filter_error = Some(e.to_string());
+                }
+            }
+            let filter = self.valid_filter.get_or_insert_with(Filter::match_all);
+            let scope = self.scope.as_ref();
+            let mut scored_items = self
                 .items
                 .iter()
                 .enumerate()
-                .filter_map(|(i, item)| {
-                    if case_insensitive_contains(&item.mod_path.display_name())
-                        || item
-                            .metadata
-                            .is_some_and2(|metadata| case_insensitive_contains(&metadata.title))
-                    {
-                        Some(i)
-                    } else {
-                        None
-                    }
-                })
+                .filter(|(_, item)| scope.is_none() || scope == Some(&group_dir(&item.mod_path)))
+                .filter_map(|(i, item)| filter.score(item).map(|score| (i, score)))
                 .collect::<Vec<_>>();
+            if filter.is_fuzzy() {
+                // Best matches first.  The sort is stable, so equal scores keep their order.
+                scored_items.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+            }
+            let filtered_items = scored_items.into_iter().map(|(i, _)| i).collect::<Vec<_>>();
             let new_now_playing_in_view = self.now_playing_in_items.and_then(|items_index| {
                 filtered_items.iter().position(|item| *item == items_index)
             });
@@ -220,6 +1061,37 @@ impl PlayList {
             };
             self.now_playing_in_view = new_now_playing_in_view;
         }
+
+        self.cursor =
+            cursor_in_items.and_then(|items_index| self.items_index_to_view_index(items_index));
+    }
+
+    pub fn get_filter_mode(&self) -> FilterMode {
+        self.filter_mode
+    }
+
+    /// Why the filter string cannot be parsed.  The view is filtered by the last valid one.
+    pub fn get_filter_error(&self) -> Option<&str> {
+        self.filter_error.as_deref()
+    }
+
+    /// The byte ranges of the parts of `text` matched by the filter, to be highlighted.  Empty if
+    /// the view is not filtered by a filter string.
+    pub fn filter_match_ranges(&self, text: &str) -> Vec<Range<usize>> {
+        match (&self.view, &self.valid_filter) {
+            (ListView::Filtered { filter_string, .. }, Some(filter))
+                if !filter_string.is_empty() =>
+            {
+                filter.match_ranges(text)
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Switch to the next way of matching, and filter again.
+    pub fn cycle_filter_mode(&mut self) {
+        self.filter_mode = self.filter_mode.next();
+        self.update_filter(self.get_filter_string().unwrap_or_default());
     }
 
     pub fn update_filter_push(&mut self, ch: char) {
@@ -245,6 +1117,15 @@ impl PlayList {
     }
 }
 
+/// Adjust an index into a list after the element at `removed` is removed.
+fn index_after_removal(index: Option<usize>, removed: usize) -> Option<usize> {
+    match index {
+        Some(i) if i == removed => None,
+        Some(i) if i > removed => Some(i - 1),
+        _ => index,
+    }
+}
+
 pub struct PlayListModuleProvider {
     playlist: Arc<Mutex<PlayList>>,
 }
@@ -259,4 +1140,246 @@ impl ModuleProvider for PlayListModuleProvider {
     fn poll_module(&mut self) -> Option<Module> {
         self.playlist.lock().unwrap().poll_module()
     }
+
+    fn peek_next_module(&mut self) -> Option<PrefetchedModule> {
+        // Do not hold the lock while opening the module.  It may take a while.
+        let (id, mod_path) = self.playlist.lock().unwrap().peek_next_item()?;
+        match open_module_from_mod_path(&mod_path) {
+            Ok(mut module) => {
+                let metadata = ModMetadata::from_module(&mut module);
+                self.playlist.lock().unwrap().set_metadata(id, metadata);
+                Some(PrefetchedModule { key: id, module })
+            }
+            Err(e) => {
+                log::debug!("Failed to prefetch {}: {}", mod_path.display_full_name(), e);
+                self.playlist.lock().unwrap().mark_failed(id, e.to_string());
+                None
+            }
+        }
+    }
+
+    fn accept_prefetched(&mut self, prefetched: &PrefetchedModule) -> bool {
+        self.playlist.lock().unwrap().advance_to(prefetched.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn mod_path(name: &str) -> ModPath {
+        ModPath {
+            root_path: "mods".into(),
+            file_path: format!("mods/{}", name).into(),
+            archive_paths: vec![],
+            is_archived_single: false,
+        }
+    }
+
+    fn playlist_of(names: &[&str]) -> PlayList {
+        let mut playlist = PlayList::new();
+        for name in names {
+            playlist.add_item(PlayListItem::new(mod_path(name)));
+        }
+        playlist
+    }
+
+    fn filtered_items(playlist: &PlayList) -> Vec<usize> {
+        match &playlist.view {
+            ListView::Direct => (0..playlist.items.len()).collect(),
+            ListView::Filtered { filtered_items, .. } => filtered_items.clone(),
+        }
+    }
+
+    /// The full name of the playing item, if it is in the view.
+    fn playing_name(playlist: &PlayList) -> Option<String> {
+        playlist
+            .now_playing_in_view
+            .and_then(|view_index| playlist.get_item(view_index))
+            .map(|item| item.mod_path.display_full_name())
+    }
+
+    const CHIP_NAMES: [&str; 4] = [
+        "chip_tune.mod",
+        "chip_cover.xm",
+        "cover.s3m",
+        "chiptune_remix.it",
+    ];
+
+    #[test]
+    fn filter_requires_all_positive_terms() {
+        let mut playlist = playlist_of(&CHIP_NAMES);
+        playlist.update_filter("chip remix".to_string());
+        assert_eq!(filtered_items(&playlist), vec![3]);
+    }
+
+    #[test]
+    fn filter_excludes_negative_terms() {
+        let mut playlist = playlist_of(&CHIP_NAMES);
+        playlist.update_filter("chip -cover".to_string());
+        assert_eq!(filtered_items(&playlist), vec![0, 3]);
+    }
+
+    #[test]
+    fn filter_ignores_lone_dash() {
+        let mut playlist = playlist_of(&CHIP_NAMES);
+        playlist.update_filter("chip -".to_string());
+        assert_eq!(filtered_items(&playlist), vec![0, 1, 3]);
+    }
+
+    #[test]
+    fn filter_of_single_term_with_dash_matches_substring() {
+        let mut playlist = playlist_of(&["song-remix.mod", "remix.mod", "song.mod"]);
+        playlist.update_filter("-remix".to_string());
+        assert_eq!(filtered_items(&playlist), vec![0]);
+    }
+
+    #[test]
+    fn filter_recomputes_now_playing_in_view() {
+        let mut playlist = playlist_of(&CHIP_NAMES);
+        playlist.set_now_playing(3);
+
+        playlist.update_filter("chip -cover".to_string());
+        assert_eq!(playlist.now_playing_in_view, Some(1));
+        assert_eq!(playlist.now_playing_in_items, Some(3));
+
+        playlist.update_filter("cover".to_string());
+        assert_eq!(playlist.now_playing_in_view, None);
+        assert_eq!(playlist.now_playing_in_items, Some(3));
+
+        playlist.update_filter(String::new());
+        assert_eq!(playlist.now_playing_in_view, Some(3));
+    }
+
+    /// Poll with an opener that fails for the items whose IDs are in `failing`, and return the ID
+    /// of the item played and the number of items tried.
+    fn poll_failing(playlist: &mut PlayList, failing: &[usize]) -> (Option<usize>, usize) {
+        let mut n_tried = 0;
+        let polled = playlist.poll_with(|item| {
+            n_tried += 1;
+            if failing.contains(&item.id) {
+                anyhow::bail!("Cannot open {}", item.mod_path.display_name());
+            }
+            Ok(item.id)
+        });
+        (polled, n_tried)
+    }
+
+    /// Items alternating between "good" and "skip", filtered by "good" in some tests.
+    fn alternating_playlist(n_items: usize) -> PlayList {
+        let names = (0..n_items)
+            .map(|i| match i % 2 {
+                0 => format!("good_{}.mod", i),
+                _ => format!("skip_{}.mod", i),
+            })
+            .collect::<Vec<_>>();
+        playlist_of(&names.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn poll_skips_first_items_that_fail() {
+        let mut playlist = alternating_playlist(6);
+        assert_eq!(poll_failing(&mut playlist, &[0, 1, 2]), (Some(3), 4));
+        assert_eq!(playlist.now_playing_in_items, Some(3));
+        assert_eq!(playlist.now_playing_in_view, Some(3));
+        assert_eq!(playlist.n_failed(), 3);
+
+        // The failed items are not tried again.
+        playlist.now_playing_in_items = None;
+        playlist.now_playing_in_view = None;
+        assert_eq!(poll_failing(&mut playlist, &[0, 1, 2]), (Some(3), 1));
+    }
+
+    #[test]
+    fn poll_skips_first_items_that_fail_in_filtered_view() {
+        let mut playlist = alternating_playlist(8);
+        playlist.update_filter("good".to_string());
+        assert_eq!(filtered_items(&playlist), vec![0, 2, 4, 6]);
+
+        assert_eq!(poll_failing(&mut playlist, &[0, 2]), (Some(4), 3));
+        assert_eq!(playlist.now_playing_in_items, Some(4));
+        assert_eq!(playlist.now_playing_in_view, Some(2));
+        assert_eq!(playlist.n_failed(), 2);
+    }
+
+    #[test]
+    fn poll_gives_up_when_all_items_fail() {
+        let mut playlist = alternating_playlist(5);
+        assert_eq!(poll_failing(&mut playlist, &[0, 1, 2, 3, 4]), (None, 5));
+        assert_eq!(playlist.now_playing_in_items, None);
+        assert_eq!(playlist.n_failed(), 5);
+    }
+
+    #[test]
+    fn poll_gives_up_when_all_items_in_filtered_view_fail() {
+        let mut playlist = alternating_playlist(8);
+        playlist.update_filter("good".to_string());
+
+        // The items filtered out would open, but are not candidates.
+        assert_eq!(poll_failing(&mut playlist, &[0, 2, 4, 6]), (None, 4));
+        assert_eq!(playlist.now_playing_in_items, None);
+        assert_eq!(playlist.now_playing_in_view, None);
+        assert_eq!(playlist.n_failed(), 4);
+    }
+
+    #[test]
+    fn play_items_index_maps_to_filtered_view() {
+        let mut playlist = alternating_playlist(8);
+        playlist.update_filter("good".to_string());
+
+        assert!(playlist.play_items_index(4));
+        assert_eq!(playlist.next_to_play, Some(2));
+        assert_eq!(poll_failing(&mut playlist, &[]), (Some(4), 1));
+
+        // Items filtered out or out of range are not played.
+        assert!(!playlist.play_items_index(3));
+        assert!(!playlist.play_items_index(8));
+        assert_eq!(playlist.next_to_play, None);
+    }
+
+    #[test]
+    fn poll_stops_at_end_after_failures() {
+        let mut playlist = alternating_playlist(4);
+        playlist.stop_at_end = true;
+        playlist.set_now_playing(1);
+        assert_eq!(poll_failing(&mut playlist, &[2, 3]), (None, 2));
+        assert_eq!(playlist.now_playing_in_items, Some(1));
+    }
+
+    #[test]
+    fn shuffle_keeps_playing_item() {
+        let mut playlist = alternating_playlist(20);
+        playlist.set_now_playing(5);
+        let name = playing_name(&playlist);
+
+        for seed in 0..10 {
+            playlist.shuffle(&mut StdRng::seed_from_u64(seed));
+            assert_eq!(playing_name(&playlist), name);
+            let items_index = playlist.now_playing_in_items.unwrap();
+            assert_eq!(
+                Some(playlist.items[items_index].mod_path.display_full_name()),
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn shuffle_keeps_playing_item_in_filtered_view() {
+        let mut playlist = alternating_playlist(20);
+        playlist.update_filter("skip".to_string());
+        playlist.set_now_playing(5);
+        let name = playing_name(&playlist);
+        assert!(name.is_some());
+
+        for seed in 0..10 {
+            playlist.shuffle(&mut StdRng::seed_from_u64(seed));
+            assert_eq!(playing_name(&playlist), name);
+            assert_eq!(playlist.len(), 10);
+        }
+
+        playlist.update_filter(String::new());
+        assert_eq!(playing_name(&playlist), name);
+    }
 }
@@ -0,0 +1,131 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::module_file::open_module_for_metadata;
+
+use super::{cache::MetadataCache, ModMetadata, ModPath, PlayList};
+
+/// Items to look up or open before updating the playlist.  The lock is taken once per batch.
+const BATCH_SIZE: usize = 16;
+
+/// How long to wait before looking for new items when all items are scanned.
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Save the cache after opening this many modules, so that the work is not lost if the scan of a
+/// large collection is interrupted.
+const SAVE_INTERVAL: usize = 256;
+
+/// Fill in the metadata of the items in the playlist, until `stop` is set.
+///
+/// Metadata is taken from the on-disk cache if the file is unchanged.  Otherwise the module is
+/// opened, and the result is added to the cache.  The cache is saved whenever all items are
+/// scanned, every `SAVE_INTERVAL` modules opened, and when stopping.  Items added later are
+/// scanned, too.  Each item is looked at most once, even if it fails to open.  The playlist is
+/// not locked while opening modules.
+pub fn scan_metadata(playlist: Arc<Mutex<PlayList>>, stop: Arc<AtomicBool>) {
+    let mut cache = MetadataCache::load();
+    // The number of modules added to the cache since it was last saved.
+    let mut n_unsaved = 0;
+    let mut visited = HashSet::new();
+
+    while !stop.load(Ordering::SeqCst) {
+        let pending: Vec<(usize, ModPath)> = {
+            let mut playlist = playlist.lock().unwrap();
+            let mut pending = Vec::with_capacity(BATCH_SIZE);
+            let mut n_remaining = 0;
+            for item in playlist.items.iter() {
+                if item.metadata.is_none() && !visited.contains(&item.id) {
+                    n_remaining += 1;
+                    if pending.len() < BATCH_SIZE {
+                        pending.push((item.id, item.mod_path.clone()));
+                    }
+                }
+            }
+            let n_items = playlist.items.len();
+            playlist.metadata_scan_progress =
+                (n_remaining > 0).then_some((n_items - n_remaining, n_items));
+            pending
+        };
+
+        if pending.is_empty() {
+            save_cache(&cache, &mut n_unsaved);
+            // Unparked when asked to stop.
+            std::thread::park_timeout(IDLE_INTERVAL);
+            continue;
+        }
+
+        let mut scanned = Vec::with_capacity(pending.len());
+        for (id, mod_path) in pending {
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+            visited.insert(id);
+            if let Some(metadata) = cache.get(&mod_path) {
+                scanned.push((id, metadata));
+                continue;
+            }
+            match open_module_for_metadata(&mod_path) {
+                Ok(mut module) => {
+                    let metadata = ModMetadata::from_module(&mut module);
+                    cache.insert(&mod_path, metadata.clone());
+                    n_unsaved += 1;
+                    scanned.push((id, metadata));
+                }
+                Err(e) => {
+                    log::debug!(
+                        "Cannot scan metadata of {}: {}",
+                        mod_path.display_full_name(),
+                        e
+                    );
+                }
+            }
+        }
+
+        if !scanned.is_empty() {
+            let mut playlist = playlist.lock().unwrap();
+            for (id, metadata) in scanned {
+                playlist.set_metadata(id, metadata);
+            }
+            // Titles may now match the filter.
+            if let Some(filter_string) = playlist.get_filter_string() {
+                playlist.update_filter(filter_string);
+            }
+        }
+
+        if n_unsaved >= SAVE_INTERVAL {
+            save_cache(&cache, &mut n_unsaved);
+        }
+    }
+
+    save_cache(&cache, &mut n_unsaved);
+}
+
+/// Save the cache if any module was added since it was last saved.
+fn save_cache(cache: &MetadataCache, n_unsaved: &mut usize) {
+    if *n_unsaved == 0 {
+        return;
+    }
+    if let Err(e) = cache.save() {
+        log::warn!("Failed to save the metadata cache: {}", e);
+    }
+    *n_unsaved = 0;
+}
@@ -0,0 +1,236 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::HashSet,
+    io::{Seek, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use openmpt::module::Module;
+
+use crate::{
+    control::ModuleControl,
+    module_file::{apply_mod_settings, open_module_from_mod_path},
+    playlist::{ModPath, PlayList},
+};
+
+/// Frames rendered in each call to libopenmpt.
+const BUFFER_FRAMES: usize = 4096;
+
+const CHANNELS: usize = 2;
+
+fn wav_spec(sample_rate: usize) -> WavSpec {
+    WavSpec {
+        channels: CHANNELS as u16,
+        sample_rate: sample_rate as u32,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    }
+}
+
+/// Render the module to the end, calling `on_progress` with the position in seconds after each
+/// buffer.
+fn write_module<W: Write + Seek>(
+    writer: &mut WavWriter<W>,
+    module: &mut Module,
+    sample_rate: usize,
+    mut on_progress: impl FnMut(f64),
+) -> Result<()> {
+    let mut buf = vec![0.0f32; BUFFER_FRAMES * CHANNELS];
+    loop {
+        let frames = module.read_interleaved_float_stereo(sample_rate as i32, &mut buf);
+        if frames == 0 {
+            break;
+        }
+        for sample in buf[..frames * CHANNELS].iter() {
+            writer.write_sample(*sample)?;
+        }
+        on_progress(module.get_position_seconds());
+    }
+    Ok(())
+}
+
+/// The name of the WAV file to render the module to, e.g. `song.mod.wav`.
+pub fn wav_file_name(mod_path: &ModPath) -> String {
+    let display_name = mod_path.display_name();
+    // Entries in archives may be in directories.
+    let file_name = Path::new(&display_name)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or(display_name);
+    format!("{}.wav", file_name)
+}
+
+/// Open the module again and render the subsong into a WAV file, while the module keeps playing.
+/// The subsong is played once, regardless of the repeat mode.  The progress is logged.
+pub fn render_module_to_wav(
+    mod_path: &ModPath,
+    subsong: usize,
+    control: &ModuleControl,
+    sample_rate: usize,
+    path: &Path,
+) -> Result<()> {
+    // Log every quarter of the module.
+    let mut next_quarter = 1;
+    render_file(
+        mod_path,
+        Some(subsong),
+        control,
+        sample_rate,
+        path,
+        |position, duration| {
+            if duration > 0.0
+                && next_quarter < 4
+                && position >= duration * next_quarter as f64 / 4.0
+            {
+                log::info!(
+                    "Rendering {}: {}%",
+                    path.to_string_lossy(),
+                    next_quarter * 25
+                );
+                next_quarter += 1;
+            }
+        },
+    )
+}
+
+/// Render the module once into a WAV file, calling `on_progress` with the position and the
+/// duration in seconds.  The file is not created if the module cannot be opened.
+fn render_file(
+    mod_path: &ModPath,
+    subsong: Option<usize>,
+    control: &ModuleControl,
+    sample_rate: usize,
+    path: &Path,
+    mut on_progress: impl FnMut(f64, f64),
+) -> Result<()> {
+    let mut module = open_module_from_mod_path(mod_path)?;
+    if let Some(subsong) = subsong {
+        if !module.select_subsong(subsong as i32) {
+            log::warn!(
+                "Failed to select subsong {}.  Rendering the default one.",
+                subsong
+            );
+        }
+    }
+    apply_mod_settings(&mut module, control);
+    module.set_repeat_count(0);
+
+    let duration = module.get_duration_seconds();
+    let mut writer = WavWriter::create(path, wav_spec(sample_rate))?;
+    write_module(&mut writer, &mut module, sample_rate, |position| {
+        on_progress(position, duration)
+    })?;
+    writer.finalize()?;
+    Ok(())
+}
+
+/// Render each module in the playlist into its own WAV file in `dir`, named by `wav_file_name`.
+/// Modules that fail to open are skipped, and reported at the end as an error.
+pub fn render_playlist_to_dir(
+    playlist: &PlayList,
+    control: &ModuleControl,
+    sample_rate: usize,
+    dir: &Path,
+) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+
+    let n_items = playlist.items.len();
+    let mut used_names = HashSet::new();
+    let mut failed = Vec::new();
+
+    for (i, item) in playlist.items.iter().enumerate() {
+        // Modules of the same name in different directories must not overwrite each other.
+        let base_name = wav_file_name(&item.mod_path);
+        let file_name = (1..)
+            .map(|n| {
+                if n == 1 {
+                    base_name.clone()
+                } else {
+                    format!("{} ({}).wav", base_name.trim_end_matches(".wav"), n)
+                }
+            })
+            .find(|name| used_names.insert(name.clone()))
+            .unwrap();
+        let path = dir.join(file_name);
+
+        let full_name = item.mod_path.display_full_name();
+        println!(
+            "Rendering {}/{}: {} -> {}",
+            i + 1,
+            n_items,
+            full_name,
+            path.to_string_lossy()
+        );
+        if let Err(e) = render_file(&item.mod_path, None, control, sample_rate, &path, |_, _| {}) {
+            println!("Failed to render {}: {}", full_name, e);
+            failed.push(full_name);
+        }
+    }
+
+    println!(
+        "Rendered {} of {} modules to {}",
+        n_items - failed.len(),
+        n_items,
+        dir.to_string_lossy()
+    );
+    if !failed.is_empty() {
+        println!("Failed modules:");
+        for name in failed.iter() {
+            println!("    {}", name);
+        }
+        bail!("{} of {} modules failed to render", failed.len(), n_items);
+    }
+    Ok(())
+}
+
+/// Render every module in the playlist, one after another, into a single WAV file instead of
+/// playing them.  Each module is played once, regardless of the repeat mode.
+pub fn render_playlist_to_wav(
+    playlist: &mut PlayList,
+    control: &ModuleControl,
+    sample_rate: usize,
+    path: &Path,
+) -> Result<()> {
+    let mut writer = WavWriter::create(path, wav_spec(sample_rate))?;
+
+    playlist.stop_at_end = true;
+    let n_items = playlist.items.len();
+    let mut n_rendered = 0;
+
+    while let Some(mut module) = playlist.poll_module() {
+        apply_mod_settings(&mut module, control);
+        module.set_repeat_count(0);
+
+        let name = playlist
+            .now_playing_in_items
+            .and_then(|i| playlist.items.get(i))
+            .map(|item| item.mod_path.display_full_name())
+            .unwrap_or_default();
+        n_rendered += 1;
+        eprintln!("Rendering {}/{}: {}", n_rendered, n_items, name);
+
+        write_module(&mut writer, &mut module, sample_rate, |_| {})?;
+    }
+
+    writer.finalize()?;
+    eprintln!(
+        "Rendered {} modules to {}",
+        n_rendered,
+        path.to_string_lossy()
+    );
+    Ok(())
+}
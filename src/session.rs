@@ -0,0 +1,184 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::{path::PathBuf, str::FromStr};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    app::AppState,
+    control::{ModuleControl, RepeatMode},
+    playlist::PlayList,
+    util::IsSomeAnd,
+};
+
+/// The state saved on quit so that the next run with the same paths can continue from there.
+/// The control settings are restored even if the paths are different.
+pub struct Session {
+    pub paths: Vec<String>,
+    pub now_playing_in_items: Option<usize>,
+    /// The `display_full_name` of the item being played.  Used to find the item again in case
+    /// the index no longer points to the same item.
+    pub now_playing_name: Option<String>,
+    pub order: usize,
+    pub control: ModuleControl,
+}
+
+fn session_file_path() -> Option<PathBuf> {
+    dirs::state_dir().map(|dir| dir.join("tuimodplayer").join("session"))
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    value
+        .parse::<T>()
+        .with_context(|| format!("Invalid value for {}: {}", key, value))
+}
+
+impl Session {
+    pub fn from_app_state(app_state: &AppState) -> Self {
+        let (now_playing_in_items, now_playing_name) = {
+            let playlist = app_state.playlist.lock().unwrap();
+            let now_playing_in_items = playlist.now_playing_in_items;
+            let now_playing_name = now_playing_in_items
+                .and_then(|i| playlist.items.get(i))
+                .map(|item| item.mod_path.display_full_name());
+            (now_playing_in_items, now_playing_name)
+        };
+        let order = app_state
+            .play_state
+            .as_ref()
+            .map(|ps| ps.moment_state.read().order)
+            .unwrap_or(0);
+        Self {
+            paths: app_state.options.paths.clone(),
+            now_playing_in_items,
+            now_playing_name,
+            order,
+            control: app_state.control.clone(),
+        }
+    }
+
+    /// Load the session of the last run.  Return `None` if there is no saved session.
+    pub fn load() -> Result<Option<Self>> {
+        let Some(path) = session_file_path() else {
+            return Ok(None);
+        };
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        Self::parse(&content).map(Some)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = session_file_path().context("Cannot determine the state directory")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, self.to_string())?;
+        log::info!("Saved session to {}", path.to_string_lossy());
+        Ok(())
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let mut session = Self {
+            paths: vec![],
+            now_playing_in_items: None,
+            now_playing_name: None,
+            order: 0,
+            control: ModuleControl::default(),
+        };
+        let control = &mut session.control;
+
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key {
+                "path" => session.paths.push(value.to_string()),
+                "now_playing" => session.now_playing_in_items = Some(parse_value(key, value)?),
+                "now_playing_name" => session.now_playing_name = Some(value.to_string()),
+                "order" => session.order = parse_value(key, value)?,
+                "tempo" => control.tempo.set_value(parse_value(key, value)?),
+                "pitch" => control.pitch.set_value(parse_value(key, value)?),
+                "gain" => control.gain.set_value(parse_value(key, value)?),
+                "stereo_separation" => control
+                    .stereo_separation
+                    .set_value(parse_value(key, value)?),
+                "filter_taps" => control.filter_taps.set_value(parse_value(key, value)?),
+                "volume_ramping" => control.volume_ramping.set_value(parse_value(key, value)?),
+                "repeat" => {
+                    control.repeat = RepeatMode::from_name(value)
+                        .with_context(|| format!("Invalid value for {}: {}", key, value))?
+                }
+                _ => log::debug!("Unknown session key: {}", key),
+            }
+        }
+
+        Ok(session)
+    }
+
+    /// Set the playlist to start from the item played in this session.  Return false if the item
+    /// is not in the playlist, or is hidden by the filter or the scope.
+    pub fn restore_playlist(&self, playlist: &mut PlayList) -> bool {
+        let index_matches = |i: usize| match self.now_playing_name {
+            None => true,
+            Some(ref name) => playlist
+                .items
+                .get(i)
+                .is_some_and2(|item| item.mod_path.display_full_name() == *name),
+        };
+        let maybe_index = match self.now_playing_in_items {
+            Some(i) if i < playlist.items.len() && index_matches(i) => Some(i),
+            _ => self.now_playing_name.as_ref().and_then(|name| {
+                playlist
+                    .items
+                    .iter()
+                    .position(|item| item.mod_path.display_full_name() == *name)
+            }),
+        };
+        match maybe_index {
+            Some(index) if playlist.play_items_index(index) => {
+                log::info!("Resuming from item {}", index);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for path in self.paths.iter() {
+            writeln!(f, "path={}", path)?;
+        }
+        if let Some(now_playing) = self.now_playing_in_items {
+            writeln!(f, "now_playing={}", now_playing)?;
+        }
+        if let Some(ref name) = self.now_playing_name {
+            writeln!(f, "now_playing_name={}", name)?;
+        }
+        writeln!(f, "order={}", self.order)?;
+        let control = &self.control;
+        writeln!(f, "tempo={}", control.tempo.value())?;
+        writeln!(f, "pitch={}", control.pitch.value())?;
+        writeln!(f, "gain={}", control.gain.value())?;
+        writeln!(f, "stereo_separation={}", control.stereo_separation.value())?;
+        writeln!(f, "filter_taps={}", control.filter_taps.value())?;
+        writeln!(f, "volume_ramping={}", control.volume_ramping.value())?;
+        writeln!(f, "repeat={}", control.repeat.name())
+    }
+}
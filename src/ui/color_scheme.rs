@@ -0,0 +1,441 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use tui::style::{Color, Modifier, Style};
+
+/// Themes that can be given to `--theme` by name.
+const PRESETS: &[(&str, &str)] = &[("light", LIGHT_THEME), ("mono", MONO_THEME)];
+
+/// Dark text on a light background.
+const LIGHT_THEME: &str = r##"
+[normal]
+fg = "black"
+bg = "white"
+
+[key]
+fg = "black"
+bg = "white"
+
+[block_title]
+fg = "black"
+
+[focused_border]
+fg = "blue"
+
+[vu_bar]
+fg = "blue"
+bg = "white"
+
+[list_failed]
+fg = "gray"
+bg = "white"
+
+[list_highlight]
+fg = "white"
+bg = "blue"
+
+[list_playing]
+fg = "blue"
+bg = "white"
+
+[list_favorite]
+fg = "#806000"
+bg = "white"
+
+[log_error]
+bg = "white"
+
+[log_warn]
+bg = "white"
+
+[log_info]
+bg = "white"
+
+[log_debug]
+bg = "white"
+
+[log_trace]
+fg = "#806000"
+bg = "white"
+
+[log_target]
+fg = "dark_gray"
+bg = "white"
+
+[log_message]
+fg = "black"
+bg = "white"
+"##;
+
+/// No colors other than the default ones of the terminal.  Only modifiers tell styles apart.
+const MONO_THEME: &str = r##"
+[normal]
+fg = "reset"
+bg = "reset"
+
+[key]
+fg = "reset"
+bg = "reset"
+
+[block_title]
+fg = "reset"
+
+[focused_border]
+fg = "reset"
+modifiers = ["bold"]
+
+[vu_bar]
+fg = "reset"
+bg = "reset"
+
+[list_failed]
+fg = "reset"
+bg = "reset"
+modifiers = ["dim", "crossed_out"]
+
+[list_highlight]
+fg = "reset"
+bg = "reset"
+modifiers = ["bold", "reversed"]
+
+[list_playing]
+fg = "reset"
+bg = "reset"
+modifiers = ["bold", "underlined"]
+
+[list_favorite]
+fg = "reset"
+bg = "reset"
+modifiers = ["italic"]
+
+[log_error]
+fg = "reset"
+bg = "reset"
+
+[log_warn]
+fg = "reset"
+bg = "reset"
+
+[log_info]
+fg = "reset"
+bg = "reset"
+
+[log_debug]
+fg = "reset"
+bg = "reset"
+
+[log_trace]
+fg = "reset"
+bg = "reset"
+
+[log_target]
+fg = "reset"
+bg = "reset"
+
+[log_message]
+fg = "reset"
+bg = "reset"
+"##;
+
+/// The styles of the UI elements.
+///
+/// A theme file is a TOML file with a table for each style to change, e.g.
+///
+/// ```toml
+/// [list_playing]
+/// fg = "yellow"
+/// bg = "#202020"
+/// modifiers = ["bold"]
+/// ```
+///
+/// Styles and attributes not in the file are taken from the default scheme.
+#[derive(Clone)]
+pub struct ColorScheme {
+    pub normal: Style,
+    pub key: Style,
+    pub block_title: Style,
+    pub focused_border: Style,
+    pub vu_bar: Style,
+    pub list_failed: Style,
+    pub list_highlight: Style,
+    pub list_playing: Style,
+    pub list_favorite: Style,
+    /// Patched over the parts of the items matched by the filter.
+    pub list_match: Style,
+    pub log_error: Style,
+    pub log_warn: Style,
+    pub log_info: Style,
+    pub log_debug: Style,
+    pub log_trace: Style,
+    pub log_target: Style,
+    pub log_message: Style,
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        Self {
+            normal: Style::default().fg(Color::White).bg(Color::Black),
+            key: Style::default()
+                .fg(Color::White)
+                .bg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+            block_title: Style::default()
+                .fg(Color::White)
+                .add_modifier(Modifier::BOLD),
+            focused_border: Style::default().fg(Color::LightGreen),
+            vu_bar: Style::default().fg(Color::LightGreen).bg(Color::Black),
+            list_failed: Style::default()
+                .fg(Color::DarkGray)
+                .bg(Color::Black)
+                .add_modifier(Modifier::CROSSED_OUT),
+            list_highlight: Style::default()
+                .fg(Color::Black)
+                .bg(Color::LightGreen)
+                .add_modifier(Modifier::BOLD),
+            list_playing: Style::default()
+                .fg(Color::LightGreen)
+                .bg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+            list_favorite: Style::default().fg(Color::Yellow).bg(Color::Black),
+            list_match: Style::default().add_modifier(Modifier::UNDERLINED),
+            log_error: Style::default()
+                .fg(Color::Red)
+                .bg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+            log_warn: Style::default()
+                .fg(Color::Magenta)
+                .bg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+            log_info: Style::default()
+                .fg(Color::Green)
+                .bg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+            log_debug: Style::default()
+                .fg(Color::Blue)
+                .bg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+            log_trace: Style::default()
+                .fg(Color::Yellow)
+                .bg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+            log_target: Style::default()
+                .fg(Color::Gray)
+                .bg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+            log_message: Style::default().fg(Color::White).bg(Color::Black),
+        }
+    }
+}
+
+impl ColorScheme {
+    /// Load a preset by name, or a theme file.  A file of the same name as a preset takes
+    /// precedence.
+    pub fn load(theme: &str) -> Result<Self> {
+        let path = Path::new(theme);
+        if !path.exists() {
+            if let Some((_, preset)) = PRESETS.iter().find(|(name, _)| *name == theme) {
+                return Self::parse(preset).with_context(|| format!("Bad preset theme {}", theme));
+            }
+        }
+        let content = std::fs::read_to_string(path).with_context(|| {
+            let preset_names = PRESETS.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            format!(
+                "Cannot read theme file {}.  Preset themes: {}",
+                theme,
+                preset_names.join(", ")
+            )
+        })?;
+        Self::parse(&content).with_context(|| format!("Bad theme file {}", theme))
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let table = content.parse::<toml::Table>()?;
+        let mut color_scheme = Self::default();
+        for (name, value) in table.iter() {
+            let style = color_scheme
+                .style_mut(name)
+                .ok_or_else(|| anyhow!("Unknown style {:?}", name))?;
+            let attributes = value
+                .as_table()
+                .ok_or_else(|| anyhow!("Style {:?} is not a table", name))?;
+            apply_attributes(style, attributes).with_context(|| format!("In style {:?}", name))?;
+        }
+        Ok(color_scheme)
+    }
+
+    fn style_mut(&mut self, name: &str) -> Option<&mut Style> {
+        let style = match name {
+            "normal" => &mut self.normal,
+            "key" => &mut self.key,
+            "block_title" => &mut self.block_title,
+            "focused_border" => &mut self.focused_border,
+            "vu_bar" => &mut self.vu_bar,
+            "list_failed" => &mut self.list_failed,
+            "list_highlight" => &mut self.list_highlight,
+            "list_playing" => &mut self.list_playing,
+            "list_favorite" => &mut self.list_favorite,
+            "list_match" => &mut self.list_match,
+            "log_error" => &mut self.log_error,
+            "log_warn" => &mut self.log_warn,
+            "log_info" => &mut self.log_info,
+            "log_debug" => &mut self.log_debug,
+            "log_trace" => &mut self.log_trace,
+            "log_target" => &mut self.log_target,
+            "log_message" => &mut self.log_message,
+            _ => return None,
+        };
+        Some(style)
+    }
+}
+
+/// Override the attributes of `style` given in the theme.  `modifiers` replaces the modifiers of
+/// the default style rather than adding to them.
+fn apply_attributes(style: &mut Style, attributes: &toml::Table) -> Result<()> {
+    for (attribute, value) in attributes.iter() {
+        match attribute.as_str() {
+            "fg" => style.fg = Some(parse_color(value)?),
+            "bg" => style.bg = Some(parse_color(value)?),
+            "modifiers" => {
+                let names = value
+                    .as_array()
+                    .ok_or_else(|| anyhow!("modifiers must be an array of names"))?;
+                let mut modifiers = Modifier::empty();
+                for name in names {
+                    modifiers |= parse_modifier(name)?;
+                }
+                style.add_modifier = modifiers;
+                style.sub_modifier = Modifier::empty();
+            }
+            _ => bail!("Unknown attribute {:?}", attribute),
+        }
+    }
+    Ok(())
+}
+
+/// Parse a color name like `light_green`, or `#rrggbb`.
+fn parse_color(value: &toml::Value) -> Result<Color> {
+    let name = value
+        .as_str()
+        .ok_or_else(|| anyhow!("A color must be a string, not {}", value))?;
+    if let Some(hex) = name.strip_prefix('#') {
+        if hex.len() == 6 {
+            if let Ok(rgb) = u32::from_str_radix(hex, 16) {
+                return Ok(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+            }
+        }
+        bail!("Bad color {:?}.  Expected #rrggbb", name);
+    }
+    let color = match name.to_ascii_lowercase().replace('-', "_").as_str() {
+        "reset" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" => Color::Gray,
+        "dark_gray" => Color::DarkGray,
+        "light_red" => Color::LightRed,
+        "light_green" => Color::LightGreen,
+        "light_yellow" => Color::LightYellow,
+        "light_blue" => Color::LightBlue,
+        "light_magenta" => Color::LightMagenta,
+        "light_cyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => bail!("Unknown color {:?}", name),
+    };
+    Ok(color)
+}
+
+fn parse_modifier(value: &toml::Value) -> Result<Modifier> {
+    let name = value
+        .as_str()
+        .ok_or_else(|| anyhow!("A modifier must be a string, not {}", value))?;
+    let modifier = match name {
+        "bold" => Modifier::BOLD,
+        "dim" => Modifier::DIM,
+        "italic" => Modifier::ITALIC,
+        "underlined" => Modifier::UNDERLINED,
+        "slow_blink" => Modifier::SLOW_BLINK,
+        "rapid_blink" => Modifier::RAPID_BLINK,
+        "reversed" => Modifier::REVERSED,
+        "hidden" => Modifier::HIDDEN,
+        "crossed_out" => Modifier::CROSSED_OUT,
+        _ => bail!("Unknown modifier {:?}", name),
+    };
+    Ok(modifier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_parse() {
+        for (name, preset) in PRESETS {
+            if let Err(e) = ColorScheme::parse(preset) {
+                panic!("Bad preset theme {}: {:#}", name, e);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_overrides_only_given_attributes() {
+        let color_scheme = ColorScheme::parse(
+            r##"
+            [list_playing]
+            fg = "#20a0ff"
+            modifiers = ["italic"]
+            "##,
+        )
+        .unwrap();
+        let default = ColorScheme::default();
+        assert_eq!(
+            color_scheme.list_playing.fg,
+            Some(Color::Rgb(0x20, 0xa0, 0xff))
+        );
+        assert_eq!(color_scheme.list_playing.bg, default.list_playing.bg);
+        assert_eq!(color_scheme.list_playing.add_modifier, Modifier::ITALIC);
+        assert_eq!(color_scheme.normal, default.normal);
+    }
+
+    #[test]
+    fn parse_color_names_and_hex() {
+        let color = |name: &str| parse_color(&toml::Value::from(name));
+        assert_eq!(color("light_green").unwrap(), Color::LightGreen);
+        assert_eq!(color("Dark-Gray").unwrap(), Color::DarkGray);
+        assert_eq!(color("#806000").unwrap(), Color::Rgb(0x80, 0x60, 0x00));
+        assert!(color("#80600").is_err());
+        assert!(color("#80600g").is_err());
+        assert!(color("orange").is_err());
+        assert!(parse_color(&toml::Value::from(1)).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_unknown_names() {
+        for theme in [
+            "[normal]\nfg = \"orange\"",
+            "[no_such_style]\nfg = \"red\"",
+            "[normal]\nunderline = \"red\"",
+            "[normal]\nmodifiers = [\"blinking\"]",
+            "[normal]\nmodifiers = \"bold\"",
+            "normal = \"red\"",
+        ] {
+            assert!(ColorScheme::parse(theme).is_err(), "{}", theme);
+        }
+    }
+}
@@ -11,7 +11,9 @@
 // You should have received a copy of the GNU General Public License along with TUIModPlayer. If
 // not, see <https://www.gnu.org/licenses/>.
 
-use crate::app::{AppState, UiMode};
+use crate::app::{AppState, Focus, UiMode};
+
+use super::keymap::{Action, KeyCombo, KeyMap};
 
 use crossterm::event::{self, KeyModifiers};
 
@@ -23,81 +25,17 @@ pub enum HandleKeyResult {
     Quit,
 }
 
-pub fn handle_key_event(ev: &Event, app_state: &mut AppState) -> HandleKeyResult {
+pub fn handle_key_event(ev: &Event, app_state: &mut AppState, key_map: &KeyMap) -> HandleKeyResult {
     match app_state.ui_mode {
         UiMode::Normal => {
-            #[allow(clippy::single_match)] // Will add more event handling in the future
-            #[allow(clippy::collapsible_match)]
-            match ev {
-                Event::Key(KeyEvent {
-                    code, modifiers, ..
-                }) => match code {
-                    KeyCode::Char('l') if modifiers.contains(KeyModifiers::CONTROL) => {
-                        return HandleKeyResult::Redraw;
-                    }
-                    KeyCode::Char('q') => {
-                        return HandleKeyResult::Quit;
-                    }
-                    KeyCode::Char('m') => {
-                        app_state.next();
-                    }
-                    KeyCode::Char('n') => {
-                        app_state.prev();
-                    }
-                    KeyCode::Char('M') => {
-                        app_state.next10();
-                    }
-                    KeyCode::Char('N') => {
-                        app_state.prev10();
-                    }
-                    KeyCode::Char('u') => {
-                        app_state.tempo_down();
-                    }
-                    KeyCode::Char('i') => {
-                        app_state.tempo_up();
-                    }
-                    KeyCode::Char('o') => {
-                        app_state.pitch_down();
-                    }
-                    KeyCode::Char('p') => {
-                        app_state.pitch_up();
-                    }
-                    KeyCode::Char('3') => {
-                        app_state.gain_down();
-                    }
-                    KeyCode::Char('4') => {
-                        app_state.gain_up();
-                    }
-                    KeyCode::Char('5') => {
-                        app_state.stereo_separation_down();
-                    }
-                    KeyCode::Char('6') => {
-                        app_state.stereo_separation_up();
-                    }
-                    KeyCode::Char('7') => {
-                        app_state.filter_taps_down();
-                    }
-                    KeyCode::Char('8') => {
-                        app_state.filter_taps_up();
-                    }
-                    KeyCode::Char('9') => {
-                        app_state.volume_ramping_down();
-                    }
-                    KeyCode::Char('0') => {
-                        app_state.volume_ramping_up();
-                    }
-                    KeyCode::Char('r') => {
-                        app_state.toggle_repeat();
-                    }
-                    KeyCode::Char(' ') => {
-                        app_state.pause_resume();
-                    }
-                    KeyCode::Char('/') => {
-                        app_state.ui_mode = UiMode::Filter;
-                    }
-                    _ => {}
-                },
-                _ => {}
+            if let Event::Key(key_event) = ev {
+                if app_state.show_help && key_event.code == KeyCode::Esc {
+                    app_state.show_help = false;
+                    return HandleKeyResult::Nothing;
+                }
+                if let Some(action) = bound_action(key_event, key_map) {
+                    return perform_action(action, app_state);
+                }
             }
         }
         UiMode::Filter => {
@@ -115,6 +53,10 @@ pub fn handle_key_event(ev: &Event, app_state: &mut AppState) -> HandleKeyResult
                     KeyCode::Enter => {
                         app_state.ui_mode = UiMode::Normal;
                     }
+                    KeyCode::Tab => {
+                        let mut playlist = app_state.playlist.lock().unwrap();
+                        playlist.cycle_filter_mode();
+                    }
                     KeyCode::Backspace => {
                         let mut playlist = app_state.playlist.lock().unwrap();
                         playlist.update_filter_pop();
@@ -128,7 +70,338 @@ pub fn handle_key_event(ev: &Event, app_state: &mut AppState) -> HandleKeyResult
                 _ => {}
             }
         }
+        UiMode::AddPath => handle_text_input(ev, app_state, |_| true, AppState::add_path),
+        UiMode::ExportView => handle_text_input(ev, app_state, |_| true, AppState::export_view),
+        UiMode::JumpTo => handle_text_input(
+            ev,
+            app_state,
+            |ch| ch.is_ascii_digit(),
+            |app_state, input| app_state.jump_to(&input),
+        ),
+        UiMode::SetTempo | UiMode::SetPitch => {
+            if let Event::Key(KeyEvent { code, .. }) = ev {
+                match code {
+                    KeyCode::Esc => {
+                        app_state.input.clear();
+                        app_state.ui_mode = UiMode::Normal;
+                    }
+                    KeyCode::Enter => app_state.set_typed_control(),
+                    KeyCode::Backspace => {
+                        app_state.input.pop();
+                    }
+                    KeyCode::Char(ch) if ch.is_ascii_digit() || matches!(ch, '.' | '-' | '+') => {
+                        app_state.input.push(*ch);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        UiMode::SelectDevice => {
+            if let Event::Key(KeyEvent { code, .. }) = ev {
+                match code {
+                    KeyCode::Esc => app_state.ui_mode = UiMode::Normal,
+                    KeyCode::Enter => app_state.select_device(),
+                    KeyCode::Up => app_state.move_device_cursor(-1),
+                    KeyCode::Down => app_state.move_device_cursor(1),
+                    _ => {}
+                }
+            }
+        }
+        UiMode::Goto => handle_text_input(
+            ev,
+            app_state,
+            |ch| ch.is_ascii_digit(),
+            |app_state, input| app_state.goto_order(&input),
+        ),
+    }
+
+    HandleKeyResult::Nothing
+}
+
+/// Edit `AppState::input` in the modes where a line is typed.  Only the characters `accepts`
+/// returns true for are added.  On enter, go back to the normal mode and call `on_enter` with the
+/// line.
+fn handle_text_input(
+    ev: &Event,
+    app_state: &mut AppState,
+    accepts: impl Fn(char) -> bool,
+    on_enter: impl FnOnce(&mut AppState, String),
+) {
+    if let Event::Key(KeyEvent { code, .. }) = ev {
+        match code {
+            KeyCode::Esc => {
+                app_state.input.clear();
+                app_state.ui_mode = UiMode::Normal;
+            }
+            KeyCode::Enter => {
+                let input = std::mem::take(&mut app_state.input);
+                app_state.ui_mode = UiMode::Normal;
+                on_enter(app_state, input);
+            }
+            KeyCode::Backspace => {
+                app_state.input.pop();
+            }
+            KeyCode::Char(ch) if accepts(*ch) => {
+                app_state.input.push(*ch);
+            }
+            _ => {}
+        }
     }
+}
 
+/// The action the key performs in the normal mode.
+fn bound_action(key_event: &KeyEvent, key_map: &KeyMap) -> Option<Action> {
+    key_map.get(key_event).or_else(|| default_action(key_event))
+}
+
+/// The keys bound to each action, as listed in the help overlay.  Found by trying the keys
+/// `bound_action` may be asked about, so that it agrees with the key binding file.
+pub fn key_help(key_map: &KeyMap) -> Vec<(String, &'static str)> {
+    let chars = (' '..='~').map(KeyCode::Char);
+    let others = [
+        KeyCode::Enter,
+        KeyCode::Esc,
+        KeyCode::Tab,
+        KeyCode::Backspace,
+        KeyCode::Delete,
+        KeyCode::Insert,
+        KeyCode::Left,
+        KeyCode::Right,
+        KeyCode::Up,
+        KeyCode::Down,
+        KeyCode::PageUp,
+        KeyCode::PageDown,
+        KeyCode::Home,
+        KeyCode::End,
+    ]
+    .into_iter()
+    .chain((1..=12).map(KeyCode::F));
+    let modifier_sets = [
+        KeyModifiers::SHIFT,
+        KeyModifiers::CONTROL,
+        KeyModifiers::ALT,
+    ];
+    let mut bindings: Vec<(KeyCombo, Action)> = vec![];
+    for code in chars.chain(others) {
+        let plain = KeyCombo::new(code, KeyModifiers::NONE);
+        let plain_action = bound_action(&plain.to_event(), key_map);
+        bindings.extend(plain_action.map(|action| (plain, action)));
+        for modifiers in modifier_sets {
+            let combo = KeyCombo::new(code, modifiers);
+            let action = bound_action(&combo.to_event(), key_map);
+            // Most default keys ignore the modifiers.  Only list the combinations that differ.
+            if combo != plain && action != plain_action {
+                bindings.extend(action.map(|action| (combo, action)));
+            }
+        }
+    }
+
+    Action::ALL
+        .iter()
+        .filter_map(|action| {
+            let keys = bindings
+                .iter()
+                .filter(|(_, bound)| bound == action)
+                .map(|(combo, _)| combo.to_string())
+                .collect::<Vec<_>>();
+            (!keys.is_empty()).then(|| (keys.join(" "), action.name()))
+        })
+        .collect()
+}
+
+/// The action bound to the key if the key binding file does not bind it.
+fn default_action(key_event: &KeyEvent) -> Option<Action> {
+    let KeyEvent {
+        code, modifiers, ..
+    } = key_event;
+    let action = match code {
+        KeyCode::Char('l') if modifiers.contains(KeyModifiers::CONTROL) => Action::Redraw,
+        KeyCode::Char('s') if modifiers.contains(KeyModifiers::CONTROL) => Action::SavePlaylist,
+        KeyCode::Char('q') => Action::Quit,
+        KeyCode::Char('m') => Action::Next,
+        KeyCode::Char('n') => Action::Prev,
+        KeyCode::Char('M') => Action::Next10,
+        KeyCode::Char('N') => Action::Prev10,
+        KeyCode::Char('u') => Action::TempoDown,
+        KeyCode::Char('i') => Action::TempoUp,
+        KeyCode::Char('o') => Action::PitchDown,
+        KeyCode::Char('p') => Action::PitchUp,
+        KeyCode::Char('3') => Action::GainDown,
+        KeyCode::Char('4') => Action::GainUp,
+        KeyCode::Char('5') => Action::StereoSeparationDown,
+        KeyCode::Char('6') => Action::StereoSeparationUp,
+        KeyCode::Char('7') => Action::FilterTapsDown,
+        KeyCode::Char('8') => Action::FilterTapsUp,
+        KeyCode::Char('9') => Action::VolumeRampingDown,
+        KeyCode::Char('0') => Action::VolumeRampingUp,
+        KeyCode::Char('=') => Action::ResetControls,
+        KeyCode::Char('-') => Action::VolumeDown,
+        KeyCode::Char('+') => Action::VolumeUp,
+        KeyCode::Char('U') => Action::SetTempo,
+        KeyCode::Char('P') => Action::SetPitch,
+        KeyCode::Char('r') => Action::CycleRepeat,
+        KeyCode::Char('b') => Action::ToggleStopAfterCurrent,
+        KeyCode::Char(' ') => Action::PauseResume,
+        KeyCode::Char('[') => Action::SetLoopA,
+        KeyCode::Char(']') => Action::SetLoopB,
+        KeyCode::Char('\\') => Action::ClearLoop,
+        KeyCode::Char(',') => Action::PrevSubsong,
+        KeyCode::Char('.') => Action::NextSubsong,
+        KeyCode::Char('<') => Action::PrevOrder,
+        KeyCode::Char('>') => Action::NextOrder,
+        KeyCode::Char('x') => Action::ToggleSongMessage,
+        KeyCode::Char('?') => Action::ToggleHelp,
+        KeyCode::Char('/') => Action::Filter,
+        KeyCode::Char('e') => Action::ToggleQueued,
+        KeyCode::Char('*') => Action::ToggleFavorite,
+        KeyCode::Char('R') => Action::Rescan,
+        KeyCode::Char('w') => Action::ExportPlaylist,
+        KeyCode::Char('W') => Action::ExportView,
+        KeyCode::Char('E') => Action::ExportCurrent,
+        KeyCode::Char(':') => Action::JumpTo,
+        KeyCode::Char('O') => Action::GotoOrder,
+        KeyCode::Char('a') => Action::AddPath,
+        KeyCode::Left if modifiers.contains(KeyModifiers::SHIFT) => Action::SeekBackwardLong,
+        KeyCode::Right if modifiers.contains(KeyModifiers::SHIFT) => Action::SeekForwardLong,
+        KeyCode::Left => Action::SeekBackward,
+        KeyCode::Right => Action::SeekForward,
+        KeyCode::Up if modifiers.contains(KeyModifiers::SHIFT) => Action::MoveItemUp,
+        KeyCode::Down if modifiers.contains(KeyModifiers::SHIFT) => Action::MoveItemDown,
+        KeyCode::Char('K') => Action::MoveItemUp,
+        KeyCode::Char('J') => Action::MoveItemDown,
+        KeyCode::Tab => Action::ToggleFocus,
+        KeyCode::Up => Action::Up,
+        KeyCode::Down => Action::Down,
+        KeyCode::PageUp => Action::PageUp,
+        KeyCode::PageDown => Action::PageDown,
+        KeyCode::Home => Action::Home,
+        KeyCode::End => Action::End,
+        KeyCode::Enter => Action::PlayCursor,
+        KeyCode::Char('g') => Action::PlayFirst,
+        KeyCode::Char('G') => Action::PlayLast,
+        KeyCode::Char('d') => Action::RemoveItem,
+        KeyCode::Char('s') => Action::CycleSort,
+        KeyCode::Char('S') => Action::ToggleShuffle,
+        KeyCode::Char('Z') => Action::Reshuffle,
+        KeyCode::Char('z') => Action::ToggleRandom,
+        KeyCode::Char('F') => Action::ClearFailed,
+        KeyCode::Char('f') => Action::ToggleScope,
+        KeyCode::Char('v') => Action::ToggleGrouped,
+        KeyCode::Char('c') => Action::ToggleGroup,
+        KeyCode::Char('D') => Action::FindDuplicates,
+        KeyCode::Char('X') => Action::RemoveDuplicates,
+        KeyCode::Char('t') => Action::CycleSleepTimer,
+        KeyCode::Char('A') => Action::SelectDevice,
+        KeyCode::Esc => Action::ResetCursor,
+        _ => return None,
+    };
+    Some(action)
+}
+
+fn perform_action(action: Action, app_state: &mut AppState) -> HandleKeyResult {
+    let message_focused = app_state.focus == Focus::Message;
+    match action {
+        Action::Redraw => return HandleKeyResult::Redraw,
+        Action::SavePlaylist => app_state.save_playlist(),
+        Action::Quit => return HandleKeyResult::Quit,
+        Action::Next => app_state.next(),
+        Action::Prev => app_state.prev(),
+        Action::Next10 => app_state.next10(),
+        Action::Prev10 => app_state.prev10(),
+        Action::TempoDown => app_state.tempo_down(),
+        Action::TempoUp => app_state.tempo_up(),
+        Action::PitchDown => app_state.pitch_down(),
+        Action::PitchUp => app_state.pitch_up(),
+        Action::GainDown => app_state.gain_down(),
+        Action::GainUp => app_state.gain_up(),
+        Action::StereoSeparationDown => app_state.stereo_separation_down(),
+        Action::StereoSeparationUp => app_state.stereo_separation_up(),
+        Action::FilterTapsDown => app_state.filter_taps_down(),
+        Action::FilterTapsUp => app_state.filter_taps_up(),
+        Action::VolumeRampingDown => app_state.volume_ramping_down(),
+        Action::VolumeRampingUp => app_state.volume_ramping_up(),
+        Action::ResetControls => app_state.reset_controls(),
+        Action::VolumeDown => app_state.volume_down(),
+        Action::VolumeUp => app_state.volume_up(),
+        Action::SetTempo => {
+            app_state.input.clear();
+            app_state.ui_mode = UiMode::SetTempo;
+        }
+        Action::SetPitch => {
+            app_state.input.clear();
+            app_state.ui_mode = UiMode::SetPitch;
+        }
+        Action::CycleRepeat => app_state.cycle_repeat(),
+        Action::ToggleStopAfterCurrent => app_state.toggle_stop_after_current(),
+        Action::PauseResume => app_state.pause_resume(),
+        Action::SetLoopA => app_state.set_loop_a(),
+        Action::SetLoopB => app_state.set_loop_b(),
+        Action::ClearLoop => app_state.clear_loop(),
+        Action::PrevSubsong => app_state.prev_subsong(),
+        Action::NextSubsong => app_state.next_subsong(),
+        Action::ToggleSongMessage => app_state.toggle_song_message(),
+        Action::ToggleHelp => app_state.show_help = !app_state.show_help,
+        Action::Filter => app_state.ui_mode = UiMode::Filter,
+        Action::ToggleQueued => app_state.toggle_queued(),
+        Action::ToggleFavorite => app_state.toggle_favorite(),
+        Action::Rescan => app_state.rescan(),
+        Action::ExportPlaylist => app_state.export_playlist_out(),
+        Action::JumpTo => {
+            app_state.input.clear();
+            app_state.ui_mode = UiMode::JumpTo;
+        }
+        Action::GotoOrder => {
+            app_state.input.clear();
+            app_state.ui_mode = UiMode::Goto;
+        }
+        Action::AddPath => {
+            app_state.input.clear();
+            app_state.ui_mode = UiMode::AddPath;
+        }
+        Action::ExportView => {
+            app_state.input.clear();
+            app_state.ui_mode = UiMode::ExportView;
+        }
+        Action::ExportCurrent => app_state.export_current(),
+        Action::SeekBackward => app_state.seek_backward(),
+        Action::SeekForward => app_state.seek_forward(),
+        Action::SeekBackwardLong => app_state.seek_backward_long(),
+        Action::SeekForwardLong => app_state.seek_forward_long(),
+        Action::PrevOrder => app_state.prev_order(),
+        Action::NextOrder => app_state.next_order(),
+        Action::MoveItemUp => app_state.move_item_up(),
+        Action::MoveItemDown => app_state.move_item_down(),
+        Action::ToggleFocus => app_state.toggle_focus(),
+        // The cursor keys scroll the Message pane if it is focused.
+        Action::Up if message_focused => app_state.scroll_message(-1),
+        Action::Down if message_focused => app_state.scroll_message(1),
+        Action::PageUp if message_focused => app_state.scroll_message_pages(-1),
+        Action::PageDown if message_focused => app_state.scroll_message_pages(1),
+        Action::Home if message_focused => app_state.message_scroll = 0,
+        Action::End if message_focused => app_state.scroll_message(isize::MAX),
+        Action::Up => app_state.cursor_up(),
+        Action::Down => app_state.cursor_down(),
+        Action::PageUp => app_state.cursor_page_up(),
+        Action::PageDown => app_state.cursor_page_down(),
+        Action::Home => app_state.cursor_first(),
+        Action::End => app_state.cursor_last(),
+        Action::PlayCursor => app_state.play_cursor(),
+        Action::PlayFirst => app_state.play_first(),
+        Action::PlayLast => app_state.play_last(),
+        Action::RemoveItem => app_state.remove_item(),
+        Action::CycleSort => app_state.cycle_sort(),
+        Action::ToggleShuffle => app_state.toggle_shuffle(),
+        Action::Reshuffle => app_state.reshuffle(),
+        Action::ToggleRandom => app_state.toggle_random(),
+        Action::ClearFailed => app_state.clear_failed(),
+        Action::ToggleScope => app_state.toggle_scope(),
+        Action::ToggleGrouped => app_state.toggle_grouped(),
+        Action::ToggleGroup => app_state.toggle_group(),
+        Action::FindDuplicates => app_state.find_duplicates(),
+        Action::RemoveDuplicates => app_state.remove_duplicates(),
+        Action::CycleSleepTimer => app_state.cycle_sleep_timer(),
+        Action::SelectDevice => app_state.start_selecting_device(),
+        Action::ResetCursor => app_state.reset_cursor(),
+    }
     HandleKeyResult::Nothing
 }
@@ -11,89 +11,99 @@
 // You should have received a copy of the GNU General Public License along with TUIModPlayer. If
 // not, see <https://www.gnu.org/licenses/>.
 
-use std::borrow::Cow;
+use std::{borrow::Cow, ops::Range, sync::atomic::Ordering, time::Instant};
 
 use crate::{
-    app::{AppState, UiMode},
+    app::{AppState, Focus, UiMode},
     backend::DecodeStatus,
     logging::LogRecord,
-    player::{ModuleInfo, MomentState},
-    util::{center_region, LayoutSplitN},
+    player::{ModuleInfo, MomentState, MAX_VU_CHANNELS},
+    playlist::{FilterMode, GroupRow},
+    util::{center_region, screen_width, truncate_to_width, LayoutSplitN},
 };
 
+use super::ColorScheme;
+
 use tui::{
     backend::Backend,
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
+    style::{Modifier, Style},
+    symbols,
     terminal::Frame,
     text::{Span, Spans, Text},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    widgets::{Block, Borders, Clear, LineGauge, List, ListItem, ListState, Paragraph},
 };
 
-pub fn render_ui<'a, 'f, 't, B>(frame: &'f mut Frame<'t, B>, area: Rect, app_state: &'a AppState)
-where
+/// `key_help` is listed over the other panes if `AppState::show_help` is set.
+pub fn render_ui<'a, 'f, 't, B>(
+    frame: &'f mut Frame<'t, B>,
+    area: Rect,
+    app_state: &'a AppState,
+    key_help: &[(String, &str)],
+) where
     B: Backend + 't,
     't: 'f,
 {
-    let mut ui_renderer = UIRenderer::new(app_state, frame, ColorScheme::default());
+    let mut ui_renderer = UIRenderer::new(app_state, frame, &app_state.color_scheme);
     ui_renderer.render_ui(area);
+    if let UiMode::SelectDevice = app_state.ui_mode {
+        ui_renderer.render_device_selection(area);
+    }
+    if app_state.show_help {
+        ui_renderer.render_help(area, key_help);
+    }
 }
 
-struct ColorScheme {
-    normal: Style,
-    key: Style,
-    block_title: Style,
-    list_highlight: Style,
-    log_error: Style,
-    log_warn: Style,
-    log_info: Style,
-    log_debug: Style,
-    log_trace: Style,
-    log_target: Style,
-    log_message: Style,
+/// A rectangle of the given size (or smaller, to fit) at the center of `area`.
+fn centered_rect(area: Rect, width: usize, height: usize) -> Rect {
+    let width = width.min(area.width as usize) as u16;
+    let height = height.min(area.height as usize) as u16;
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
 }
 
-impl Default for ColorScheme {
-    fn default() -> Self {
-        Self {
-            normal: Style::default().fg(Color::White).bg(Color::Black),
-            key: Style::default()
-                .fg(Color::White)
-                .bg(Color::Black)
-                .add_modifier(Modifier::BOLD),
-            block_title: Style::default()
-                .fg(Color::White)
-                .add_modifier(Modifier::BOLD),
-            list_highlight: Style::default()
-                .fg(Color::Black)
-                .bg(Color::LightGreen)
-                .add_modifier(Modifier::BOLD),
-            log_error: Style::default()
-                .fg(Color::Red)
-                .bg(Color::Black)
-                .add_modifier(Modifier::BOLD),
-            log_warn: Style::default()
-                .fg(Color::Magenta)
-                .bg(Color::Black)
-                .add_modifier(Modifier::BOLD),
-            log_info: Style::default()
-                .fg(Color::Green)
-                .bg(Color::Black)
-                .add_modifier(Modifier::BOLD),
-            log_debug: Style::default()
-                .fg(Color::Blue)
-                .bg(Color::Black)
-                .add_modifier(Modifier::BOLD),
-            log_trace: Style::default()
-                .fg(Color::Yellow)
-                .bg(Color::Black)
-                .add_modifier(Modifier::BOLD),
-            log_target: Style::default()
-                .fg(Color::Gray)
-                .bg(Color::Black)
-                .add_modifier(Modifier::BOLD),
-            log_message: Style::default().fg(Color::White).bg(Color::Black),
+/// Split `line` into spans, giving the byte ranges (sorted and not overlapping) the style
+/// `highlight`.
+fn highlight_ranges(line: String, ranges: &[Range<usize>], highlight: Style) -> Spans<'static> {
+    if ranges.is_empty() {
+        return Spans::from(line);
+    }
+    let mut spans = vec![];
+    let mut pos = 0;
+    for range in ranges {
+        if range.start < pos {
+            continue;
         }
+        if range.start > pos {
+            spans.push(Span::raw(line[pos..range.start].to_string()));
+        }
+        spans.push(Span::styled(line[range.clone()].to_string(), highlight));
+        pos = range.end;
+    }
+    if pos < line.len() {
+        spans.push(Span::raw(line[pos..].to_string()));
+    }
+    Spans::from(spans)
+}
+
+/// Format seconds as `mm:ss`.
+fn format_mm_ss(seconds: f64) -> String {
+    let total_seconds = seconds.max(0.0) as u64;
+    format!("{:02}:{:02}", total_seconds / 60, total_seconds % 60)
+}
+
+/// The name of the interpolation filter libopenmpt uses for the number of taps.
+fn filter_name(filter_taps: i32) -> Cow<'static, str> {
+    match filter_taps {
+        1 => "none".into(),
+        2 => "linear".into(),
+        4 => "cubic".into(),
+        8 => "sinc".into(),
+        _ => format!("{} taps", filter_taps).into(),
     }
 }
 
@@ -107,6 +117,16 @@ trait ThemedUIBuilder {
             .title(Span::styled(title, self.color_scheme().block_title))
     }
 
+    /// A block whose border is highlighted if it is the focused pane.
+    fn new_focusable_block<'t, S: Into<Cow<'t, str>>>(&self, title: S, focused: bool) -> Block<'t> {
+        let block = self.new_block(title);
+        if focused {
+            block.border_style(self.color_scheme().focused_border)
+        } else {
+            block
+        }
+    }
+
     fn build_state_line<'t, F: FnOnce(&mut LineBuilder<Self>)>(&self, f: F) -> Spans<'t> {
         let mut builder = LineBuilder::new(self);
         f(&mut builder);
@@ -203,12 +223,12 @@ where
 {
     app_state: &'a AppState,
     frame: &'f mut Frame<'t, B>,
-    color_scheme: ColorScheme,
+    color_scheme: &'a ColorScheme,
 }
 
 impl<B: Backend> ThemedUIBuilder for UIRenderer<'_, '_, '_, B> {
     fn color_scheme(&self) -> &ColorScheme {
-        &self.color_scheme
+        self.color_scheme
     }
 }
 
@@ -220,7 +240,7 @@ where
     pub fn new(
         app_state: &'a AppState,
         frame: &'f mut Frame<'t, B>,
-        color_scheme: ColorScheme,
+        color_scheme: &'a ColorScheme,
     ) -> Self {
         Self {
             app_state,
@@ -231,12 +251,20 @@ where
 
     const MAX_MOD_SAMPLE_NAME_LEN: usize = 22;
 
+    /// Longer durations, reported by some formats, are treated as unknown.
+    const MAX_SENSIBLE_DURATION: f64 = 24.0 * 60.0 * 60.0;
+
     pub fn render_ui(&mut self, area: Rect) {
-        let maybe_message_width = self
-            .app_state
-            .play_state
-            .as_ref()
-            .map(|ps| ps.module_info.message_width);
+        let maybe_message_width = self.app_state.play_state.as_ref().map(|ps| {
+            if self.app_state.show_song_message {
+                // Do not let long lines squeeze the other panes.
+                ps.module_info
+                    .song_message_width
+                    .min(area.width as usize / 2)
+            } else {
+                ps.module_info.message_width
+            }
+        });
 
         let message_window_width = maybe_message_width
             .iter()
@@ -254,29 +282,101 @@ where
 
         let [state, left_bottom] = Layout::default()
             .direction(Direction::Vertical)
-            .split_n(left, [Constraint::Length(7), Constraint::Min(1)]);
+            .split_n(left, [Constraint::Length(10), Constraint::Min(1)]);
 
-        let [playlist_filter, log] = Layout::default().direction(Direction::Horizontal).split_n(
-            left_bottom,
-            [Constraint::Ratio(1, 2), Constraint::Ratio(1, 2)],
+        let [playlist_filter, right_bottom] =
+            Layout::default().direction(Direction::Horizontal).split_n(
+                left_bottom,
+                [Constraint::Ratio(1, 2), Constraint::Ratio(1, 2)],
+            );
+
+        // One line per channel, but leave at least half of the space to the log.
+        let n_channels = self
+            .app_state
+            .play_state
+            .as_ref()
+            .map_or(0, |ps| ps.moment_state.read().n_channels);
+        let vu_height = if n_channels > 0 {
+            (n_channels.min(MAX_VU_CHANNELS) as u16 + 2).min(right_bottom.height / 2)
+        } else {
+            0
+        };
+        let [vu, errors_and_log] = Layout::default().direction(Direction::Vertical).split_n(
+            right_bottom,
+            [Constraint::Length(vu_height), Constraint::Min(1)],
         );
 
-        let maybe_filter_string = {
+        // Only shown after some modules failed to open, and above the log, which scrolls them away.
+        let n_recent_failures = self
+            .app_state
+            .playlist
+            .lock()
+            .unwrap()
+            .recent_failures
+            .len();
+        let errors_height = if n_recent_failures > 0 {
+            (n_recent_failures as u16 + 2).min(errors_and_log.height / 2)
+        } else {
+            0
+        };
+        let [errors, log] = Layout::default().direction(Direction::Vertical).split_n(
+            errors_and_log,
+            [Constraint::Length(errors_height), Constraint::Min(1)],
+        );
+
+        let (maybe_filter_string, filter_title) = {
             let playlist = self.app_state.playlist.lock().unwrap();
-            playlist.get_filter_string()
+            let mode_title = match playlist.get_filter_mode() {
+                FilterMode::Substring => "Filter",
+                FilterMode::Regex => "Regex filter",
+                FilterMode::Fuzzy => "Fuzzy filter",
+            };
+            let n_matches = playlist.len();
+            let matches_text = if n_matches == 1 {
+                "1 match".to_string()
+            } else {
+                format!("{} matches", n_matches)
+            };
+            let filter_title = match playlist.get_filter_error() {
+                Some(_) => format!("{} (invalid, {})", mode_title, matches_text),
+                None => format!("{} ({})", mode_title, matches_text),
+            };
+            (playlist.get_filter_string(), filter_title)
         };
 
-        let (show_filter, edit_filter) = match self.app_state.ui_mode {
-            UiMode::Normal => (maybe_filter_string.is_some(), false),
-            UiMode::Filter => (true, true),
+        let maybe_input_box = match self.app_state.ui_mode {
+            UiMode::Normal => maybe_filter_string.map(|f| (filter_title.clone(), f)),
+            UiMode::Filter => Some((
+                format!("{} (edit)", filter_title),
+                maybe_filter_string.unwrap_or_default(),
+            )),
+            UiMode::AddPath => Some(("Add path".to_string(), self.app_state.input.clone())),
+            UiMode::JumpTo => Some(("Jump to".to_string(), self.app_state.input.clone())),
+            UiMode::Goto => Some(("Go to order".to_string(), self.app_state.input.clone())),
+            UiMode::ExportView => {
+                Some(("Export view to".to_string(), self.app_state.input.clone()))
+            }
+            UiMode::SetTempo | UiMode::SetPitch => {
+                let name = if let UiMode::SetTempo = self.app_state.ui_mode {
+                    "Tempo in percent"
+                } else {
+                    "Pitch in semitones"
+                };
+                let title = match self.app_state.typed_control_value() {
+                    Some(value) => format!("{} (= {}/24)", name, value),
+                    None => name.to_string(),
+                };
+                Some((title, self.app_state.input.clone()))
+            }
+            UiMode::SelectDevice => None,
         };
 
-        let (playlist, maybe_filter) = if show_filter {
-            let [filter, playlist] = Layout::default().direction(Direction::Vertical).split_n(
+        let (playlist, maybe_input_area) = if maybe_input_box.is_some() {
+            let [input_area, playlist] = Layout::default().direction(Direction::Vertical).split_n(
                 playlist_filter,
                 [Constraint::Length(3), Constraint::Percentage(100)],
             );
-            (playlist, Some(filter))
+            (playlist, Some(input_area))
         } else {
             (playlist_filter, None)
         };
@@ -284,9 +384,15 @@ where
         self.render_state(state);
         self.render_playlist(playlist);
         self.render_message(message);
+        if vu_height > 2 {
+            self.render_vu(vu);
+        }
+        if errors_height > 2 {
+            self.render_errors(errors);
+        }
         self.render_log(log);
-        if let Some(filter) = maybe_filter {
-            self.render_filter(filter, maybe_filter_string, edit_filter);
+        if let (Some(input_area), Some((title, text))) = (maybe_input_area, maybe_input_box) {
+            self.render_input_box(input_area, &title, &text);
         }
     }
 
@@ -301,6 +407,9 @@ where
                 n_orders,
                 n_patterns,
                 message: _,
+                duration,
+                n_subsongs,
+                subsong,
                 ..
             } = play_state.module_info.clone();
 
@@ -310,10 +419,10 @@ where
                 row,
                 speed,
                 tempo,
+                position,
+                ..
             } = play_state.moment_state.read();
 
-            let sample_rate = app_state.options.sample_rate;
-
             let tempo_factor = app_state.control.tempo.value();
             let pitch_factor = app_state.control.pitch.value();
             let gain = app_state.control.gain.output();
@@ -323,9 +432,11 @@ where
             let repeat = app_state.control.repeat;
 
             let DecodeStatus {
+                sample_rate,
+                sample_format,
                 buffer_samples: buffer_size,
+                decode_time,
                 cpu_util,
-                ..
             } = app_state.backend.read_decode_status();
 
             let title_line = self.build_state_line(|b| {
@@ -334,18 +445,58 @@ where
                 b.value(title);
             });
 
+            let next_title = app_state.playlist.lock().unwrap().peek_next_title();
+            let next_line = self.build_state_line(|b| {
+                b.key("Next");
+                b.space("    ");
+                b.value(next_title.unwrap_or_else(|| "(end)".to_string()));
+            });
+
+            let duration_known = duration > 0.0 && duration < Self::MAX_SENSIBLE_DURATION;
+            let time_line = self.build_state_line(|b| {
+                let duration_text = if duration_known {
+                    format_mm_ss(duration)
+                } else {
+                    "--:--".to_string()
+                };
+                b.kv(
+                    "Time",
+                    format!("{} / {}", format_mm_ss(position), duration_text),
+                );
+                let loop_text = match (app_state.loop_a, app_state.loop_b) {
+                    (None, None) => "off".to_string(),
+                    (a, b) => format!(
+                        "{}-{}",
+                        a.map(format_mm_ss).unwrap_or_default(),
+                        b.map(format_mm_ss).unwrap_or_default()
+                    ),
+                };
+                b.kv("Loop", loop_text);
+                if n_subsongs > 1 {
+                    b.kv("Subsong", format!("{}/{}", subsong + 1, n_subsongs));
+                }
+                if let Some(deadline) = app_state.sleep_deadline {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    b.kv("Sleep", format_mm_ss(remaining.as_secs_f64()));
+                }
+            });
+
             let player_line = self.build_state_line(|b| {
                 b.kv("Order", format!("{:02}/{:02}", order, n_orders));
                 b.kv("Pattern", format!("{:02}/{:02}", pattern, n_patterns));
                 b.kv("Row", format!("{:02}", row));
                 b.space(" ");
-                b.kv("Repeat", if repeat { "on" } else { "off" });
+                b.kv("Repeat", repeat.name());
+                if app_state.stop_after_current {
+                    b.kv("Then", "stop");
+                }
             });
 
             let control_line = self.build_state_line(|b| {
-                b.kv("Gain", format!("{} dB", gain / 100));
+                b.kv("Volume", format!("{}%", app_state.volume));
+                b.kv("Gain", format!("{:+.1} dB", gain as f64 / 100.0));
                 b.kv("Stereo", format!("{}%", stereo_separation));
-                b.kv("Filter", format!("{} taps", filter_taps));
+                b.kv("Filter", filter_name(filter_taps));
                 b.kv("Ramping", format!("{}", volume_ramping));
             });
 
@@ -358,13 +509,19 @@ where
 
             let decoding_line = self.build_state_line(|b| {
                 b.kv("Sample Rate", format!("{}", sample_rate));
+                b.kv("Format", sample_format);
                 b.kv("Buffer Size", format!("{}", buffer_size));
+                b.kv("Decode", format!("{}µs", decode_time.as_micros()));
                 b.kv("CPU", format!("{:.2}%", cpu_util * 100.0));
             });
 
             let text = Text {
                 lines: vec![
                     title_line,
+                    next_line,
+                    time_line,
+                    // Covered by the progress bar.
+                    Spans::default(),
                     player_line,
                     speed_line,
                     control_line,
@@ -372,8 +529,34 @@ where
                 ],
             };
 
+            let inner = block.inner(area);
             let paragraph = Paragraph::new(text).block(block);
             self.frame.render_widget(paragraph, area);
+
+            const PROGRESS_LINE: u16 = 3;
+            if inner.height > PROGRESS_LINE {
+                // Fill the bar if the progress cannot be told.
+                let (ratio, gauge_style) = if duration_known {
+                    (
+                        (position / duration).clamp(0.0, 1.0),
+                        self.color_scheme.vu_bar,
+                    )
+                } else {
+                    (1.0, self.color_scheme.vu_bar.add_modifier(Modifier::DIM))
+                };
+                let gauge = LineGauge::default()
+                    .ratio(ratio)
+                    .label("")
+                    .line_set(symbols::line::THICK)
+                    .style(self.color_scheme.normal)
+                    .gauge_style(gauge_style);
+                let gauge_area = Rect {
+                    y: inner.y + PROGRESS_LINE,
+                    height: 1,
+                    ..inner
+                };
+                self.frame.render_widget(gauge, gauge_area);
+            }
         } else {
             let paragraph = Paragraph::new("No module").block(block);
             self.frame.render_widget(paragraph, area);
@@ -382,44 +565,187 @@ where
 
     fn render_playlist(&mut self, area: Rect) {
         let app_state = self.app_state;
-        let color_scheme = &self.color_scheme;
+        let color_scheme = self.color_scheme;
 
-        let window_height = area.height as usize - 2;
+        let window_height = (area.height as usize).saturating_sub(2);
+        // Leave room for the borders and the highlight symbol.
+        let row_width = (area.width as usize).saturating_sub(2 + 3);
 
-        let (shown_titles, list_len, now_playing, offset) = {
+        let (shown_rows, list_len, now_playing, selected_row, offset, order, scan_progress) = {
             let playlist = app_state.playlist.lock().unwrap();
 
             let list_len = playlist.len();
             let now_playing = playlist.now_playing_in_view;
             assert!(now_playing.is_none() || list_len > 0);
-            let offset = now_playing
-                .map(|s| center_region(list_len, window_height, s))
-                .unwrap_or(0);
-            let limit = (offset + window_height).min(playlist.len());
-
-            let shown_titles = (offset..limit)
-                .map(|i| {
-                    let item = playlist.get_item(i).unwrap();
-                    item.mod_path.display_name()
-                })
-                .collect::<Vec<_>>();
-            (shown_titles, list_len, now_playing, offset)
+            let selected = playlist.cursor.or(now_playing);
+
+            let format_item = |i: usize| {
+                let item = playlist.get_item(i).unwrap();
+                let title = item
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.title.as_ref());
+                let name = match title {
+                    Some(title) => format!("{} - {}", item.mod_path.display_name(), title),
+                    None => item.mod_path.display_name(),
+                };
+                let match_ranges = playlist.filter_match_ranges(&name);
+                let base_len = name.len();
+                let name = match playlist.queue_position(item.id) {
+                    Some(position) => format!("[{}] {}", position, name),
+                    None => name,
+                };
+                let name = if item.duplicate {
+                    format!("(dup) {}", name)
+                } else {
+                    name
+                };
+                let name = if item.play_count > 0 {
+                    format!("· {}", name)
+                } else {
+                    name
+                };
+                // Tell the playing item apart from the cursor while browsing.
+                let name = if now_playing == Some(i) {
+                    format!("♪ {}", name)
+                } else {
+                    name
+                };
+                // The prefixes are added in front of the matched text.
+                let prefix_len = name.len() - base_len;
+                let duration = item
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.duration);
+                let (line, shown_name_len) = match duration {
+                    Some(duration) => {
+                        // Truncate the name so that durations are aligned to the right.
+                        let duration_text = format_mm_ss(duration.as_secs_f64());
+                        let name_width = row_width.saturating_sub(screen_width(&duration_text) + 1);
+                        let truncated = truncate_to_width(&name, name_width);
+                        let shown_name_len = if truncated.len() < name.len() {
+                            truncated.len().saturating_sub('…'.len_utf8())
+                        } else {
+                            name.len()
+                        };
+                        let padding = name_width.saturating_sub(screen_width(&truncated));
+                        let line =
+                            format!("{}{} {}", truncated, " ".repeat(padding), duration_text);
+                        (line, shown_name_len)
+                    }
+                    None => {
+                        let shown_name_len = name.len();
+                        (name, shown_name_len)
+                    }
+                };
+                let highlighted = match_ranges
+                    .into_iter()
+                    .map(|range| range.start + prefix_len..range.end + prefix_len)
+                    .filter(|range| range.end <= shown_name_len)
+                    .collect::<Vec<_>>();
+                let style = if now_playing == Some(i) {
+                    color_scheme.list_playing
+                } else if item.failed {
+                    color_scheme.list_failed
+                } else if item.favorite {
+                    color_scheme.list_favorite
+                } else {
+                    color_scheme.normal
+                };
+                let line = highlight_ranges(line, &highlighted, color_scheme.list_match);
+                (line, style)
+            };
+
+            let (shown_rows, selected_row, offset) = if playlist.is_grouped() {
+                let grouped_view = playlist.grouped_view();
+                let n_rows = grouped_view.rows.len();
+                // Items in collapsed groups are shown by the headers.
+                let selected_row = selected.map(|s| grouped_view.row_of_item[s]);
+                let playing_row = now_playing.map(|n| grouped_view.row_of_item[n]);
+                let offset = selected_row
+                    .map(|s| center_region(n_rows, window_height, s))
+                    .unwrap_or(0);
+                let limit = (offset + window_height).min(n_rows);
+                let shown_rows = (offset..limit)
+                    .map(|row| match grouped_view.rows[row] {
+                        GroupRow::Header {
+                            ref dir,
+                            n_items,
+                            collapsed,
+                            ..
+                        } => {
+                            let marker = if collapsed { "▸" } else { "▾" };
+                            let line = Spans::from(format!("{} {} ({})", marker, dir, n_items));
+                            let style = if playing_row == Some(row) {
+                                color_scheme.list_playing
+                            } else {
+                                color_scheme.block_title
+                            };
+                            (line, style)
+                        }
+                        GroupRow::Item(view_index) => format_item(view_index),
+                    })
+                    .collect::<Vec<_>>();
+                (shown_rows, selected_row, offset)
+            } else {
+                let offset = selected
+                    .map(|s| center_region(list_len, window_height, s))
+                    .unwrap_or(0);
+                let limit = (offset + window_height).min(list_len);
+                let shown_rows = (offset..limit).map(format_item).collect::<Vec<_>>();
+                (shown_rows, selected, offset)
+            };
+
+            let mut order = playlist.get_order().to_string();
+            if playlist.is_random() {
+                order.push_str(", random");
+            }
+            if playlist.is_grouped() {
+                order.push_str(", grouped");
+            }
+            if let Some(scope) = playlist.get_scope() {
+                order.push_str(&format!(", only {}", scope));
+            }
+
+            (
+                shown_rows,
+                list_len,
+                now_playing,
+                selected_row,
+                offset,
+                order,
+                playlist.metadata_scan_progress,
+            )
         };
 
-        let items: Vec<ListItem> = shown_titles
-            .iter()
-            .cloned()
-            .map(|line| {
-                let span = Spans::from(line);
-                ListItem::new(span).style(color_scheme.normal)
-            })
+        let items: Vec<ListItem> = shown_rows
+            .into_iter()
+            .map(|(line, style)| ListItem::new(line).style(style))
             .collect();
 
         let now_playing_text = now_playing
             .map(|n| n.to_string())
             .unwrap_or_else(|| "-".to_string());
 
-        let block = self.new_block(format!("Playlist {}/{}", now_playing_text, list_len));
+        let rescanning_text = if app_state.loading.load(Ordering::SeqCst) {
+            " (loading…)".to_string()
+        } else if app_state.rescanning.load(Ordering::SeqCst) {
+            " (rescanning…)".to_string()
+        } else if app_state.finding_duplicates.load(Ordering::SeqCst) {
+            " (finding duplicates…)".to_string()
+        } else if let Some((n_scanned, n_items)) = scan_progress {
+            format!(" (scanning {}/{}…)", n_scanned, n_items)
+        } else {
+            String::new()
+        };
+
+        let block = self.new_focusable_block(
+            format!(
+                "Playlist {}/{} ({}){}",
+                now_playing_text, list_len, order, rescanning_text
+            ),
+            app_state.focus == Focus::Playlist,
+        );
 
         let items = List::new(items)
             .block(block)
@@ -428,26 +754,117 @@ where
             .highlight_symbol(">> ");
 
         let mut state = ListState::default();
-        state.select(now_playing.map(|s| s - offset));
+        state.select(selected_row.map(|s| s - offset));
 
         self.frame.render_stateful_widget(items, area, &mut state);
     }
 
     fn render_message(&mut self, area: Rect) {
         let app_state = self.app_state;
-        let lines: Vec<Cow<str>> = if let Some(ref play_state) = app_state.play_state {
-            play_state
-                .module_info
-                .message
+        let title = if app_state.show_song_message {
+            "Song Message"
+        } else {
+            "Message"
+        };
+        let lines: Vec<Cow<str>> = match app_state.shown_message() {
+            Some(message) if message.is_empty() && app_state.show_song_message => {
+                vec![Cow::Borrowed("(No song message)")]
+            }
+            Some(message) => message
                 .iter()
                 .map(|s| Cow::<str>::Borrowed(s))
-                .collect::<Vec<_>>()
+                .collect::<Vec<_>>(),
+            None => vec![Cow::Borrowed("(No module)")],
+        };
+
+        let window_height = area.height.saturating_sub(2) as usize;
+        app_state.message_pane_height.set(window_height);
+        let scroll = app_state
+            .message_scroll
+            .min(lines.len().saturating_sub(window_height));
+
+        let title = if lines.len() > window_height {
+            format!(
+                "{} {}-{}/{}",
+                title,
+                scroll + 1,
+                scroll + window_height,
+                lines.len()
+            )
         } else {
-            vec![Cow::Borrowed("(No module)")]
+            title.to_string()
+        };
+
+        let block = self.new_focusable_block(title, app_state.focus == Focus::Message);
+        let paragraph = self
+            .new_paragraph_from_raw_lines(lines)
+            .block(block)
+            .scroll((scroll as u16, 0));
+        self.frame.render_widget(paragraph, area);
+    }
+
+    fn render_vu(&mut self, area: Rect) {
+        let Some(ref play_state) = self.app_state.play_state else {
+            return;
         };
+        let MomentState {
+            n_channels,
+            channel_vu,
+            ..
+        } = play_state.moment_state.read();
 
-        let block = self.new_block("Message");
-        let paragraph = self.new_paragraph_from_raw_lines(lines).block(block);
+        let window_height = (area.height - 2) as usize;
+        let bar_width = (area.width as usize).saturating_sub(2 + 4);
+        let n_shown = n_channels.min(MAX_VU_CHANNELS).min(window_height);
+
+        let lines = channel_vu[..n_shown]
+            .iter()
+            .enumerate()
+            .map(|(i, vu)| {
+                let filled =
+                    ((vu.clamp(0.0, 1.0) * bar_width as f32).round() as usize).min(bar_width);
+                Spans::from(vec![
+                    self.new_span_key(format!("{:>3} ", i + 1)),
+                    self.new_span("█".repeat(filled), self.color_scheme().vu_bar),
+                ])
+            })
+            .collect::<Vec<_>>();
+
+        let title = if n_shown < n_channels {
+            format!("VU (channels 1-{} of {})", n_shown, n_channels)
+        } else {
+            "VU".to_string()
+        };
+        let block = self.new_block(title);
+        let paragraph = Paragraph::new(Text::from(lines))
+            .block(block)
+            .style(self.color_scheme().normal);
+        self.frame.render_widget(paragraph, area);
+    }
+
+    /// List the last modules that failed to open, the latest first.
+    fn render_errors(&mut self, area: Rect) {
+        let (lines, n_failed) = {
+            let playlist = self.app_state.playlist.lock().unwrap();
+            let lines = playlist
+                .recent_failures
+                .iter()
+                .rev()
+                .map(|(name, error)| {
+                    Spans::from(vec![
+                        self.new_span_normal(name.clone()),
+                        self.new_span(format!("  {}", error), self.color_scheme().log_message),
+                    ])
+                })
+                .collect::<Vec<_>>();
+            (lines, playlist.n_failed())
+        };
+
+        let title = format!("Errors ({} failed to open, F to clear)", n_failed);
+        let block = self.new_block(title);
+        let paragraph = Paragraph::new(Text::from(lines))
+            .block(block)
+            .style(self.color_scheme().normal);
         self.frame.render_widget(paragraph, area);
     }
 
@@ -507,11 +924,179 @@ where
         self.frame.render_widget(list, area);
     }
 
-    fn render_filter(&mut self, area: Rect, maybe_filter_string: Option<String>, editing: bool) {
-        let title = if editing { "Filter (edit)" } else { "Filter" };
-        let filter_string = maybe_filter_string.as_deref().unwrap_or("");
+    fn render_input_box(&mut self, area: Rect, title: &str, text: &str) {
         let block = self.new_block(title);
-        let paragraph = Paragraph::new(self.new_span_value(filter_string)).block(block);
+        let paragraph = Paragraph::new(self.new_span_value(text)).block(block);
         self.frame.render_widget(paragraph, area);
     }
+
+    /// List the output devices in a box at the center, highlighting the one under the cursor.
+    fn render_device_selection(&mut self, area: Rect) {
+        let app_state = self.app_state;
+        let title = "Output device (enter to select, esc to cancel)";
+        let width = app_state
+            .device_names
+            .iter()
+            .map(|name| screen_width(name))
+            .chain(std::iter::once(screen_width(title)))
+            .max()
+            .unwrap_or(0)
+            + 2
+            + 3;
+        let device_area = centered_rect(area, width, app_state.device_names.len() + 2);
+
+        let items = app_state
+            .device_names
+            .iter()
+            .map(|name| ListItem::new(name.as_str()))
+            .collect::<Vec<_>>();
+        let list = List::new(items)
+            .block(self.new_block(title))
+            .style(self.color_scheme.normal)
+            .highlight_style(self.color_scheme.list_highlight)
+            .highlight_symbol(">> ");
+        let mut state = ListState::default();
+        state.select(Some(app_state.device_cursor));
+
+        self.frame.render_widget(Clear, device_area);
+        self.frame
+            .render_stateful_widget(list, device_area, &mut state);
+    }
+
+    /// List the keys and the names of the actions in columns, in a box at the center.
+    fn render_help(&mut self, area: Rect, key_help: &[(String, &str)]) {
+        const COLUMN_GAP: usize = 3;
+
+        let key_width = key_help
+            .iter()
+            .map(|(keys, _)| screen_width(keys))
+            .max()
+            .unwrap_or(0);
+        let name_width = key_help
+            .iter()
+            .map(|(_, name)| screen_width(name))
+            .max()
+            .unwrap_or(0);
+        let column_width = key_width + 1 + name_width;
+
+        let max_rows = (area.height as usize).saturating_sub(2).max(1);
+        let n_columns = key_help.len().div_ceil(max_rows).max(1);
+        let n_rows = key_help.len().div_ceil(n_columns);
+
+        let width = n_columns * (column_width + COLUMN_GAP) - COLUMN_GAP + 2;
+        let help_area = centered_rect(area, width, n_rows + 2);
+
+        let lines = (0..n_rows)
+            .map(|row| {
+                let mut spans = vec![];
+                for column in 0..n_columns {
+                    let Some((keys, name)) = key_help.get(column * n_rows + row) else {
+                        break;
+                    };
+                    if column > 0 {
+                        spans.push(self.new_span_normal(" ".repeat(COLUMN_GAP)));
+                    }
+                    let key_padding = key_width - screen_width(keys);
+                    spans.push(self.new_span_key(format!("{}{}", " ".repeat(key_padding), keys)));
+                    let name_padding = name_width - screen_width(name);
+                    spans.push(self.new_span_value(format!(
+                        " {}{}",
+                        name,
+                        " ".repeat(name_padding)
+                    )));
+                }
+                Spans::from(spans)
+            })
+            .collect::<Vec<_>>();
+
+        let block = self.new_block("Keys (press ? or esc to close)");
+        let paragraph = Paragraph::new(Text::from(lines)).block(block);
+        self.frame.render_widget(Clear, help_area);
+        self.frame.render_widget(paragraph, help_area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use clap::Parser;
+    use tui::{backend::TestBackend, buffer::Buffer, style::Color, Terminal};
+
+    use crate::{
+        backend::NullBackend,
+        control::ModuleControl,
+        options::Options,
+        playlist::{ModPath, PlayList, PlayListItem, PlayListModuleProvider},
+    };
+
+    use super::*;
+
+    fn render(color_scheme: ColorScheme) -> Buffer {
+        let mut playlist = PlayList::new();
+        playlist.add_item(PlayListItem::new(ModPath {
+            root_path: "mods".into(),
+            file_path: "mods/song.mod".into(),
+            archive_paths: vec![],
+            is_archived_single: false,
+        }));
+        let playlist = Arc::new(Mutex::new(playlist));
+        let module_provider = Box::new(PlayListModuleProvider::new(playlist.clone()));
+        let backend = Box::new(NullBackend::new(
+            44100,
+            module_provider,
+            ModuleControl::default(),
+        ));
+        let app_state = AppState::new(
+            Options::parse_from(["tuimodplayer"]),
+            backend,
+            playlist,
+            ModuleControl::default(),
+            color_scheme,
+        );
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        terminal
+            .draw(|frame| render_ui(frame, frame.size(), &app_state, &[]))
+            .unwrap();
+        terminal.backend().buffer().clone()
+    }
+
+    /// The style of the cell where `text` starts in the rendered buffer.
+    fn style_of_text(buffer: &Buffer, text: &str) -> Option<Style> {
+        let area = buffer.area;
+        for y in area.top()..area.bottom() {
+            let line = (area.left()..area.right())
+                .map(|x| buffer.get(x, y).symbol.as_str())
+                .collect::<String>();
+            if let Some(position) = line.find(text) {
+                // The text before it is ASCII, so the byte position is the column.
+                return Some(buffer.get(area.left() + position as u16, y).style());
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn rendering_uses_color_scheme_of_app_state() {
+        let title_color = Color::Rgb(1, 2, 3);
+        let background = Color::Rgb(4, 5, 6);
+        let color_scheme = ColorScheme {
+            block_title: Style::default().fg(title_color),
+            normal: Style::default().fg(Color::Rgb(7, 8, 9)).bg(background),
+            ..ColorScheme::default()
+        };
+
+        let buffer = render(color_scheme);
+        let title_style = style_of_text(&buffer, "Playlist").unwrap();
+        assert_eq!(title_style.fg, Some(title_color));
+        assert_eq!(buffer.get(0, 0).style().bg, Some(background));
+    }
+
+    #[test]
+    fn rendering_uses_default_color_scheme_by_default() {
+        let buffer = render(ColorScheme::default());
+        let title_style = style_of_text(&buffer, "Playlist").unwrap();
+        assert_eq!(title_style.fg, ColorScheme::default().block_title.fg);
+    }
 }
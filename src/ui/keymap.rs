@@ -0,0 +1,359 @@
+// Copyright 2022 Kunshan Wang
+//
+// This file is part of TUIModPlayer.  TUIModPlayer is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// TUIModPlayer is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See
+// the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with TUIModPlayer. If
+// not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+macro_rules! define_actions {
+    ($($variant:ident => $name:literal,)*) => {
+        /// Something a key can be bound to in the normal mode.
+        #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+        pub enum Action {
+            $($variant,)*
+        }
+
+        impl Action {
+            pub const ALL: &'static [Action] = &[$(Action::$variant,)*];
+
+            /// The name used in the key binding file.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Action::$variant => $name,)*
+                }
+            }
+        }
+    };
+}
+
+define_actions! {
+    Redraw => "redraw",
+    SavePlaylist => "save_playlist",
+    Quit => "quit",
+    Next => "next",
+    Prev => "prev",
+    Next10 => "next10",
+    Prev10 => "prev10",
+    TempoDown => "tempo_down",
+    TempoUp => "tempo_up",
+    PitchDown => "pitch_down",
+    PitchUp => "pitch_up",
+    GainDown => "gain_down",
+    GainUp => "gain_up",
+    StereoSeparationDown => "stereo_separation_down",
+    StereoSeparationUp => "stereo_separation_up",
+    FilterTapsDown => "filter_taps_down",
+    FilterTapsUp => "filter_taps_up",
+    VolumeRampingDown => "volume_ramping_down",
+    VolumeRampingUp => "volume_ramping_up",
+    ResetControls => "reset_controls",
+    VolumeDown => "volume_down",
+    VolumeUp => "volume_up",
+    SetTempo => "set_tempo",
+    SetPitch => "set_pitch",
+    CycleRepeat => "cycle_repeat",
+    ToggleStopAfterCurrent => "toggle_stop_after_current",
+    PauseResume => "pause_resume",
+    SetLoopA => "set_loop_a",
+    SetLoopB => "set_loop_b",
+    ClearLoop => "clear_loop",
+    PrevSubsong => "prev_subsong",
+    NextSubsong => "next_subsong",
+    ToggleSongMessage => "toggle_song_message",
+    ToggleHelp => "toggle_help",
+    Filter => "filter",
+    ToggleQueued => "toggle_queued",
+    ToggleFavorite => "toggle_favorite",
+    Rescan => "rescan",
+    ExportPlaylist => "export_playlist",
+    ExportView => "export_view",
+    ExportCurrent => "export_current",
+    JumpTo => "jump_to",
+    GotoOrder => "goto_order",
+    AddPath => "add_path",
+    SeekBackward => "seek_backward",
+    SeekForward => "seek_forward",
+    SeekBackwardLong => "seek_backward_long",
+    SeekForwardLong => "seek_forward_long",
+    PrevOrder => "prev_order",
+    NextOrder => "next_order",
+    MoveItemUp => "move_item_up",
+    MoveItemDown => "move_item_down",
+    ToggleFocus => "toggle_focus",
+    Up => "up",
+    Down => "down",
+    PageUp => "page_up",
+    PageDown => "page_down",
+    Home => "home",
+    End => "end",
+    PlayCursor => "play_cursor",
+    PlayFirst => "play_first",
+    PlayLast => "play_last",
+    RemoveItem => "remove_item",
+    CycleSort => "cycle_sort",
+    ToggleShuffle => "toggle_shuffle",
+    Reshuffle => "reshuffle",
+    ToggleRandom => "toggle_random",
+    ClearFailed => "clear_failed",
+    ToggleScope => "toggle_scope",
+    ToggleGrouped => "toggle_grouped",
+    ToggleGroup => "toggle_group",
+    FindDuplicates => "find_duplicates",
+    RemoveDuplicates => "remove_duplicates",
+    CycleSleepTimer => "cycle_sleep_timer",
+    SelectDevice => "select_device",
+    ResetCursor => "reset_cursor",
+}
+
+impl Action {
+    /// The inverse of `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|action| action.name() == name)
+    }
+}
+
+/// A key together with the modifiers held.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct KeyCombo {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyCombo {
+    pub fn from_event(event: &KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+
+    pub fn to_event(self) -> KeyEvent {
+        KeyEvent::new(self.code, self.modifiers)
+    }
+
+    pub fn new(code: KeyCode, mut modifiers: KeyModifiers) -> Self {
+        // The case of a letter already tells whether shift is held.  Terminals disagree on
+        // whether to report the shift modifier with it.
+        if let KeyCode::Char(_) = code {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        Self { code, modifiers }
+    }
+
+    /// Parse key names like `u`, `ctrl+s`, `shift+left` or `page_down`.
+    pub fn parse(name: &str) -> Option<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut key = name;
+        // Leave a lone `+` as the key itself.
+        while let Some((modifier, rest)) = key.split_once('+').filter(|(_, rest)| !rest.is_empty())
+        {
+            modifiers |= match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "shift" => KeyModifiers::SHIFT,
+                "alt" => KeyModifiers::ALT,
+                _ => return None,
+            };
+            key = rest;
+        }
+
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(ch), None) if modifiers.contains(KeyModifiers::SHIFT) => {
+                KeyCode::Char(ch.to_ascii_uppercase())
+            }
+            (Some(ch), None) => KeyCode::Char(ch),
+            _ => match key.to_ascii_lowercase().as_str() {
+                "space" => KeyCode::Char(' '),
+                "enter" => KeyCode::Enter,
+                "esc" | "escape" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "page_up" | "pageup" => KeyCode::PageUp,
+                "page_down" | "pagedown" => KeyCode::PageDown,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                function_key => {
+                    let n = function_key.strip_prefix('f')?.parse().ok()?;
+                    KeyCode::F(n)
+                }
+            },
+        };
+        Some(Self::new(code, modifiers))
+    }
+}
+
+/// Formatted like the names `parse` accepts.
+impl std::fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "alt+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "shift+")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(ch) => write!(f, "{}", ch),
+            KeyCode::Enter => write!(f, "enter"),
+            KeyCode::Esc => write!(f, "esc"),
+            KeyCode::Tab => write!(f, "tab"),
+            KeyCode::Backspace => write!(f, "backspace"),
+            KeyCode::Delete => write!(f, "delete"),
+            KeyCode::Insert => write!(f, "insert"),
+            KeyCode::Left => write!(f, "left"),
+            KeyCode::Right => write!(f, "right"),
+            KeyCode::Up => write!(f, "up"),
+            KeyCode::Down => write!(f, "down"),
+            KeyCode::PageUp => write!(f, "page_up"),
+            KeyCode::PageDown => write!(f, "page_down"),
+            KeyCode::Home => write!(f, "home"),
+            KeyCode::End => write!(f, "end"),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+/// Key bindings loaded from the key binding file.  They take precedence over the default ones.
+#[derive(Default)]
+pub struct KeyMap {
+    bindings: HashMap<KeyCombo, Action>,
+}
+
+fn default_key_map_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("tuimodplayer").join("keys.toml"))
+}
+
+impl KeyMap {
+    /// Load the key binding file given by `--config`, or the default one if it exists.
+    pub fn load(config: Option<&str>) -> Result<Self> {
+        let path = match config {
+            Some(path) => PathBuf::from(path),
+            None => match default_key_map_path() {
+                Some(path) if path.is_file() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+        let key_map = Self::load_from_file(&path)
+            .with_context(|| format!("Failed to load key bindings from {}", path.display()))?;
+        log::info!(
+            "Loaded {} key bindings from {}",
+            key_map.bindings.len(),
+            path.display()
+        );
+        Ok(key_map)
+    }
+
+    /// The file is a TOML table from key names to action names, e.g. `h = "tempo_down"`.
+    fn load_from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let table = content.parse::<toml::Table>()?;
+        let mut bindings = HashMap::new();
+        for (key_name, value) in table.iter() {
+            let key =
+                KeyCombo::parse(key_name).ok_or_else(|| anyhow!("Unknown key {:?}", key_name))?;
+            let action_name = value
+                .as_str()
+                .ok_or_else(|| anyhow!("The action of {:?} is not a string", key_name))?;
+            let action = Action::from_name(action_name)
+                .ok_or_else(|| anyhow!("Unknown action {:?}", action_name))?;
+            bindings.insert(key, action);
+        }
+        Ok(Self { bindings })
+    }
+
+    pub fn get(&self, event: &KeyEvent) -> Option<Action> {
+        self.bindings.get(&KeyCombo::from_event(event)).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_key_names() {
+        let parse = |name| KeyCombo::parse(name).unwrap();
+        assert_eq!(
+            parse("ctrl+s"),
+            KeyCombo::new(KeyCode::Char('s'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            parse("shift+a"),
+            KeyCombo::new(KeyCode::Char('A'), KeyModifiers::NONE)
+        );
+        assert_eq!(parse("shift+a").to_string(), "A");
+        assert_eq!(
+            parse("+"),
+            KeyCombo::new(KeyCode::Char('+'), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            parse("ctrl++"),
+            KeyCombo::new(KeyCode::Char('+'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            parse("f5"),
+            KeyCombo::new(KeyCode::F(5), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            parse("page_down"),
+            KeyCombo::new(KeyCode::PageDown, KeyModifiers::NONE)
+        );
+        assert_eq!(
+            parse("Shift+Left"),
+            KeyCombo::new(KeyCode::Left, KeyModifiers::SHIFT)
+        );
+    }
+
+    #[test]
+    fn parse_rejects_unknown_names() {
+        assert_eq!(KeyCombo::parse("page_sideways"), None);
+        assert_eq!(KeyCombo::parse("fx"), None);
+        assert_eq!(KeyCombo::parse("meta+s"), None);
+        assert_eq!(KeyCombo::parse(""), None);
+    }
+
+    #[test]
+    fn display_round_trips_through_parse() {
+        for name in [
+            "u",
+            "A",
+            "+",
+            "space",
+            "ctrl+s",
+            "ctrl+alt+x",
+            "shift+left",
+            "alt+enter",
+            "f12",
+            "page_up",
+            "esc",
+        ] {
+            let key = KeyCombo::parse(name).unwrap();
+            assert_eq!(key.to_string(), name);
+            assert_eq!(KeyCombo::parse(&key.to_string()), Some(key));
+        }
+    }
+}
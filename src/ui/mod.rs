@@ -11,10 +11,17 @@
 // You should have received a copy of the GNU General Public License along with TUIModPlayer. If
 // not, see <https://www.gnu.org/licenses/>.
 
+mod color_scheme;
 mod control;
 mod display;
+mod keymap;
 
-use std::{io::stdout, panic::PanicInfo, time::Duration};
+pub use self::color_scheme::ColorScheme;
+
+use std::{
+    io::{stdout, Write},
+    time::Duration,
+};
 
 use crate::app::AppState;
 
@@ -23,15 +30,16 @@ use crossterm::{event, execute, terminal};
 use anyhow::Result;
 
 use self::{
-    control::{handle_key_event, HandleKeyResult},
+    control::{handle_key_event, key_help, HandleKeyResult},
     display::render_ui,
+    keymap::KeyMap,
 };
 
-type BoxedHook = Box<dyn Fn(&PanicInfo) + Sync + Send>;
-static mut OLD_HOOK: Option<BoxedHook> = None;
 static REGISTER_PANIC_HOOK: std::sync::Once = std::sync::Once::new();
 
-fn ui_panic_hook(panic_info: &PanicInfo<'_>) {
+/// Leave the alternate screen and the raw mode before the panic message is printed.  Otherwise
+/// the message is lost and the terminal is left garbled.
+fn restore_terminal_on_panic() {
     execute!(stdout(), terminal::LeaveAlternateScreen).unwrap_or_else(|e| {
         // Cannot handle error while handling panic.  Printing is the best effort.
         eprintln!("Failed to leave alternative screen: {}", e);
@@ -41,32 +49,72 @@ fn ui_panic_hook(panic_info: &PanicInfo<'_>) {
         // Cannot handle error while handling panic.  Printing is the best effort.
         eprintln!("Failed to disable raw mode: {}", e);
     });
-    let old_hook = unsafe { OLD_HOOK.as_ref().unwrap() };
-    old_hook(panic_info);
+}
+
+/// Push the current title onto the title stack of the terminal.  Terminals that do not support
+/// the title stack (an xterm extension) ignore it.
+fn save_terminal_title() -> Result<()> {
+    let mut out = stdout();
+    out.write_all(b"\x1b[22;0t")?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Clear the title set while playing, and pop the title saved by `save_terminal_title`, if the
+/// terminal supports it.
+fn restore_terminal_title() -> Result<()> {
+    let mut out = stdout();
+    execute!(out, terminal::SetTitle(""))?;
+    out.write_all(b"\x1b[23;0t")?;
+    out.flush()?;
+    Ok(())
 }
 
 pub fn run_ui(app_state: &mut AppState) -> Result<()> {
     REGISTER_PANIC_HOOK.call_once(|| {
-        unsafe {
-            OLD_HOOK = Some(std::panic::take_hook());
-        }
-        std::panic::set_hook(Box::new(ui_panic_hook));
+        let old_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |panic_info| {
+            restore_terminal_on_panic();
+            old_hook(panic_info);
+        }));
     });
 
+    // Report mistakes in the key binding file before the terminal is taken over.
+    let key_map = KeyMap::load(app_state.options.config.as_deref())?;
+
     terminal::enable_raw_mode()?;
 
     crate::logging::set_stderr_enabled(false);
     execute!(stdout(), terminal::EnterAlternateScreen)?;
+    if app_state.options.set_title {
+        save_terminal_title()?;
+    }
 
+    // Restore the terminal even if the event loop fails.
+    let result = event_loop(app_state, &key_map);
+
+    if app_state.options.set_title {
+        restore_terminal_title()?;
+    }
+    execute!(stdout(), terminal::LeaveAlternateScreen)?;
+    crate::logging::set_stderr_enabled(true);
+
+    terminal::disable_raw_mode()?;
+
+    result
+}
+
+fn event_loop(app_state: &mut AppState, key_map: &KeyMap) -> Result<()> {
     let backend = tui::backend::CrosstermBackend::new(stdout());
     let mut term = tui::Terminal::new(backend)?;
+    let key_help = key_help(key_map);
 
     'event_loop: loop {
         let mut redraw = false;
 
         if event::poll(Duration::from_millis(100))? {
             let ev = event::read()?;
-            let key_event_result = handle_key_event(&ev, app_state);
+            let key_event_result = handle_key_event(&ev, app_state, key_map);
             match key_event_result {
                 HandleKeyResult::Nothing => {}
                 HandleKeyResult::Redraw => {
@@ -79,6 +127,11 @@ pub fn run_ui(app_state: &mut AppState) -> Result<()> {
         }
 
         app_state.handle_backend_events();
+        app_state.handle_mpris_commands();
+
+        if app_state.check_sleep_timer() {
+            break 'event_loop;
+        }
 
         if std::mem::take(&mut redraw) {
             term.clear()?;
@@ -86,14 +139,9 @@ pub fn run_ui(app_state: &mut AppState) -> Result<()> {
 
         term.draw(|frame| {
             let area = frame.size();
-            render_ui(frame, area, app_state);
+            render_ui(frame, area, app_state, &key_help);
         })?;
     }
 
-    execute!(stdout(), terminal::LeaveAlternateScreen)?;
-    crate::logging::set_stderr_enabled(true);
-
-    terminal::disable_raw_mode()?;
-
     Ok(())
 }
@@ -3,6 +3,7 @@ use tui::{
     layout::{Constraint, Layout, Rect},
     text::{Span, Spans, Text},
 };
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 // Copyright 2022 Kunshan Wang
 //
@@ -103,11 +104,31 @@ pub fn center_region(list_len: usize, window_len: usize, selected: usize) -> usi
 }
 
 /// Return the width of a string when printed on the screen.
-/// Currently we just use the number of characters
-/// because mod files may not (really?) contain full-width characters,
-/// such as Chinese characters, which occupy the width of two letters.
+/// Full-width characters, such as Chinese characters, occupy the width of two letters.
 pub fn screen_width(s: &str) -> usize {
-    s.chars().count()
+    s.width()
+}
+
+/// Cut a string to at most `width` columns, ending with `…` if cut.
+pub fn truncate_to_width(s: &str, width: usize) -> String {
+    if s.width() <= width {
+        return s.to_string();
+    }
+    let mut result = String::new();
+    let mut result_width = 0;
+    for ch in s.chars() {
+        let ch_width = ch.width().unwrap_or(0);
+        // Leave one column for the ellipsis.
+        if result_width + ch_width + 1 > width {
+            break;
+        }
+        result.push(ch);
+        result_width += ch_width;
+    }
+    if width > 0 {
+        result.push('…');
+    }
+    result
 }
 
 /// Wrap lines of a `Text` to a fixed width.
@@ -123,38 +144,36 @@ pub fn force_wrap_text<'a>(text: &Text<'a>, width: usize) -> Text<'a> {
     }
 }
 
+/// Wrap a line of spans to lines of at most `width` columns.
+/// Characters are never split, and wide characters count as two columns.  A character wider than
+/// `width` gets a line of its own.
 pub fn force_wrap_spans<'b>(spans: &Spans<'_>, width: usize) -> Vec<Spans<'b>> {
     let mut lines: Vec<Spans> = vec![];
     let mut current_line = vec![];
-    let mut line_rem_len = width;
+    let mut line_width = 0;
     for span in spans.0.iter() {
-        let content_len = span.content.len();
-        let mut content_cursor = 0;
-        while content_len - content_cursor > line_rem_len {
-            let portion_content =
-                span.content[content_cursor..content_cursor + line_rem_len].to_string();
-            content_cursor += line_rem_len;
-
-            let small_span = Span {
-                content: Cow::Owned(portion_content),
-                style: span.style,
-            };
-            current_line.push(small_span);
-            lines.push(Spans(current_line));
-
-            current_line = vec![];
-            line_rem_len = width;
+        let mut portion_content = String::new();
+        for ch in span.content.chars() {
+            let ch_width = ch.width().unwrap_or(0);
+            if line_width > 0 && line_width + ch_width > width {
+                if !portion_content.is_empty() {
+                    current_line.push(Span {
+                        content: Cow::Owned(std::mem::take(&mut portion_content)),
+                        style: span.style,
+                    });
+                }
+                lines.push(Spans(std::mem::take(&mut current_line)));
+                line_width = 0;
+            }
+            portion_content.push(ch);
+            line_width += ch_width;
         }
 
-        assert!(content_len - content_cursor <= line_rem_len);
-
-        if content_len - content_cursor > 0 {
-            let portion_content = span.content[content_cursor..].to_string();
-            let small_span = Span {
+        if !portion_content.is_empty() {
+            current_line.push(Span {
                 content: Cow::Owned(portion_content),
                 style: span.style,
-            };
-            current_line.push(small_span);
+            });
         }
     }
     if !current_line.is_empty() {
@@ -178,3 +197,84 @@ impl<T> IsSomeAnd for Option<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tui::style::{Modifier, Style};
+
+    use super::*;
+
+    #[test]
+    fn screen_width_of_cjk_is_two() {
+        assert_eq!(screen_width("音"), 2);
+        assert_eq!(screen_width("音楽"), 4);
+        assert_eq!(screen_width("a音"), 3);
+    }
+
+    #[test]
+    fn screen_width_of_combining_mark_is_zero() {
+        // "e" followed by U+0301 COMBINING ACUTE ACCENT.
+        assert_eq!(screen_width("\u{301}"), 0);
+        assert_eq!(screen_width("e\u{301}"), 1);
+    }
+
+    fn wrap(spans: Vec<Span<'static>>, width: usize) -> Vec<String> {
+        force_wrap_spans(&Spans(spans), width)
+            .iter()
+            .map(|line| line.0.iter().map(|span| span.content.as_ref()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn wrap_ascii() {
+        assert_eq!(wrap(vec![Span::raw("abcdefg")], 3), ["abc", "def", "g"]);
+    }
+
+    #[test]
+    fn wrap_accented_letters() {
+        assert_eq!(
+            wrap(vec![Span::raw("Größenwahn é")], 4),
+            ["Größ", "enwa", "hn é"]
+        );
+    }
+
+    #[test]
+    fn wrap_emoji() {
+        assert_eq!(wrap(vec![Span::raw("a🎵🎵b")], 3), ["a🎵", "🎵b"]);
+    }
+
+    #[test]
+    fn wrap_cjk_without_splitting_characters() {
+        // Each character is two columns wide, so only two fit in five columns.
+        assert_eq!(
+            wrap(vec![Span::raw("音楽の世界")], 5),
+            ["音楽", "の世", "界"]
+        );
+        assert_eq!(wrap(vec![Span::raw("a音b楽c")], 4), ["a音b", "楽c"]);
+    }
+
+    #[test]
+    fn wrap_wide_character_wider_than_line() {
+        assert_eq!(wrap(vec![Span::raw("a音b")], 1), ["a", "音", "b"]);
+    }
+
+    #[test]
+    fn wrap_keeps_styles_of_spans() {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let lines = force_wrap_spans(&Spans(vec![Span::raw("ab"), Span::styled("cdé", bold)]), 3);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].0[0].content, "ab");
+        assert_eq!(lines[0].0[1].content, "c");
+        assert_eq!(lines[0].0[1].style, bold);
+        assert_eq!(lines[1].0[0].content, "dé");
+        assert_eq!(lines[1].0[0].style, bold);
+    }
+
+    #[test]
+    fn wrap_keeps_combining_marks_with_their_letters() {
+        assert_eq!(
+            wrap(vec![Span::raw("abe\u{301}cd")], 3),
+            ["abe\u{301}", "cd"]
+        );
+    }
+}